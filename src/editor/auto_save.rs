@@ -0,0 +1,106 @@
+use super::buffer_list::name_of;
+use super::file_buffer::sibling_path;
+use crate::syscall::{MAX_PATH, Stat, stat, unlink};
+
+// Typed keys, and milliseconds without any, after which edits get auto-saved
+pub(in crate::editor) const AUTO_SAVE_KEYS: usize = 300;
+pub(in crate::editor) const AUTO_SAVE_IDLE_MS: usize = 30_000;
+
+// How often auto-revert mode looks at the files while the user is idle
+pub(in crate::editor) const AUTO_REVERT_INTERVAL_MS: usize = 5_000;
+
+// Auto-save file of a file: #name# in the same directory
+pub(in crate::editor) fn auto_save_path(path: &[u8], out: &mut [u8; MAX_PATH]) -> bool {
+    !name_of(path).is_empty() && sibling_path(path, b"#", b"#", out)
+}
+
+// Whether the auto-save file holds edits made after the file was last written
+pub(in crate::editor) fn has_newer_auto_save(path: &[u8]) -> bool {
+    let mut auto_save = [0u8; MAX_PATH];
+    if !auto_save_path(path, &mut auto_save) {
+        return false;
+    }
+
+    let mut saved = Stat::new();
+    if stat(&auto_save, &mut saved).is_err() {
+        return false;
+    }
+
+    // Edits to a file that was never written are newer by definition
+    let mut file = Stat::new();
+    if stat(path, &mut file).is_err() {
+        return true;
+    }
+    (saved.mtime, saved.mtime_nsec) > (file.mtime, file.mtime_nsec)
+}
+
+pub(in crate::editor) fn remove_auto_save(path: &[u8]) {
+    let mut auto_save = [0u8; MAX_PATH];
+    if auto_save_path(path, &mut auto_save) {
+        let _ = unlink(&auto_save);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::BackupMode;
+    use crate::editor::file_buffer::tests::create_test_file_buffer;
+
+    #[test]
+    fn test_auto_save_until_real_save() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = "test_auto_save_123456";
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir(dir).expect("Failed to create test directory");
+        let file = format!("{dir}/a.txt");
+        let auto_save = format!("{dir}/#a.txt#");
+        let path = format!("{file}\0");
+        std::fs::write(&file, "old").expect("Failed to create test file");
+        let past = std::time::SystemTime::now() - std::time::Duration::from_secs(100);
+        std::fs::File::options()
+            .write(true)
+            .open(&file)
+            .and_then(|f| f.set_modified(past))
+            .expect("Failed to age test file");
+
+        // Nothing is written until the text changes
+        let mut buffer = create_test_file_buffer(b"");
+        assert_eq!(buffer.auto_save(path.as_bytes()), Ok(0));
+        assert!(!has_newer_auto_save(path.as_bytes()));
+
+        buffer.insert_char(0, 0, b'x').unwrap();
+        assert_eq!(buffer.auto_save(path.as_bytes()), Ok(1));
+        assert_eq!(std::fs::read_to_string(&auto_save).unwrap(), "x");
+        let mode = std::fs::metadata(&auto_save).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600, "Only the owner may read it");
+        assert!(has_newer_auto_save(path.as_bytes()));
+        assert_eq!(buffer.auto_save(path.as_bytes()), Ok(0), "Already saved");
+
+        // Unnamed buffers have no auto-save file
+        buffer.insert_char(0, 0, b'y').unwrap();
+        assert_eq!(buffer.auto_save(b"\0"), Ok(0));
+
+        // Writing a copy elsewhere leaves the buffer's own recovery alone
+        let copy = format!("{dir}/copy.txt\0");
+        assert_eq!(buffer.write_copy(copy.as_bytes()), Ok(2));
+        assert_eq!(
+            std::fs::read_to_string(format!("{dir}/copy.txt")).unwrap(),
+            "yx"
+        );
+        assert!(std::path::Path::new(&auto_save).exists());
+        assert!(buffer.has_unsaved_changes());
+
+        buffer
+            .save_to_file(path.as_bytes(), BackupMode::Off)
+            .unwrap();
+        assert!(
+            !std::path::Path::new(&auto_save).exists(),
+            "Real save removes it"
+        );
+        assert!(!has_newer_auto_save(path.as_bytes()));
+
+        std::fs::remove_dir_all(dir).expect("Failed to clean up test directory");
+    }
+}
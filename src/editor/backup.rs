@@ -0,0 +1,273 @@
+use super::buffer_list::name_of;
+use super::file_buffer::copy_attributes;
+use crate::syscall::{
+    EINVAL, EIO, ENAMETOOLONG, ENOENT, ENOSYS, EOPNOTSUPP, EXDEV, MAX_PATH, O_CREAT, O_NOFOLLOW,
+    O_RDONLY, O_TRUNC, O_WRONLY, Stat, SysResult, close, copy_file_range, fsync, lstat, open, read,
+    write,
+};
+use crate::terminal::write_usize_to_buf;
+
+// Bytes handed to the kernel per copy when writing a backup
+const COPY_CHUNK: usize = 1 << 20;
+// Buffer for copying where the kernel cannot copy by itself
+const COPY_BUF: usize = 4096;
+
+// How the first save of a session keeps the previous version of a file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(in crate::editor) enum BackupMode {
+    Off,      // No backups at all
+    Single,   // One backup named name~
+    Numbered, // A new name.~N~ every session
+}
+
+impl BackupMode {
+    // Mode the backup-mode command switches to
+    pub(in crate::editor) fn next(self) -> Self {
+        match self {
+            BackupMode::Off => BackupMode::Single,
+            BackupMode::Single => BackupMode::Numbered,
+            BackupMode::Numbered => BackupMode::Off,
+        }
+    }
+
+    pub(in crate::editor) fn describe(self) -> &'static str {
+        match self {
+            BackupMode::Off => "Backups disabled",
+            BackupMode::Single => "Backups saved as name~",
+            BackupMode::Numbered => "Backups saved as name.~N~",
+        }
+    }
+}
+
+// Whether the file still needs its backup. The first save of a session
+// copies the file on disk into a backup just before replacing it, and later
+// saves leave that backup alone.
+pub(in crate::editor) struct Original {
+    pending: bool,
+    failed: bool, // The backup could not be written; the save went on
+}
+
+impl Original {
+    pub(in crate::editor) const fn none() -> Self {
+        Self {
+            pending: false,
+            failed: false,
+        }
+    }
+
+    // A file loaded from disk, backed up on its first save
+    pub(in crate::editor) const fn loaded() -> Self {
+        Self {
+            pending: true,
+            failed: false,
+        }
+    }
+
+    // Whether the last save lost its backup, which is reported only once
+    pub(in crate::editor) fn take_failure(&mut self) -> bool {
+        core::mem::take(&mut self.failed)
+    }
+
+    // Copy the file at `path`, which must not be a symlink, next to it. A
+    // backup is tried only once: the save replaces the file either way, so a
+    // failure is kept for the caller to warn about.
+    pub(in crate::editor) fn write_backup(
+        &mut self,
+        path: &[u8],
+        existing: Option<&Stat>,
+        mode: BackupMode,
+    ) {
+        if mode == BackupMode::Off || !self.pending {
+            return;
+        }
+        self.pending = false;
+
+        // A file that went away has nothing left to back up
+        if existing.is_some() {
+            self.failed = copy_to_backup(path, existing, mode).is_err();
+        }
+    }
+}
+
+// The copy ends at whatever end of file it finds, so a file another program
+// shrank just gives a shorter backup
+fn copy_to_backup(path: &[u8], existing: Option<&Stat>, mode: BackupMode) -> SysResult {
+    let mut backup = [0u8; MAX_PATH];
+    backup_path(path, mode, &mut backup)?;
+
+    let source = open(path, O_RDONLY | O_NOFOLLOW)?;
+    let result = open(&backup, O_WRONLY | O_CREAT | O_TRUNC | O_NOFOLLOW).and_then(|fd| {
+        let written = copy_attributes(fd, existing)
+            .and_then(|()| copy_all(source, fd))
+            .and_then(|bytes_written| fsync(fd).map(|_| bytes_written));
+        let closed = close(fd);
+        let bytes_written = written?;
+        closed?;
+        Ok(bytes_written)
+    });
+    let _ = close(source);
+    result
+}
+
+// Copy the rest of one file into another inside the kernel, or through a
+// buffer where the kernel or filesystem cannot
+fn copy_all(from: usize, to: usize) -> SysResult {
+    let mut total = 0;
+    loop {
+        match copy_file_range(from, to, COPY_CHUNK) {
+            Ok(0) => return Ok(total),
+            Ok(copied) => total += copied,
+            Err(ENOSYS | EXDEV | EINVAL | EOPNOTSUPP) => {
+                return Ok(total + copy_by_reading(from, to)?);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn copy_by_reading(from: usize, to: usize) -> SysResult {
+    let mut buf = [0u8; COPY_BUF];
+    let mut total = 0;
+    loop {
+        let filled = read(from, &mut buf, COPY_BUF)?;
+        if filled == 0 {
+            return Ok(total);
+        }
+        let mut offset = 0;
+        while offset < filled {
+            let written = write(to, &buf[offset..filled])?;
+            if written == 0 {
+                return Err(EIO);
+            }
+            offset += written;
+        }
+        total += filled;
+    }
+}
+
+// Name of the backup for path: name~, or the first free name.~N~
+fn backup_path(path: &[u8], mode: BackupMode, backup: &mut [u8; MAX_PATH]) -> Result<(), usize> {
+    let path = name_of(path);
+    if mode == BackupMode::Single {
+        return join(backup, &[path, b"~"]);
+    }
+
+    let mut digits = [0u8; 20];
+    for number in 1.. {
+        let len = write_usize_to_buf(&mut digits, number);
+        join(backup, &[path, b".~", &digits[..len], b"~"])?;
+
+        let mut stat = Stat::new();
+        match lstat(backup, &mut stat) {
+            Err(ENOENT) => return Ok(()),
+            Err(e) => return Err(e),
+            Ok(_) => {}
+        }
+    }
+    Err(ENAMETOOLONG)
+}
+
+// Concatenate parts into a NUL-terminated path
+pub(in crate::editor) fn join(out: &mut [u8; MAX_PATH], parts: &[&[u8]]) -> Result<(), usize> {
+    let len: usize = parts.iter().map(|part| part.len()).sum();
+    if len >= MAX_PATH {
+        return Err(ENAMETOOLONG);
+    }
+
+    let mut pos = 0;
+    for part in parts {
+        out[pos..pos + part.len()].copy_from_slice(part);
+        pos += part.len();
+    }
+    out[pos] = 0;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backup_path() {
+        let dir = "test_backup_path_123456";
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir(dir).expect("Failed to create test directory");
+
+        let mut backup = [0u8; MAX_PATH];
+        let path = format!("{dir}/a.txt\0");
+        assert!(backup_path(path.as_bytes(), BackupMode::Single, &mut backup).is_ok());
+        assert_eq!(name_of(&backup), format!("{dir}/a.txt~").as_bytes());
+
+        // Numbered backups take the first number not in use
+        assert!(backup_path(path.as_bytes(), BackupMode::Numbered, &mut backup).is_ok());
+        assert_eq!(name_of(&backup), format!("{dir}/a.txt.~1~").as_bytes());
+        std::fs::write(format!("{dir}/a.txt.~1~"), "").unwrap();
+        assert!(backup_path(path.as_bytes(), BackupMode::Numbered, &mut backup).is_ok());
+        assert_eq!(name_of(&backup), format!("{dir}/a.txt.~2~").as_bytes());
+
+        let long = [b'a'; MAX_PATH - 1];
+        assert_eq!(
+            backup_path(&long, BackupMode::Single, &mut backup),
+            Err(ENAMETOOLONG)
+        );
+
+        std::fs::remove_dir_all(dir).expect("Failed to clean up test directory");
+    }
+
+    #[test]
+    fn test_first_save_writes_backup() {
+        use crate::editor::file_buffer::tests::create_test_file_buffer;
+
+        let dir = "test_first_save_backup_123456";
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir(dir).expect("Failed to create test directory");
+        let file = format!("{dir}/a.txt");
+        std::fs::write(&file, "old").expect("Failed to create test file");
+
+        let path = format!("{file}\0");
+        let mut buffer = create_test_file_buffer(b"new");
+        buffer.original = Original::loaded();
+
+        buffer
+            .save_to_file(path.as_bytes(), BackupMode::Numbered)
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "new");
+        assert_eq!(
+            std::fs::read_to_string(format!("{file}.~1~")).unwrap(),
+            "old"
+        );
+        assert!(!buffer.original.pending, "Only one backup per session");
+
+        // Later saves of the session keep the backup as it is
+        buffer
+            .save_to_file(path.as_bytes(), BackupMode::Numbered)
+            .unwrap();
+        assert!(!std::path::Path::new(&format!("{file}.~2~")).exists());
+
+        // A file shrunk by another program since loading is copied as it is
+        std::fs::write(&file, "").unwrap();
+        buffer.original = Original::loaded();
+        buffer
+            .save_to_file(path.as_bytes(), BackupMode::Single)
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(format!("{file}~")).unwrap(), "");
+
+        // A link planted at the backup name is not written through; the
+        // save still happens and the lost backup is reported once
+        let victim = format!("{dir}/victim.txt");
+        std::fs::write(&victim, "keep").unwrap();
+        std::fs::remove_file(format!("{file}~")).unwrap();
+        std::os::unix::fs::symlink("victim.txt", format!("{file}~")).unwrap();
+        buffer.original = Original::loaded();
+        std::fs::write(&file, "older").unwrap();
+        buffer
+            .save_to_file(path.as_bytes(), BackupMode::Single)
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&victim).unwrap(), "keep");
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "new");
+        assert!(buffer.original.take_failure());
+        assert!(!buffer.original.take_failure());
+
+        std::fs::remove_dir_all(dir).expect("Failed to clean up test directory");
+    }
+}
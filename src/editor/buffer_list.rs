@@ -0,0 +1,260 @@
+use super::{FileBuffer, SyntaxHighlighter};
+use crate::syscall::{MAP_ANONYMOUS, MAP_PRIVATE, MAX_PATH, PROT_READ, PROT_WRITE, mmap, munmap};
+
+const PAGE_SIZE: usize = 4096;
+
+// Everything that belongs to one open file, kept aside while another one is shown
+pub(in crate::editor) struct BufferState {
+    pub(in crate::editor) buffer: FileBuffer,
+    pub(in crate::editor) filename: [u8; MAX_PATH],
+    pub(in crate::editor) highlighter: SyntaxHighlighter,
+    pub(in crate::editor) file_row: usize,
+    pub(in crate::editor) file_col: usize,
+    pub(in crate::editor) preferred_col: usize,
+    pub(in crate::editor) scroll_row: usize,
+    pub(in crate::editor) scroll_col: usize,
+    pub(in crate::editor) mark_active: bool,
+    pub(in crate::editor) mark_row: usize,
+    pub(in crate::editor) mark_col: usize,
+}
+
+impl BufferState {
+    pub(in crate::editor) fn new(buffer: FileBuffer, filename: &[u8]) -> Self {
+        let mut own_filename = [0u8; MAX_PATH];
+        let len = name_of(filename).len().min(MAX_PATH - 1);
+        own_filename[..len].copy_from_slice(&filename[..len]);
+
+        let mut highlighter = SyntaxHighlighter::new();
+        highlighter.detect_file_type(&own_filename);
+
+        Self {
+            buffer,
+            filename: own_filename,
+            highlighter,
+            file_row: 0,
+            file_col: 0,
+            preferred_col: 0,
+            scroll_row: 0,
+            scroll_col: 0,
+            mark_active: false,
+            mark_row: 0,
+            mark_col: 0,
+        }
+    }
+
+    pub(in crate::editor) fn name(&self) -> &[u8] {
+        name_of(&self.filename)
+    }
+}
+
+// Buffer name stored in a NUL-terminated file name
+pub(in crate::editor) fn name_of(filename: &[u8]) -> &[u8] {
+    let len = filename
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(filename.len());
+    &filename[..len]
+}
+
+// Extend the input as far as all candidates starting with it agree, returning the new length
+pub(in crate::editor) fn complete_prefix<'a>(
+    input: &mut [u8; MAX_PATH],
+    len: usize,
+    candidates: impl Iterator<Item = &'a [u8]>,
+) -> usize {
+    let mut common: Option<&[u8]> = None;
+    for candidate in candidates.filter(|c| c.starts_with(&input[..len])) {
+        common = Some(match common {
+            None => candidate,
+            Some(prefix) => {
+                let same = prefix
+                    .iter()
+                    .zip(candidate)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                &prefix[..same]
+            }
+        });
+    }
+
+    let Some(common) = common else {
+        return len;
+    };
+    let new_len = common.len().min(MAX_PATH - 1);
+    input[len..new_len].copy_from_slice(&common[len..new_len]);
+    new_len
+}
+
+// BufferList holds the buffers that are not shown, most recently used first
+pub(in crate::editor) struct BufferList {
+    entries: *mut BufferState, // Raw mmap-backed entry storage
+    capacity: usize,           // Number of entries that fit into the mapping
+    count: usize,              // Number of buffers
+}
+
+impl BufferList {
+    pub(in crate::editor) const fn new() -> Self {
+        Self {
+            entries: core::ptr::null_mut(),
+            capacity: 0,
+            count: 0,
+        }
+    }
+
+    pub(in crate::editor) fn len(&self) -> usize {
+        self.count
+    }
+
+    pub(in crate::editor) fn get(&self, idx: usize) -> Option<&BufferState> {
+        if idx < self.count {
+            Some(unsafe { &*self.entries.add(idx) })
+        } else {
+            None
+        }
+    }
+
+    pub(in crate::editor) fn get_mut(&mut self, idx: usize) -> Option<&mut BufferState> {
+        if idx < self.count {
+            Some(unsafe { &mut *self.entries.add(idx) })
+        } else {
+            None
+        }
+    }
+
+    // Index of the buffer with the given name
+    pub(in crate::editor) fn find(&self, name: &[u8]) -> Option<usize> {
+        (0..self.count).find(|&idx| self.get(idx).is_some_and(|b| b.name() == name))
+    }
+
+    // Add a buffer as the most recently used one; room must have been reserved
+    pub(in crate::editor) fn push_front(&mut self, buffer: BufferState) {
+        self.insert(0, buffer);
+    }
+
+    // Put a buffer at the given position; room must have been reserved
+    pub(in crate::editor) fn insert(&mut self, idx: usize, buffer: BufferState) {
+        debug_assert!(self.count < self.capacity, "No room reserved for a buffer");
+        let idx = idx.min(self.count);
+        unsafe {
+            core::ptr::copy(
+                self.entries.add(idx),
+                self.entries.add(idx + 1),
+                self.count - idx,
+            );
+            core::ptr::write(self.entries.add(idx), buffer);
+        }
+        self.count += 1;
+    }
+
+    pub(in crate::editor) fn remove(&mut self, idx: usize) -> Option<BufferState> {
+        if idx >= self.count {
+            return None;
+        }
+
+        let buffer = unsafe { core::ptr::read(self.entries.add(idx)) };
+        unsafe {
+            core::ptr::copy(
+                self.entries.add(idx + 1),
+                self.entries.add(idx),
+                self.count - idx - 1,
+            );
+        }
+        self.count -= 1;
+        Some(buffer)
+    }
+
+    // Make sure one more buffer fits into the mapping
+    pub(in crate::editor) fn reserve(&mut self) -> bool {
+        let entries = self.count + 1;
+        if entries <= self.capacity {
+            return true;
+        }
+
+        let entry_size = core::mem::size_of::<BufferState>();
+        let mut new_capacity = if self.capacity == 0 {
+            (PAGE_SIZE / entry_size).max(1)
+        } else {
+            self.capacity * 2
+        };
+        while new_capacity < entries {
+            new_capacity *= 2;
+        }
+
+        let prot = PROT_READ | PROT_WRITE;
+        let flags = MAP_PRIVATE | MAP_ANONYMOUS;
+        let Ok(addr) = mmap(0, new_capacity * entry_size, prot, flags, usize::MAX, 0) else {
+            return false;
+        };
+        let new_entries = addr as *mut BufferState;
+
+        if !self.entries.is_null() {
+            unsafe { core::ptr::copy_nonoverlapping(self.entries, new_entries, self.count) };
+            let _ = munmap(self.entries as usize, self.capacity * entry_size);
+        }
+
+        self.entries = new_entries;
+        self.capacity = new_capacity;
+        true
+    }
+}
+
+impl Drop for BufferList {
+    fn drop(&mut self) {
+        while let Some(buffer) = self.remove(0) {
+            drop(buffer);
+        }
+
+        if !self.entries.is_null() && self.capacity > 0 {
+            let _ = munmap(
+                self.entries as usize,
+                self.capacity * core::mem::size_of::<BufferState>(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::file_buffer::tests::create_test_file_buffer;
+
+    #[test]
+    fn test_buffer_list_order() {
+        let mut list = BufferList::new();
+        assert!(list.get(0).is_none(), "New list is empty");
+
+        for name in [&b"a.c\0"[..], b"b.rs\0", b"c.txt\0"] {
+            assert!(list.reserve());
+            list.push_front(BufferState::new(create_test_file_buffer(b""), name));
+        }
+
+        // Most recently added buffers come first
+        assert_eq!(list.len(), 3);
+        assert_eq!(list.get(0).map(BufferState::name), Some(&b"c.txt"[..]));
+        assert_eq!(list.find(b"a.c"), Some(2));
+        assert_eq!(list.find(b"a"), None, "Names must match exactly");
+
+        let removed = list.remove(1).unwrap();
+        assert_eq!(removed.name(), b"b.rs");
+        assert_eq!(list.len(), 2);
+        assert_eq!(list.find(b"a.c"), Some(1));
+        assert!(list.remove(2).is_none());
+    }
+
+    #[test]
+    fn test_buffer_list_grows() {
+        let mut list = BufferList::new();
+        let entries = 3 * PAGE_SIZE / core::mem::size_of::<BufferState>() + 1;
+        for i in 0..entries {
+            let name = [b'a' + u8::try_from(i % 26).unwrap(), 0];
+            assert!(list.reserve());
+            list.push_front(BufferState::new(create_test_file_buffer(b""), &name));
+        }
+
+        assert_eq!(list.len(), entries);
+        assert_eq!(
+            list.get(entries - 1).map(BufferState::name),
+            Some(&b"a"[..])
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use super::backup::join;
+use super::directory::for_each_entry;
+use crate::syscall::{MAX_PATH, O_RDONLY, close, open, read};
+
+// Room for the candidates shown below a prompt
+const CANDIDATE_BYTES: usize = 4096;
+const MAX_CANDIDATES: usize = 256;
+
+// Completion candidates in sorted order, packed one after another. Matches
+// that do not fit are only counted.
+pub(in crate::editor) struct Candidates {
+    names: [u8; CANDIDATE_BYTES],
+    ends: [usize; MAX_CANDIDATES],
+    count: usize,
+    total: usize,
+}
+
+impl Candidates {
+    pub(in crate::editor) const fn new() -> Self {
+        Self {
+            names: [0; CANDIDATE_BYTES],
+            ends: [0; MAX_CANDIDATES],
+            count: 0,
+            total: 0,
+        }
+    }
+
+    pub(in crate::editor) fn clear(&mut self) {
+        self.count = 0;
+        self.total = 0;
+    }
+
+    // Number of matches, including those that did not fit
+    pub(in crate::editor) fn total(&self) -> usize {
+        self.total
+    }
+
+    pub(in crate::editor) fn iter(&self) -> impl Iterator<Item = &[u8]> {
+        (0..self.count).map(|idx| self.get(idx))
+    }
+
+    fn get(&self, idx: usize) -> &[u8] {
+        let start = if idx == 0 { 0 } else { self.ends[idx - 1] };
+        &self.names[start..self.ends[idx]]
+    }
+
+    // Insert a name made of parts at its sorted place
+    fn add(&mut self, parts: &[&[u8]]) {
+        self.total += 1;
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+        let used = if self.count == 0 {
+            0
+        } else {
+            self.ends[self.count - 1]
+        };
+        if self.count == MAX_CANDIDATES || used + len > CANDIDATE_BYTES {
+            return;
+        }
+
+        let mut name = [0u8; MAX_PATH];
+        let mut name_len = 0;
+        for part in parts {
+            let end = (name_len + part.len()).min(MAX_PATH);
+            name[name_len..end].copy_from_slice(&part[..end - name_len]);
+            name_len = end;
+        }
+        let name = &name[..name_len];
+
+        let idx = (0..self.count)
+            .position(|idx| self.get(idx) > name)
+            .unwrap_or(self.count);
+        let start = if idx == 0 { 0 } else { self.ends[idx - 1] };
+        self.names.copy_within(start..used, start + name.len());
+        self.names[start..start + name.len()].copy_from_slice(name);
+        self.ends.copy_within(idx..self.count, idx + 1);
+        self.ends[idx] = start;
+        for end in &mut self.ends[idx..=self.count] {
+            *end += name.len();
+        }
+        self.count += 1;
+    }
+}
+
+// Extend the last component of a file name as far as the entries of its
+// directory agree, collecting them as candidates. Directories end with a
+// slash, and dot files are offered only for a name starting with a dot.
+pub(in crate::editor) fn complete_file_name(
+    input: &mut [u8; MAX_PATH],
+    len: usize,
+    home: &[u8],
+    candidates: &mut Candidates,
+) -> usize {
+    let dir_len = input[..len]
+        .iter()
+        .rposition(|&b| b == b'/')
+        .map_or(0, |slash| slash + 1);
+    let mut dir = [0u8; MAX_PATH];
+    let expanded = if dir_len == 0 {
+        join(&mut dir, &[b"."])
+    } else {
+        expand_home(&input[..dir_len], home, &mut dir)
+    };
+    if expanded.is_err() {
+        return len;
+    }
+
+    let prefix = &input[dir_len..len];
+    let mut common = [0u8; MAX_PATH];
+    let mut common_len = None;
+    let listed = for_each_entry(&dir, |name, is_dir| {
+        if !name.starts_with(prefix) || (name.starts_with(b".") && !prefix.starts_with(b".")) {
+            return;
+        }
+        let suffix: &[u8] = if is_dir { b"/" } else { b"" };
+        candidates.add(&[name, suffix]);
+
+        let full = name.iter().chain(suffix);
+        common_len = Some(match common_len {
+            None => {
+                for (slot, &b) in common.iter_mut().zip(full) {
+                    *slot = b;
+                }
+                (name.len() + suffix.len()).min(MAX_PATH)
+            }
+            Some(common_len) => common[..common_len]
+                .iter()
+                .zip(full)
+                .take_while(|(a, b)| a == b)
+                .count(),
+        });
+    });
+
+    let Some(common_len) = common_len.filter(|_| listed.is_ok()) else {
+        return len;
+    };
+    let new_len = (dir_len + common_len).min(MAX_PATH - 1);
+    input[len..new_len].copy_from_slice(&common[len - dir_len..new_len - dir_len]);
+    new_len
+}
+
+// Write the path with a leading ~ replaced by the home directory, NUL-terminated
+pub(in crate::editor) fn expand_home(
+    path: &[u8],
+    home: &[u8],
+    out: &mut [u8; MAX_PATH],
+) -> Result<(), usize> {
+    let in_home = path == b"~" || path.starts_with(b"~/");
+    if in_home && !home.is_empty() {
+        join(out, &[home, &path[1..]])
+    } else {
+        join(out, &[path])
+    }
+}
+
+// Value of HOME in the environment the editor was started with
+pub(in crate::editor) fn home_dir(out: &mut [u8; MAX_PATH]) -> usize {
+    const KEY: &[u8] = b"HOME=";
+    const CHUNK: usize = 1024;
+
+    let Ok(fd) = open(b"/proc/self/environ\0", O_RDONLY) else {
+        return 0;
+    };
+
+    // Entries are NUL-terminated; `matched` counts the bytes of KEY seen at
+    // the start of the current one
+    let mut buf = [0u8; CHUNK];
+    let mut matched = Some(0);
+    let mut len = 0;
+    'read: while let Ok(filled @ 1..) = read(fd, &mut buf, CHUNK) {
+        for &b in &buf[..filled] {
+            match matched {
+                Some(count) if count == KEY.len() => {
+                    if b == 0 {
+                        break 'read;
+                    }
+                    if len < MAX_PATH - 1 {
+                        out[len] = b;
+                        len += 1;
+                    }
+                }
+                _ if b == 0 => matched = Some(0),
+                Some(count) if KEY[count] == b => matched = Some(count + 1),
+                _ => matched = None,
+            }
+        }
+    }
+    let _ = close(fd);
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::buffer_list::name_of;
+
+    fn complete(input: &str, candidates: &mut Candidates) -> String {
+        let mut buf = [0u8; MAX_PATH];
+        buf[..input.len()].copy_from_slice(input.as_bytes());
+        candidates.clear();
+        let len = complete_file_name(&mut buf, input.len(), b"", candidates);
+        String::from_utf8(buf[..len].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_candidates_sorted() {
+        let mut candidates = Candidates::new();
+        for name in [&b"main.rs"[..], b"lib.rs", b"mod.rs", b"build.rs"] {
+            candidates.add(&[name]);
+        }
+        let names: Vec<&[u8]> = candidates.iter().collect();
+        assert_eq!(names, [&b"build.rs"[..], b"lib.rs", b"main.rs", b"mod.rs"]);
+
+        // Matches that do not fit are still counted
+        for _ in 0..MAX_CANDIDATES {
+            candidates.add(&[b"x"]);
+        }
+        assert_eq!(candidates.iter().count(), MAX_CANDIDATES);
+        assert_eq!(candidates.total(), MAX_CANDIDATES + 4);
+    }
+
+    #[test]
+    fn test_complete_file_name() {
+        let dir = "test_complete_file_name_123456";
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir(dir).expect("Failed to create test directory");
+        std::fs::write(format!("{dir}/readme.md"), "").unwrap();
+        std::fs::write(format!("{dir}/main.rs"), "").unwrap();
+        std::fs::write(format!("{dir}/mod.rs"), "").unwrap();
+        std::fs::write(format!("{dir}/.hidden"), "").unwrap();
+        std::fs::create_dir(format!("{dir}/src")).unwrap();
+
+        let mut candidates = Candidates::new();
+
+        // A single match completes in place, directories with a slash
+        assert_eq!(
+            complete(&format!("{dir}/r"), &mut candidates),
+            format!("{dir}/readme.md")
+        );
+        assert_eq!(
+            complete(&format!("{dir}/s"), &mut candidates),
+            format!("{dir}/src/")
+        );
+
+        // Several matches extend to the common prefix and become candidates
+        assert_eq!(
+            complete(&format!("{dir}/m"), &mut candidates),
+            format!("{dir}/m")
+        );
+        let names: Vec<&[u8]> = candidates.iter().collect();
+        assert_eq!(names, [&b"main.rs"[..], b"mod.rs"]);
+
+        // Dot files only when asked for
+        complete(&format!("{dir}/"), &mut candidates);
+        assert_eq!(candidates.total(), 4);
+        assert_eq!(
+            complete(&format!("{dir}/."), &mut candidates),
+            format!("{dir}/.hidden")
+        );
+
+        // No match leaves the input alone
+        assert_eq!(
+            complete(&format!("{dir}/x"), &mut candidates),
+            format!("{dir}/x")
+        );
+        assert_eq!(candidates.total(), 0);
+
+        std::fs::remove_dir_all(dir).expect("Failed to clean up test directory");
+    }
+
+    #[test]
+    fn test_expand_home() {
+        let mut out = [0u8; MAX_PATH];
+        assert!(expand_home(b"~/notes.txt", b"/home/user", &mut out).is_ok());
+        assert_eq!(name_of(&out), b"/home/user/notes.txt");
+        assert!(expand_home(b"~", b"/home/user", &mut out).is_ok());
+        assert_eq!(name_of(&out), b"/home/user");
+
+        // Only a leading ~ followed by a slash is the home directory
+        assert!(expand_home(b"~user/a", b"/home/user", &mut out).is_ok());
+        assert_eq!(name_of(&out), b"~user/a");
+        assert!(expand_home(b"~/a", b"", &mut out).is_ok());
+        assert_eq!(name_of(&out), b"~/a");
+    }
+}
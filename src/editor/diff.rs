@@ -0,0 +1,108 @@
+use super::{FileBuffer, FileBufferError};
+use crate::terminal::write_usize_to_buf;
+
+// Lines of a text, each with its newline
+fn lines(text: &[u8]) -> core::slice::SplitInclusive<'_, u8, impl FnMut(&u8) -> bool> {
+    text.split_inclusive(|&b| b == b'\n')
+}
+
+// Write a unified diff from `old` to `new` into `out`. Lines shared at the
+// start and the end are left out, so all changes end up in a single hunk.
+pub(in crate::editor) fn write_diff(
+    old: &[u8],
+    new: &[u8],
+    name: &[u8],
+    out: &mut FileBuffer,
+) -> Result<(), FileBufferError> {
+    if old == new {
+        return out.append_text(b"No differences\n");
+    }
+
+    let old_count = lines(old).count();
+    let new_count = lines(new).count();
+    let prefix = lines(old)
+        .zip(lines(new))
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = lines(old)
+        .rev()
+        .zip(lines(new).rev())
+        .take_while(|(a, b)| a == b)
+        .count()
+        .min(old_count - prefix)
+        .min(new_count - prefix);
+    let removed = old_count - prefix - suffix;
+    let added = new_count - prefix - suffix;
+
+    for part in [
+        &b"--- "[..],
+        name,
+        b" (on disk)\n+++ ",
+        name,
+        b" (buffer)\n",
+    ] {
+        out.append_text(part)?;
+    }
+    out.append_text(b"@@ -")?;
+    append_range(out, prefix, removed)?;
+    out.append_text(b" +")?;
+    append_range(out, prefix, added)?;
+    out.append_text(b" @@\n")?;
+
+    for (marker, text, count) in [(b"-", old, removed), (b"+", new, added)] {
+        for line in lines(text).skip(prefix).take(count) {
+            out.append_text(marker)?;
+            out.append_text(line)?;
+            if !line.ends_with(b"\n") {
+                out.append_text(b"\n\\ No newline at end of file\n")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Hunk range "start,count"; an empty range names the line before it
+fn append_range(out: &mut FileBuffer, skipped: usize, count: usize) -> Result<(), FileBufferError> {
+    let mut digits = [0u8; 20];
+    let start = if count == 0 { skipped } else { skipped + 1 };
+    let len = write_usize_to_buf(&mut digits, start);
+    out.append_text(&digits[..len])?;
+    out.append_text(b",")?;
+    let len = write_usize_to_buf(&mut digits, count);
+    out.append_text(&digits[..len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::file_buffer::tests::create_test_file_buffer;
+
+    fn diff(old: &[u8], new: &[u8]) -> Vec<u8> {
+        let mut out = create_test_file_buffer(b"");
+        write_diff(old, new, b"a.txt", &mut out).unwrap();
+        out.text_range(0, out.size).to_vec()
+    }
+
+    #[test]
+    fn test_write_diff() {
+        assert_eq!(diff(b"same\n", b"same\n"), b"No differences\n");
+
+        // Shared lines around the change are not repeated
+        assert_eq!(
+            diff(b"a\nb\nc\nd\n", b"a\nB\nC\nd\n"),
+            b"--- a.txt (on disk)\n+++ a.txt (buffer)\n@@ -2,2 +2,2 @@\n-b\n-c\n+B\n+C\n"
+        );
+
+        // Distant changes share one hunk with everything between them
+        assert_eq!(
+            diff(b"a\nb\nc\nd\n", b"A\nb\nc\nD\n"),
+            b"--- a.txt (on disk)\n+++ a.txt (buffer)\n@@ -1,4 +1,4 @@\n-a\n-b\n-c\n-d\n+A\n+b\n+c\n+D\n"
+        );
+
+        // Pure insertion and a missing final newline
+        assert_eq!(
+            diff(b"a\n", b"a\nb"),
+            b"--- a.txt (on disk)\n+++ a.txt (buffer)\n@@ -1,0 +2,1 @@\n+b\n\\ No newline at end of file\n"
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use super::backup::join;
+use super::buffer_list::name_of;
+use crate::syscall::{
+    DT_DIR, DT_LNK, DT_UNKNOWN, MAX_PATH, O_DIRECTORY, O_RDONLY, S_IFDIR, S_IFMT, Stat, close,
+    getdents64, open, stat,
+};
+
+// Fields of a linux_dirent64 record
+const RECLEN_OFFSET: usize = 16;
+const TYPE_OFFSET: usize = 18;
+const NAME_OFFSET: usize = 19;
+
+// Call `f` with the name of every entry of the NUL-terminated directory path
+// except . and .., and whether the entry is a directory. Symlinks count as
+// what they point to.
+pub(in crate::editor) fn for_each_entry(
+    dir: &[u8],
+    mut f: impl FnMut(&[u8], bool),
+) -> Result<(), usize> {
+    let fd = open(dir, O_RDONLY | O_DIRECTORY)?;
+    let mut buf = [0u8; 4096];
+    let result = loop {
+        let filled = match getdents64(fd, &mut buf) {
+            Ok(0) => break Ok(()),
+            Ok(filled) => filled,
+            Err(e) => break Err(e),
+        };
+
+        let mut offset = 0;
+        while offset < filled {
+            let record = &buf[offset..filled];
+            let reclen = usize::from(u16::from_ne_bytes([
+                record[RECLEN_OFFSET],
+                record[RECLEN_OFFSET + 1],
+            ]));
+            let name = name_of(&record[NAME_OFFSET..reclen]);
+            if name != b"." && name != b".." {
+                f(name, is_directory(dir, name, record[TYPE_OFFSET]));
+            }
+            offset += reclen;
+        }
+    };
+    let _ = close(fd);
+    result
+}
+
+// Not every file system fills in the type, and symlinks need a look at the target
+fn is_directory(dir: &[u8], name: &[u8], kind: u8) -> bool {
+    if kind != DT_LNK && kind != DT_UNKNOWN {
+        return kind == DT_DIR;
+    }
+
+    let mut path = [0u8; MAX_PATH];
+    let mut target = Stat::new();
+    join(&mut path, &[name_of(dir), b"/", name]).is_ok()
+        && stat(&path, &mut target).is_ok()
+        && target.mode & S_IFMT == S_IFDIR
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_each_entry() {
+        let dir = "test_for_each_entry_123456";
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir(dir).expect("Failed to create test directory");
+        std::fs::write(format!("{dir}/file.txt"), "").unwrap();
+        std::fs::create_dir(format!("{dir}/sub")).unwrap();
+        std::os::unix::fs::symlink("sub", format!("{dir}/link")).unwrap();
+
+        let mut entries = Vec::new();
+        let path = format!("{dir}\0");
+        for_each_entry(path.as_bytes(), |name, is_dir| {
+            entries.push((String::from_utf8(name.to_vec()).unwrap(), is_dir));
+        })
+        .unwrap();
+        entries.sort();
+        assert_eq!(
+            entries,
+            [
+                ("file.txt".to_string(), false),
+                ("link".to_string(), true),
+                ("sub".to_string(), true),
+            ]
+        );
+
+        assert!(for_each_entry(b"test_no_such_dir_123456\0", |_, _| {}).is_err());
+
+        std::fs::remove_dir_all(dir).expect("Failed to clean up test directory");
+    }
+}
@@ -0,0 +1,226 @@
+use super::backup::join;
+use super::buffer_list::name_of;
+use super::directory::for_each_entry;
+use super::{EditorError, FileBuffer};
+use crate::syscall::{
+    MAX_PATH, S_IFBLK, S_IFCHR, S_IFDIR, S_IFIFO, S_IFLNK, S_IFMT, S_IFSOCK, Stat, lstat, stat,
+};
+use crate::terminal::write_usize_to_buf;
+
+// Listing lines are "  drwxr-xr-x       4096 name": the name starts here
+pub(in crate::editor) const NAME_COL: usize = 24;
+const SIZE_WIDTH: usize = 10;
+
+// Line of the first entry, after the header and ..
+pub(in crate::editor) const FIRST_ENTRY: usize = 2;
+
+// Directory listings are read-only buffers named after the directory with a
+// trailing slash
+pub(in crate::editor) fn is_listing(filename: &[u8]) -> bool {
+    name_of(filename).ends_with(b"/")
+}
+
+pub(in crate::editor) fn is_directory(path: &[u8]) -> bool {
+    let mut status = Stat::new();
+    stat(path, &mut status).is_ok() && status.mode & S_IFMT == S_IFDIR
+}
+
+// Name of the listing buffer for a directory path
+pub(in crate::editor) fn listing_name(dir: &[u8], out: &mut [u8; MAX_PATH]) -> Result<(), usize> {
+    let dir = name_of(dir);
+    if is_listing(dir) {
+        join(out, &[dir])
+    } else {
+        join(out, &[dir, b"/"])
+    }
+}
+
+// Listing name of the directory above; relative paths grow ../ once they
+// run out of components
+pub(in crate::editor) fn parent_dir(dir: &[u8], out: &mut [u8; MAX_PATH]) -> Result<(), usize> {
+    let dir = name_of(dir);
+    let trimmed = dir.strip_suffix(b"/").unwrap_or(dir);
+    let start = trimmed
+        .iter()
+        .rposition(|&b| b == b'/')
+        .map_or(0, |slash| slash + 1);
+
+    match &trimmed[start..] {
+        b"" => join(out, &[b"/"]),
+        b"." => join(out, &[&trimmed[..start], b"../"]),
+        b".." => join(out, &[trimmed, b"/../"]),
+        _ if start == 0 => join(out, &[b"./"]),
+        _ => join(out, &[&trimmed[..start]]),
+    }
+}
+
+// Name of the entry on a listing line, if it has one
+pub(in crate::editor) fn entry_name(line: &[u8]) -> Option<&[u8]> {
+    line.get(NAME_COL..).filter(|name| !name.is_empty())
+}
+
+// Fill `out` with a header line and a line per entry sorted by name,
+// starting with ..
+pub(in crate::editor) fn write_listing(
+    dir: &[u8],
+    out: &mut FileBuffer,
+) -> Result<(), EditorError> {
+    let dir = name_of(dir);
+    for part in [&b"  "[..], dir, b":\n"] {
+        out.append_text(part)?;
+    }
+    add_entry(dir, b"..", out, FIRST_ENTRY - 1)?;
+
+    let mut path = [0u8; MAX_PATH];
+    join(&mut path, &[dir])?;
+    let mut result = Ok(());
+    for_each_entry(&path, |name, _| {
+        if result.is_ok() {
+            result = add_entry(dir, name, out, FIRST_ENTRY);
+        }
+    })?;
+    result
+}
+
+// Insert the line for an entry at its sorted place among the lines from `first`
+fn add_entry(
+    dir: &[u8],
+    name: &[u8],
+    out: &mut FileBuffer,
+    first: usize,
+) -> Result<(), EditorError> {
+    let mut path = [0u8; MAX_PATH];
+    join(&mut path, &[dir, name])?;
+    let mut status = Stat::new();
+    lstat(&path, &mut status)?;
+
+    // The last line is the empty one after the final newline
+    let mut low = first;
+    let mut high = out.count_lines().saturating_sub(1);
+    while low < high {
+        let mid = low.midpoint(high);
+        if out.get_line(mid).and_then(entry_name) < Some(name) {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    let pos = out.find_line_start(low).ok_or(EditorError::FileBuffer)?;
+
+    // Mode and size right-aligned in front of the name
+    let mut line = [b' '; NAME_COL];
+    line[2..12].copy_from_slice(&mode_string(status.mode));
+    let mut digits = [0u8; 20];
+    let len = write_usize_to_buf(&mut digits, usize::try_from(status.size).unwrap_or(0));
+    let len = len.min(SIZE_WIDTH);
+    line[NAME_COL - 1 - len..NAME_COL - 1].copy_from_slice(&digits[..len]);
+
+    out.insert_generated(pos, b"\n")?;
+    out.insert_generated(pos, name)?;
+    out.insert_generated(pos, &line)?;
+    Ok(())
+}
+
+// File type and permissions the way ls -l shows them
+fn mode_string(mode: u32) -> [u8; 10] {
+    let mut text = *b"----------";
+    text[0] = match mode & S_IFMT {
+        S_IFDIR => b'd',
+        S_IFLNK => b'l',
+        S_IFCHR => b'c',
+        S_IFBLK => b'b',
+        S_IFIFO => b'p',
+        S_IFSOCK => b's',
+        _ => b'-',
+    };
+
+    for (idx, &letter) in b"rwxrwxrwx".iter().enumerate() {
+        if mode & (0o400 >> idx) != 0 {
+            text[idx + 1] = letter;
+        }
+    }
+
+    // Set-user-ID, set-group-ID and sticky bits take the place of x
+    for (bit, idx, letter) in [(0o4000, 3, b's'), (0o2000, 6, b's'), (0o1000, 9, b't')] {
+        if mode & bit != 0 {
+            text[idx] = if text[idx] == b'x' {
+                letter
+            } else {
+                letter.to_ascii_uppercase()
+            };
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::file_buffer::tests::create_test_file_buffer;
+
+    fn parent(dir: &[u8]) -> Vec<u8> {
+        let mut out = [0u8; MAX_PATH];
+        parent_dir(dir, &mut out).unwrap();
+        name_of(&out).to_vec()
+    }
+
+    #[test]
+    fn test_mode_string() {
+        assert_eq!(&mode_string(S_IFDIR | 0o755), b"drwxr-xr-x");
+        assert_eq!(&mode_string(0o100_644), b"-rw-r--r--");
+        assert_eq!(&mode_string(S_IFLNK | 0o777), b"lrwxrwxrwx");
+        assert_eq!(&mode_string(0o104_755), b"-rwsr-xr-x");
+        assert_eq!(&mode_string(S_IFDIR | 0o1777), b"drwxrwxrwt");
+        assert_eq!(&mode_string(0o102_640), b"-rw-r-S---");
+    }
+
+    #[test]
+    fn test_parent_dir() {
+        assert_eq!(parent(b"src/editor/"), b"src/");
+        assert_eq!(parent(b"src/"), b"./");
+        assert_eq!(parent(b"./"), b"../");
+        assert_eq!(parent(b"../"), b"../../");
+        assert_eq!(parent(b"/usr/"), b"/");
+        assert_eq!(parent(b"/"), b"/");
+
+        let mut out = [0u8; MAX_PATH];
+        listing_name(b"src\0", &mut out).unwrap();
+        assert_eq!(name_of(&out), b"src/");
+        listing_name(b"src/", &mut out).unwrap();
+        assert_eq!(name_of(&out), b"src/");
+        assert!(is_listing(b"src/\0"));
+        assert!(!is_listing(b"src/main.rs\0"));
+    }
+
+    #[test]
+    fn test_write_listing() {
+        let dir = "test_write_listing_123456";
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir(dir).expect("Failed to create test directory");
+        std::fs::write(format!("{dir}/b.txt"), "hello").unwrap();
+        std::fs::write(format!("{dir}/a file"), "").unwrap();
+        std::fs::create_dir(format!("{dir}/sub")).unwrap();
+        assert!(is_directory(format!("{dir}/sub\0").as_bytes()));
+        assert!(!is_directory(format!("{dir}/b.txt\0").as_bytes()));
+
+        let mut out = create_test_file_buffer(b"");
+        let name = format!("{dir}/");
+        assert!(write_listing(name.as_bytes(), &mut out).is_ok());
+
+        let lines: Vec<&[u8]> = (0..out.count_lines())
+            .filter_map(|idx| out.get_line(idx))
+            .collect();
+        assert_eq!(lines[0], format!("  {dir}/:").as_bytes());
+        let names: Vec<&[u8]> = lines[1..]
+            .iter()
+            .filter_map(|line| entry_name(line))
+            .collect();
+        assert_eq!(names, [&b".."[..], b"a file", b"b.txt", b"sub"]);
+        assert_eq!(lines[3].get(2), Some(&b'-'));
+        assert!(lines[3].ends_with(b"         5 b.txt"));
+        assert_eq!(lines[4].get(2), Some(&b'd'));
+        assert!(!out.is_modified());
+
+        std::fs::remove_dir_all(dir).expect("Failed to clean up test directory");
+    }
+}
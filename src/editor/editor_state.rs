@@ -1,5 +1,8 @@
+use core::cell::Cell;
+
 use crate::{
-    syscall::{MAX_PATH, STDOUT, SysResult, putchar, puts, write_buf, write_unchecked},
+    syscall::{MAX_PATH, STDOUT, SysResult, puts, write_buf, write_unchecked},
+    terminal::write_usize_to_buf,
     terminal::{
         clear_line, move_cursor, reset_colors, restore_cursor, save_cursor, set_bg_color, set_bold,
         set_fg_color, write_number,
@@ -8,10 +11,24 @@ use crate::{
 };
 
 use super::{
-    FileBuffer, KillRing, KillRingError, SearchState, SyntaxHighlighter,
+    BackupMode, BufferList, BufferState, FileBuffer, FileBufferError, Key, KillRing, KillRingError,
+    LineIndex, Original, Screen, SearchState, SyntaxHighlighter, UndoLog,
+    buffer_list::{complete_prefix, name_of},
+    completion::Candidates,
+    regex::RegexError,
+    screen::Glyph,
+    search_state::Matcher,
     syntax_highlight::HighlightColor,
 };
 
+// How a character relates to the matches of the search being typed
+#[derive(Clone, Copy, PartialEq)]
+enum SearchHit {
+    None,
+    Other,
+    Current,
+}
+
 pub(in crate::editor) struct EditorState {
     pub(in crate::editor) winsize: Winsize, // Terminal window size
     pub(in crate::editor) cursor_row: usize, // Cursor row in the visible window
@@ -30,6 +47,13 @@ pub(in crate::editor) struct EditorState {
     pub(in crate::editor) mark_row: usize,     // Mark row position for selection
     pub(in crate::editor) mark_col: usize,     // Mark column position for selection
     pub(in crate::editor) kill_ring: KillRing, // Kill-ring for cut/copy/paste operations
+    pub(in crate::editor) yank_row: usize,     // Start row of the last yanked text
+    pub(in crate::editor) yank_col: usize,     // Start column of the last yanked text
+    pub(in crate::editor) last_key: Option<Key>, // Previously processed key
+    pub(in crate::editor) screen: Screen,      // Double-buffered text area frames
+    pub(in crate::editor) buffers: BufferList, // Open buffers other than the shown one
+    pub(in crate::editor) backup_mode: BackupMode, // How the first save keeps the old file
+    pub(in crate::editor) auto_revert: bool,   // Reload unedited buffers changed on disk
 }
 
 impl EditorState {
@@ -65,6 +89,14 @@ impl EditorState {
                 size: 0,
                 capacity: 0,
                 modified: false,
+                tail: Cell::new(0),
+                lines: LineIndex::new(),
+                undo: UndoLog::new(),
+                changed: Cell::new(0),
+                original: Original::none(),
+                read_only: false,
+                auto_save_pending: false,
+                stamp: None,
             },
             search: SearchState::new(),
             highlighter,
@@ -72,6 +104,13 @@ impl EditorState {
             mark_row: 0,
             mark_col: 0,
             kill_ring,
+            yank_row: 0,
+            yank_col: 0,
+            last_key: None,
+            screen: Screen::new(),
+            buffers: BufferList::new(),
+            backup_mode: BackupMode::Single,
+            auto_revert: false,
         }
     }
 
@@ -90,19 +129,27 @@ impl EditorState {
 
     // Start search mode
     fn display_search_direction(&self) -> SysResult {
-        if self.search.reverse {
-            puts("Reverse search")
-        } else {
-            puts("Search")
+        match (self.search.reverse, self.search.regex) {
+            (false, false) => puts("Search"),
+            (true, false) => puts("Reverse search"),
+            (false, true) => puts("Regexp search"),
+            (true, true) => puts("Reverse regexp search"),
         }
     }
 
-    fn display_case_sensitivity(&self) -> SysResult {
-        if self.search.case_sensitive {
-            puts(" (case-sensitive): ")
+    fn display_search_modes(&self) -> SysResult {
+        if self.search.is_case_sensitive() {
+            puts(" (case-sensitive")?;
         } else {
-            puts(" (case-insensitive): ")
+            puts(" (case-insensitive")?;
+        }
+        if self.search.smart_case {
+            puts(", smart-case")?;
         }
+        if self.search.whole_word {
+            puts(", whole-word")?;
+        }
+        puts("): ")
     }
 
     fn display_search_query(&self) -> SysResult {
@@ -112,7 +159,7 @@ impl EditorState {
     fn display_complete_search_prompt(&self) -> SysResult {
         let mut result = self.display_search_direction();
         if result.is_ok() {
-            result = self.display_case_sensitivity();
+            result = self.display_search_modes();
         }
         if result.is_ok() {
             result = self.display_search_query();
@@ -121,6 +168,20 @@ impl EditorState {
     }
 
     pub(in crate::editor) fn start_search(&mut self, reverse: bool) -> SysResult {
+        self.begin_search(reverse, false, false)
+    }
+
+    // Start search mode with the query read as a regular expression
+    pub(in crate::editor) fn start_regex_search(&mut self, reverse: bool) -> SysResult {
+        self.begin_search(reverse, true, false)
+    }
+
+    // Start a forward search for whole words only
+    pub(in crate::editor) fn start_word_search(&mut self) -> SysResult {
+        self.begin_search(false, false, true)
+    }
+
+    fn begin_search(&mut self, reverse: bool, regex: bool, whole_word: bool) -> SysResult {
         // Save current position to return to if search is cancelled
         self.search.orig_row = self.file_row;
         self.search.orig_col = self.file_col;
@@ -131,6 +192,10 @@ impl EditorState {
         self.search.query_len = 0;
         self.search.match_len = 0; // Reset match length
         self.search.case_sensitive = false; // Default to case-insensitive
+        self.search.smart_case = true;
+        self.search.regex = regex;
+        self.search.whole_word = whole_word;
+        self.search.history_pos = 0;
 
         // Clear the query array
         let mut i = 0;
@@ -144,6 +209,7 @@ impl EditorState {
 
     // Cancel search mode and return to original position
     pub(in crate::editor) fn cancel_search(&mut self) -> SysResult {
+        self.search.remember_query();
         self.search.mode = false;
         self.search.match_len = 0; // Clear highlighting
 
@@ -159,59 +225,34 @@ impl EditorState {
 
     // Accept current search match and exit search mode
     pub(in crate::editor) fn accept_search(&mut self) -> SysResult {
+        self.search.remember_query();
         self.search.mode = false;
         self.search.match_len = 0; // Clear highlighting
         self.draw_screen()?;
         self.print_message("")
     }
 
-    fn chars_match_case_sensitive(line_ch: u8, query_ch: u8, case_sensitive: bool) -> bool {
-        if case_sensitive {
-            line_ch == query_ch
-        } else {
-            let line_ch_lower = if line_ch.is_ascii_uppercase() {
-                line_ch + 32
-            } else {
-                line_ch
-            };
-
-            let query_ch_lower = if query_ch.is_ascii_uppercase() {
-                query_ch + 32
-            } else {
-                query_ch
-            };
-
-            line_ch_lower == query_ch_lower
-        }
-    }
-
     fn validate_current_match(&mut self) -> bool {
-        match self.buffer.get_line(self.file_row) {
-            Some(line) if self.file_col + self.search.query_len <= line.len() => {
-                let query = &self.search.query[..self.search.query_len];
-
-                for j in 0..self.search.query_len {
-                    let line_ch = line[self.file_col + j];
-                    let query_ch = query[j];
-
-                    if !Self::chars_match_case_sensitive(
-                        line_ch,
-                        query_ch,
-                        self.search.case_sensitive,
-                    ) {
-                        return false;
-                    }
-                }
-
-                self.search.match_row = self.file_row;
-                self.search.match_col = self.file_col;
-                self.search.match_len = self.search.query_len;
+        let Some(line) = self.buffer.get_line(self.file_row) else {
+            return false;
+        };
+        let mut matcher = self.search.matcher();
+        match self.search.match_at(&mut matcher, line, self.file_col) {
+            Some(len) => {
+                self.search
+                    .set_match(&self.buffer, self.file_row, self.file_col, len);
                 true
             }
-            _ => false,
+            None => false,
         }
     }
 
+    // A regex that does not compile says why instead
+    fn print_search_failure(&self, msg: &'static str) -> SysResult {
+        let error = self.search.regex_error();
+        self.print_warning(error.map_or(msg, RegexError::message))
+    }
+
     fn execute_search(&mut self) -> SysResult {
         let search_result = if self.search.reverse {
             self.search
@@ -221,18 +262,15 @@ impl EditorState {
                 .find_substring_forward(&self.buffer, self.file_row, self.file_col)
         };
 
-        match search_result {
-            Some((row, col, len)) => {
-                self.search.match_row = row;
-                self.search.match_col = col;
-                self.search.match_len = len;
-                self.file_row = row;
-                self.file_col = col;
-                self.scroll_to_cursor();
-                self.draw_screen()
-            }
-            None => self.print_warning("No match found"),
-        }
+        let Some((row, col, len)) = search_result else {
+            self.search.update_position(&self.buffer);
+            return self.print_search_failure("No match found");
+        };
+        self.search.set_match(&self.buffer, row, col, len);
+        self.file_row = row;
+        self.file_col = col;
+        self.scroll_to_cursor();
+        self.draw_screen()
     }
 
     pub(in crate::editor) fn update_search(&mut self) -> SysResult {
@@ -251,11 +289,17 @@ impl EditorState {
 
     // Add a character to the search query
     pub(in crate::editor) fn add_search_char(&mut self, ch: u8) -> SysResult {
-        // Check if the query still has space for one more character
-        if self.search.query_len < self.search.query.len() - 1 {
-            // Add the character to the query
-            self.search.query[self.search.query_len] = ch;
-            self.search.query_len += 1;
+        self.add_search_text(&[ch])
+    }
+
+    // Add text to the end of the search query
+    fn add_search_text(&mut self, text: &[u8]) -> SysResult {
+        // Check if the query still has space for the text
+        let len = self.search.query_len;
+        if len + text.len() < self.search.query.len() {
+            // Add the text to the query
+            self.search.query[len..len + text.len()].copy_from_slice(text);
+            self.search.query_len += text.len();
 
             // Update search prompt
             let result = self.print_status(|| self.display_complete_search_prompt());
@@ -271,6 +315,65 @@ impl EditorState {
         }
     }
 
+    // Add the rest of the word after the current match, or after the cursor
+    // without one, to the search query
+    pub(in crate::editor) fn yank_word_into_search(&mut self) -> SysResult {
+        let (row, point) = if self.search.query_len > 0 && self.search.match_len > 0 {
+            (
+                self.search.match_row,
+                self.search.match_col + self.search.match_len,
+            )
+        } else {
+            (self.file_row, self.file_col)
+        };
+        let line = self.buffer.get_line(row).unwrap_or_default();
+        let point = point.min(line.len());
+        let end = Self::skip_current_word(line, point);
+
+        let mut text = [0u8; MAX_PATH];
+        let len = self.search.quote(&line[point..end], &mut text);
+        if len == 0 {
+            return Ok(0);
+        }
+        self.add_search_text(&text[..len])
+    }
+
+    // Add the newest kill to the search query
+    pub(in crate::editor) fn yank_kill_into_search(&mut self) -> SysResult {
+        let mut text = [0u8; MAX_PATH];
+        let len = self.search.quote(self.kill_ring.content(), &mut text);
+        if len == 0 {
+            return Ok(0);
+        }
+        self.add_search_text(&text[..len])
+    }
+
+    // Replace the query with an older one from the history, or a newer one,
+    // and search for it from where the search started
+    pub(in crate::editor) fn walk_search_history(&mut self, older: bool) -> SysResult {
+        let back = if older {
+            self.search.history_pos + 1
+        } else if self.search.history_pos > 0 {
+            self.search.history_pos - 1
+        } else {
+            return self.print_warning("No newer search query");
+        };
+        if !self.search.recall_query(back) {
+            return self.print_warning("No older search query");
+        }
+
+        self.file_row = self.search.orig_row;
+        self.file_col = self.search.orig_col;
+        self.search.match_len = 0;
+        self.print_status(|| self.display_complete_search_prompt())?;
+        if self.search.query_len > 0 {
+            self.execute_search()
+        } else {
+            self.scroll_to_cursor();
+            self.draw_screen()
+        }
+    }
+
     // Switch search direction and update display
     pub(in crate::editor) fn switch_search_direction(&mut self) -> SysResult {
         // Only proceed if in search mode and with a valid query
@@ -322,9 +425,7 @@ impl EditorState {
                 // Process search result
                 if let Some((row, col, len)) = search_result {
                     // Found a match with new case sensitivity setting
-                    self.search.match_row = row;
-                    self.search.match_col = col;
-                    self.search.match_len = len;
+                    self.search.set_match(&self.buffer, row, col, len);
                     self.file_row = row;
                     self.file_col = col;
                     self.scroll_to_cursor();
@@ -332,7 +433,8 @@ impl EditorState {
                     Ok(0)
                 } else {
                     // No match found with new case sensitivity setting
-                    self.print_warning("No match found")?;
+                    self.search.update_position(&self.buffer);
+                    self.print_search_failure("No match found")?;
                     Ok(0)
                 }
             }
@@ -341,6 +443,22 @@ impl EditorState {
         }
     }
 
+    // Toggle whole-word matching in search mode
+    pub(in crate::editor) fn toggle_search_whole_word(&mut self) -> SysResult {
+        if !self.search.mode {
+            return Ok(0);
+        }
+        self.search.whole_word = !self.search.whole_word;
+        self.print_status(|| self.display_complete_search_prompt())?;
+
+        // Search again from the cursor with the new setting
+        if self.search.query_len == 0 {
+            return Ok(0);
+        }
+        self.search.match_len = 0;
+        self.execute_search()
+    }
+
     // Remove the last character from the search query
     pub(in crate::editor) fn remove_search_char(&mut self) -> SysResult {
         // Only proceed if there is at least one character in the query
@@ -382,9 +500,9 @@ impl EditorState {
 
     // Find the next match for the current search query
     pub(in crate::editor) fn find_next_match(&mut self) -> SysResult {
-        // Only proceed if there is a query to search for
+        // With nothing typed yet, search for the last query again
         if self.search.query_len == 0 {
-            Ok(0)
+            self.walk_search_history(true)
         } else {
             // Determine search starting position based on direction
             let (search_row, search_col) = if self.search.reverse {
@@ -417,7 +535,8 @@ impl EditorState {
                 // For forward search, start after the end of the current match
                 (
                     self.search.match_row,
-                    self.search.match_col + self.search.match_len,
+                    // An empty regex match moves on by one
+                    self.search.match_col + self.search.match_len.max(1),
                 )
             };
 
@@ -434,9 +553,7 @@ impl EditorState {
             match search_result {
                 Some((row, col, len)) => {
                     // Store the match information
-                    self.search.match_row = row;
-                    self.search.match_col = col;
-                    self.search.match_len = len;
+                    self.search.set_match(&self.buffer, row, col, len);
 
                     // Move cursor to the match position
                     self.file_row = row;
@@ -448,7 +565,7 @@ impl EditorState {
                 }
                 None => {
                     // No more matches found
-                    self.print_warning("No more matches")
+                    self.print_search_failure("No more matches")
                 }
             }
         }
@@ -565,7 +682,7 @@ impl EditorState {
     }
 
     #[inline]
-    fn is_alnum(ch: u8) -> bool {
+    pub(in crate::editor) fn is_alnum(ch: u8) -> bool {
         ch.is_ascii_alphanumeric()
     }
 
@@ -625,12 +742,14 @@ impl EditorState {
 
         let mut new_row = self.file_row - 1;
         loop {
-            if let Some(line) = self.buffer.get_line(new_row) {
-                if !line.is_empty() {
-                    let col = line.len().saturating_sub(1);
-                    let col = EditorState::find_word_start(line, col);
-                    return Some((new_row, col));
-                }
+            if let Some(line) = self
+                .buffer
+                .get_line(new_row)
+                .filter(|line| !line.is_empty())
+            {
+                let col = line.len().saturating_sub(1);
+                let col = EditorState::find_word_start(line, col);
+                return Some((new_row, col));
             }
 
             if new_row == 0 {
@@ -800,6 +919,16 @@ impl EditorState {
         puts(":")?;
         write_number(self.file_col);
 
+        if self.search.mode && self.search.query_len > 0 {
+            let (current, total) = self.search.position;
+            if total > 0 {
+                puts(" match ")?;
+                write_number(current);
+                puts(" of ")?;
+                write_number(total);
+            }
+        }
+
         #[cfg(debug_assertions)]
         {
             puts(" ")?;
@@ -815,60 +944,68 @@ impl EditorState {
         restore_cursor()
     }
 
-    // Handle tabs in the editor display - convert to spaces
-    fn handle_tab_display(
-        &self,
-        _tab_byte: u8,
-        col: &mut usize,
-        screen_col: &mut usize,
-        chars_to_skip: &mut usize,
-    ) -> SysResult {
-        let spaces = self.tab_size - (*col % self.tab_size);
-        *col += spaces;
-
-        // Skip if we're still scrolled horizontally
-        if *chars_to_skip > 0 {
-            if *chars_to_skip >= spaces {
-                *chars_to_skip -= spaces;
-                return Ok(0);
-            }
+    pub(in crate::editor) fn draw_screen(&mut self) -> SysResult {
+        self.compose_text_area()?;
 
-            // Draw partial spaces after the horizontal scroll point
-            let visible_spaces = spaces - *chars_to_skip;
-            for _ in 0..visible_spaces {
-                if *screen_col < self.winsize.cols as usize {
-                    putchar(b' ')?;
-                    *screen_col += 1;
-                } else {
-                    break;
-                }
-            }
-            *chars_to_skip = 0;
+        // Send the changes and move cursor to the correct position
+        self.screen.flush(self.cursor_row, self.cursor_col)
+    }
+
+    // Show completion candidates in columns at the bottom of the text area
+    pub(in crate::editor) fn draw_candidates(&mut self, candidates: &Candidates) -> SysResult {
+        self.compose_text_area()?;
+
+        let cols = self.winsize.cols as usize;
+        let width = candidates.iter().map(<[u8]>::len).max().unwrap_or(0) + 2;
+        let per_row = (cols / width).max(1);
+        let shown = candidates.iter().count();
+        let rows = shown.div_ceil(per_row).min(self.editing_rows());
+        let top = self.editing_rows() - rows;
+        for row in top..top + rows {
+            self.screen.clear_row(row);
+        }
+
+        // The last slot tells how many did not fit
+        let slots = rows * per_row;
+        let listed = if candidates.total() > slots {
+            slots.saturating_sub(1)
         } else {
-            // Draw spaces for tab
-            for _ in 0..spaces {
-                if *screen_col < self.winsize.cols as usize {
-                    putchar(b' ')?;
-                    *screen_col += 1;
-                } else {
-                    break;
-                }
-            }
+            candidates.total()
+        };
+        for (idx, name) in candidates.iter().take(listed).enumerate() {
+            self.screen.put_text(
+                top + idx / per_row,
+                idx % per_row * width,
+                name,
+                Glyph::BLANK,
+            );
+        }
+        if listed < candidates.total() && rows > 0 {
+            let mut digits = [0u8; 20];
+            let len = write_usize_to_buf(&mut digits, candidates.total() - listed);
+            let row = top + rows - 1;
+            let col = self
+                .screen
+                .put_text(row, listed % per_row * width, b"+", Glyph::BLANK);
+            let col = self.screen.put_text(row, col, &digits[..len], Glyph::BLANK);
+            self.screen.put_text(row, col, b" more", Glyph::BLANK);
         }
 
-        Ok(0)
+        self.screen.flush(self.cursor_row, self.cursor_col)
     }
 
-    pub(in crate::editor) fn draw_screen(&mut self) -> SysResult {
+    // Compose the visible lines of the buffer into the back frame
+    fn compose_text_area(&mut self) -> SysResult {
         // Calculate available height for content
         let available_rows = self.editing_rows();
         let line_count = self.buffer.count_lines();
 
-        // Draw lines from the file buffer
+        // Compose the text area into the back frame
+        self.screen
+            .begin(available_rows, self.winsize.cols as usize)?;
+        let searching = self.search.mode && self.search.query_len > 0;
+        let mut matcher = searching.then(|| self.search.matcher());
         for i in 0..available_rows {
-            move_cursor(i, 0)?;
-            clear_line()?;
-
             let file_line_idx = self.scroll_row + i;
             if file_line_idx >= line_count {
                 // We're past the end of file, leave the rest of screen empty
@@ -876,135 +1013,116 @@ impl EditorState {
             }
 
             // Process this line
-            self.draw_line_at_index(file_line_idx)?;
+            self.draw_line_at_index(i, file_line_idx, matcher.as_mut());
         }
-
-        // Move cursor to the correct position
-        move_cursor(self.cursor_row, self.cursor_col)?;
         Ok(0)
     }
 
-    // Draw a specific line in the buffer by its index
-    fn draw_line_at_index(&mut self, file_line_idx: usize) -> SysResult {
-        // Get the line
-        if let Some(line) = self.buffer.get_line(file_line_idx) {
-            if line.is_empty() {
-                return Ok(0);
+    // Attributes for a character based on selection, search match and syntax
+    fn glyph_for(byte: u8, is_selected: bool, hit: SearchHit, color: HighlightColor) -> Glyph {
+        let mut glyph = Glyph::plain(byte);
+        if is_selected {
+            // Selection highlighting takes precedence over everything
+            glyph.bg = 4; // Blue background
+            glyph.fg = 7; // White text
+        } else if hit == SearchHit::Current {
+            // Search match highlighting takes precedence after selection
+            glyph.bg = 7;
+            glyph.fg = 0;
+        } else if hit == SearchHit::Other {
+            glyph.bg = 6; // Cyan background for the other matches
+            glyph.fg = 0;
+        } else {
+            // Apply regular syntax highlighting based on character type
+            match color {
+                HighlightColor::Default => {}              // No color change
+                HighlightColor::Delimiter => glyph.fg = 6, // Cyan for delimiters
+                HighlightColor::Comment => glyph.fg = 2,   // Green for comments
+                HighlightColor::Keyword => {
+                    glyph.fg = 4; // Blue for keywords
+                    glyph.bold = true;
+                }
+                HighlightColor::String => glyph.fg = 1, // Red for strings
+                HighlightColor::Number => glyph.fg = 5, // Magenta for numbers
             }
+        }
+        glyph
+    }
 
-            // Get line info
-            let line_start = self.buffer.find_line_start(file_line_idx).unwrap_or(0);
+    // Compose a specific line in the buffer by its index into a screen row,
+    // marking the matches of the matcher when searching
+    fn draw_line_at_index(
+        &mut self,
+        screen_row: usize,
+        file_line_idx: usize,
+        mut matcher: Option<&mut Matcher>,
+    ) {
+        // Get the line
+        let Some(line) = self.buffer.get_line(file_line_idx) else {
+            return;
+        };
 
-            // Check for search match
-            let is_match_line = self.search.mode
-                && self.search.query_len > 0
-                && file_line_idx == self.search.match_row;
+        // Get line info
+        let line_start = self.buffer.find_line_start(file_line_idx).unwrap_or(0);
+
+        // Check for search match
+        let is_match_line =
+            self.search.mode && self.search.query_len > 0 && file_line_idx == self.search.match_row;
+        let match_start = self.search.match_col;
+        let match_end = self.search.match_col + self.search.match_len;
+        let mut hit_end = 0;
+
+        // Horizontal scrolling: columns before scroll_col are not shown
+        let visible_end = self.scroll_col + self.winsize.cols as usize;
+        let mut col = 0;
+
+        // Colors come from lexing the line from its start, including the scrolled-off part
+        let mut colors = self
+            .highlighter
+            .line_colors(&self.buffer, file_line_idx, line_start);
+
+        // Display each character in the line
+        for (idx, &byte) in line.iter().enumerate() {
+            if byte == 0 || col >= visible_end {
+                // Stop at null byte or the right edge of the screen
+                break;
+            }
 
-            let match_start = if is_match_line {
-                self.search.match_col
-            } else {
-                0
-            };
-            let match_end = if is_match_line {
-                self.search.match_col + self.search.match_len
+            // Tabs are shown as spaces up to the next tab stop
+            let (shown, width) = if byte == b'\t' {
+                (b' ', self.tab_size - (col % self.tab_size))
             } else {
-                0
+                (byte, 1)
             };
 
-            // Calculate how much to skip from the start (for horizontal scrolling)
-            let mut chars_to_skip = self.scroll_col;
-            let mut col = 0;
-            let mut screen_col = 0;
-
-            // Display each character in the line
-            let mut idx = 0;
-            while idx < line.len() {
-                let byte = line[idx];
-
-                if byte == 0 {
-                    // Stop at null byte
-                    break;
-                }
-
-                // Track if current character is part of a search match for highlighting
-                let is_highlight = is_match_line && idx >= match_start && idx < match_end;
-
-                // Get syntax highlight color directly
-                let abs_pos = line_start + idx;
-                let syntax_highlight = self.highlighter.highlight_char(&self.buffer, abs_pos);
-
-                // Apply highlighting if visible
-                if chars_to_skip == 0 && screen_col < self.winsize.cols as usize {
-                    // Check if the character is part of a selection
-                    let is_selected = self.is_position_selected(file_line_idx, idx);
-
-                    if is_selected {
-                        // Selection highlighting takes precedence over everything
-                        set_bg_color(4)?; // Blue background
-                        set_fg_color(7)?; // White text
-                    } else if is_highlight {
-                        // Search match highlighting takes precedence after selection
-                        set_bg_color(7)?;
-                        set_fg_color(0)?;
-                    } else if let HighlightColor::Delimiter = syntax_highlight {
-                        // For delimiters, use cyan
-                        set_fg_color(6)?;
-                    } else {
-                        // Apply regular syntax highlighting based on character type
-                        match syntax_highlight {
-                            HighlightColor::Default | HighlightColor::Delimiter => {} // No color change
-                            HighlightColor::Comment => {
-                                set_fg_color(2)?; // Green for comments
-                            }
-                            HighlightColor::Keyword => {
-                                set_fg_color(4)?; // Blue for keywords
-                                set_bold()?;
-                            }
-                            HighlightColor::String => {
-                                set_fg_color(1)?; // Red for strings
-                            }
-                            HighlightColor::Number => {
-                                set_fg_color(5)?; // Magenta for numbers
-                            }
-                        }
-                    }
-                }
+            // Matches are looked for after the end of the previous one
+            if let Some(len) = matcher
+                .as_deref_mut()
+                .filter(|_| idx >= hit_end)
+                .and_then(|matcher| self.search.match_at(matcher, line, idx))
+            {
+                hit_end = idx + len;
+            }
 
-                // Handle the character display
-                if byte == b'\t' {
-                    self.handle_tab_display(byte, &mut col, &mut screen_col, &mut chars_to_skip)?;
+            let color = colors.next().unwrap_or(HighlightColor::Default);
+            if col + width > self.scroll_col {
+                let hit = if is_match_line && idx >= match_start && idx < match_end {
+                    SearchHit::Current
+                } else if idx < hit_end {
+                    SearchHit::Other
                 } else {
-                    col += 1;
-
-                    // Only print if we've scrolled past the horizontal skip point
-                    if chars_to_skip > 0 {
-                        chars_to_skip -= 1;
-                    } else if screen_col < self.winsize.cols as usize {
-                        putchar(byte)?;
-                        screen_col += 1;
-                    } else {
-                        break;
-                    }
-                }
-
-                // Reset colors after printing if needed
+                    SearchHit::None
+                };
                 let is_selected = self.is_position_selected(file_line_idx, idx);
-                if (is_selected || is_highlight || syntax_highlight != HighlightColor::Default)
-                    && chars_to_skip == 0
-                    && screen_col <= self.winsize.cols as usize
-                {
-                    reset_colors()?;
-                }
+                let glyph = Self::glyph_for(shown, is_selected, hit, color);
 
-                // Move to next character
-                idx += 1;
+                for c in col.max(self.scroll_col)..col + width {
+                    self.screen.put(screen_row, c - self.scroll_col, glyph);
+                }
             }
 
-            // Ensure colors are reset at the end of each line
-            reset_colors()?;
+            col += width;
         }
-
-        Ok(0)
     }
 
     // Print a message to the last line of the screen
@@ -1043,6 +1161,16 @@ impl EditorState {
         })
     }
 
+    // Print a prompt made of several parts to the status line
+    pub(in crate::editor) fn print_prompt(&self, parts: &[&[u8]]) -> SysResult {
+        self.print_status(|| {
+            for part in parts {
+                write_buf(part)?;
+            }
+            Ok(0)
+        })
+    }
+
     // Set mark at current cursor position
     pub(in crate::editor) fn set_mark(&mut self) -> SysResult {
         self.mark_active = true;
@@ -1058,6 +1186,13 @@ impl EditorState {
         self.print_message("Mark cleared")
     }
 
+    // Buffer position of a row and column, with the column clamped to the line
+    fn position_at(&self, row: usize, col: usize) -> Option<usize> {
+        let start = self.buffer.find_line_start(row)?;
+        let end = self.buffer.find_line_end(row)?;
+        Some(start + col.min(end - start))
+    }
+
     // Get selected text range (ordered by position)
     fn get_selection_range(&self) -> ((usize, usize), (usize, usize)) {
         let (start_row, start_col, end_row, end_col) = if self.file_row < self.mark_row
@@ -1071,6 +1206,55 @@ impl EditorState {
         ((start_row, start_col), (end_row, end_col))
     }
 
+    // Insert text at the cursor and select it, leaving the cursor in front
+    // of it as C-x i does in Emacs
+    pub(in crate::editor) fn insert_block(&mut self, bytes: &[u8]) -> Result<(), FileBufferError> {
+        let pos = self
+            .position_at(self.file_row, self.file_col)
+            .ok_or(FileBufferError::InvalidOperation)?;
+        self.buffer.insert_text(pos, bytes)?;
+
+        let (row, col) = self.buffer.position_to_row_col(pos + bytes.len());
+        self.mark_active = true;
+        self.mark_row = row;
+        self.mark_col = col;
+        self.scroll_to_cursor();
+        Ok(())
+    }
+
+    // Replace text at a row and column, leaving the cursor after the new text
+    pub(in crate::editor) fn replace_text(
+        &mut self,
+        row: usize,
+        col: usize,
+        len: usize,
+        with: &[u8],
+    ) -> Result<(), FileBufferError> {
+        let pos = self
+            .position_at(row, col)
+            .ok_or(FileBufferError::InvalidOperation)?;
+        self.buffer.delete_text(pos, len)?;
+        self.buffer.insert_text(pos, with)?;
+
+        let (row, col) = self.buffer.position_to_row_col(pos + with.len());
+        self.file_row = row;
+        self.file_col = col;
+        self.preferred_col = col;
+        Ok(())
+    }
+
+    // Buffer offsets of the active selection
+    pub(in crate::editor) fn selection_bounds(&self) -> Option<(usize, usize)> {
+        if !self.mark_active {
+            return None;
+        }
+        let ((start_row, start_col), (end_row, end_col)) = self.get_selection_range();
+        Some((
+            self.position_at(start_row, start_col)?,
+            self.position_at(end_row, end_col)?,
+        ))
+    }
+
     // Calculate total selection size in bytes
     fn selection_size(&self) -> usize {
         if !self.mark_active {
@@ -1118,93 +1302,27 @@ impl EditorState {
             return self.print_message("No selection (mark not active)");
         }
 
-        let selection_size = self.selection_size();
-        if selection_size == 0 {
+        if self.selection_size() == 0 {
             return self.print_message("Empty selection");
-        } else if selection_size > self.kill_ring.capacity() {
-            return self.print_error("Selection too large for kill-ring");
         }
 
         let ((start_row, start_col), (end_row, end_col)) = self.get_selection_range();
-
-        // Create a fixed-size buffer for the selected text
-        let mut selected_text = [0u8; 4096]; // Maximum size (one page)
-        let mut text_len = 0;
-
-        // Copy text from buffer to the selected_text array
-        if start_row == end_row {
-            // Single line selection
-            if let Some(line) = self.buffer.get_line(start_row) {
-                let end_idx = end_col.min(line.len());
-                // Use indices as we need to control text_len
-                let end = end_idx.min(line.len());
-                let mut idx = start_col;
-                while idx < end && text_len < selected_text.len() {
-                    selected_text[text_len] = line[idx];
-                    text_len += 1;
-                    idx += 1;
-                }
-            }
-        } else {
-            // Multiline selection
-
-            // First line - from start_col to end of line
-            if let Some(line) = self.buffer.get_line(start_row) {
-                // Use indices as we need to control text_len
-                let mut idx = start_col;
-                while idx < line.len() && text_len < selected_text.len() {
-                    selected_text[text_len] = line[idx];
-                    text_len += 1;
-                    idx += 1;
-                }
-                if text_len < selected_text.len() {
-                    selected_text[text_len] = b'\n';
-                    text_len += 1;
-                }
-            }
-
-            // Middle lines (complete lines)
-            for row in (start_row + 1)..end_row {
-                if let Some(line) = self.buffer.get_line(row) {
-                    for &ch in line {
-                        if text_len < selected_text.len() {
-                            selected_text[text_len] = ch;
-                            text_len += 1;
-                        }
-                    }
-                    if text_len < selected_text.len() {
-                        selected_text[text_len] = b'\n';
-                        text_len += 1;
-                    }
-                }
-            }
-
-            // Last line - from beginning to end_col
-            if let Some(line) = self.buffer.get_line(end_row) {
-                let end_idx = end_col.min(line.len());
-                // Use indices as we need to control text_len
-                let end = end_idx.min(line.len());
-                let mut idx = 0;
-                while idx < end && text_len < selected_text.len() {
-                    selected_text[text_len] = line[idx];
-                    text_len += 1;
-                    idx += 1;
-                }
-            }
-        }
+        let (Some(start), Some(end)) = (
+            self.position_at(start_row, start_col),
+            self.position_at(end_row, end_col),
+        ) else {
+            return self.print_error("Failed to copy selection");
+        };
 
         // Copy to kill-ring
-        match self.kill_ring.copy(&selected_text[..text_len]) {
+        match self.kill_ring.copy(self.buffer.text_range(start, end)) {
             Ok(()) => {
                 // Deactivate the mark after copying
                 self.mark_active = false;
                 self.draw_screen()?;
                 self.print_message("Copied selection to kill-ring")
             }
-            Err(KillRingError::BufferTooLarge) => {
-                self.print_error("Selection too large for kill-ring")
-            }
-            Err(_) => self.print_error("Failed to copy selection"),
+            Err(KillRingError::AllocationFailed) => self.print_error("Failed to copy selection"),
         }
     }
 
@@ -1214,20 +1332,33 @@ impl EditorState {
             return self.print_message("No selection (mark not active)");
         }
 
-        // First copy the selection to kill-ring
-        let copy_result = self.copy_selection();
-        copy_result?;
+        let ((start_row, start_col), (end_row, end_col)) = self.get_selection_range();
+        let (Some(start), Some(end)) = (
+            self.position_at(start_row, start_col),
+            self.position_at(end_row, end_col),
+        ) else {
+            return self.print_error("Failed to cut selection");
+        };
+        if start == end {
+            return self.print_message("Empty selection");
+        }
+
+        // First copy the selection to kill-ring; with the cursor at its start
+        // the region was killed backwards
+        let backward = (self.file_row, self.file_col) == (start_row, start_col);
+        if self.record_kill(start, end, backward).is_err() {
+            return self.print_error("Failed to copy selection");
+        }
 
         // Now delete the selection
-        let ((start_row, start_col), (end_row, end_col)) = self.get_selection_range();
 
         // Delete selection from buffer
         if start_row == end_row {
             // Single line selection - delete characters from start_col to end_col
             for _ in start_col..end_col {
                 let result = self.buffer.delete_char(start_row, start_col);
-                if result.is_err() {
-                    return self.print_error("Failed to delete selection");
+                if let Err(e) = result {
+                    return self.print_error(e.describe("Failed to delete selection"));
                 }
             }
         } else {
@@ -1242,16 +1373,16 @@ impl EditorState {
                 };
 
                 let result = self.buffer.delete_at_position(line_end);
-                if result.is_err() {
-                    return self.print_error("Failed to delete line in selection");
+                if let Err(e) = result {
+                    return self.print_error(e.describe("Failed to delete line in selection"));
                 }
             }
 
             // Delete partial last line (from beginning to end_col)
             for _ in 0..end_col {
                 let result = self.buffer.delete_char(start_row + 1, 0);
-                if result.is_err() {
-                    return self.print_error("Failed to delete end of selection");
+                if let Err(e) = result {
+                    return self.print_error(e.describe("Failed to delete end of selection"));
                 }
             }
 
@@ -1266,8 +1397,8 @@ impl EditorState {
             // Delete multiple characters at the same position
             for _ in 0..chars_to_delete {
                 let result = self.buffer.delete_char(start_row, start_col);
-                if result.is_err() {
-                    return self.print_error("Failed to delete start of selection");
+                if let Err(e) = result {
+                    return self.print_error(e.describe("Failed to delete start of selection"));
                 }
             }
 
@@ -1277,8 +1408,9 @@ impl EditorState {
             };
             let result = self.buffer.delete_at_position(pos);
 
-            if result.is_err() {
-                return self.print_error("Failed to join lines after deleting selection");
+            if let Err(e) = result {
+                return self
+                    .print_error(e.describe("Failed to join lines after deleting selection"));
             }
         }
 
@@ -1297,90 +1429,298 @@ impl EditorState {
 
     // Paste text from kill-ring at current position
     pub(in crate::editor) fn paste_from_kill_ring(&mut self) -> SysResult {
-        let kill_ring_content = self.kill_ring.content();
-        if kill_ring_content.is_empty() {
+        if self.kill_ring.content().is_empty() {
             return self.print_message("Kill-ring is empty");
         }
 
-        // Insert text from kill-ring
-        for &byte in kill_ring_content {
+        if let Err(msg) = self.insert_kill_ring_entry() {
+            return self.print_error(msg);
+        }
+
+        // Update buffer and screen
+        self.buffer.modified = true;
+        self.scroll_to_cursor();
+        self.draw_screen()?;
+
+        self.print_message("Pasted from kill-ring")
+    }
+
+    // Replace the text inserted by the previous yank with the next older kill
+    pub(in crate::editor) fn yank_pop(&mut self) -> SysResult {
+        if !matches!(self.last_key, Some(Key::Paste | Key::YankPop)) {
+            return self.print_message("Previous command was not a yank");
+        }
+
+        // The yanked text spans from the yank start to the cursor
+        let (Some(start), Some(end)) = (
+            self.position_at(self.yank_row, self.yank_col),
+            self.position_at(self.file_row, self.file_col),
+        ) else {
+            return self.print_error("Failed to find yanked text");
+        };
+
+        for _ in start..end {
+            if let Err(e) = self.buffer.delete_at_position(start) {
+                return self.print_error(e.describe("Failed to remove yanked text"));
+            }
+        }
+        self.file_row = self.yank_row;
+        self.file_col = self.yank_col;
+
+        self.kill_ring.rotate();
+        if let Err(msg) = self.insert_kill_ring_entry() {
+            return self.print_error(msg);
+        }
+
+        self.buffer.modified = true;
+        self.scroll_to_cursor();
+        self.draw_screen()?;
+
+        self.print_message("Yanked older kill")
+    }
+
+    // Insert the current kill-ring entry at the cursor, remembering where it starts
+    fn insert_kill_ring_entry(&mut self) -> Result<(), &'static str> {
+        self.yank_row = self.file_row;
+        self.yank_col = self.file_col;
+
+        for &byte in self.kill_ring.content() {
             if byte == b'\n' {
                 // Insert a newline
-                match self.buffer.insert_newline(self.file_row, self.file_col) {
-                    Ok(()) => {
-                        self.file_row += 1;
-                        self.file_col = 0;
-                    }
-                    Err(_) => {
-                        return self.print_error("Failed to insert newline during paste");
-                    }
-                }
+                self.buffer
+                    .insert_newline(self.file_row, self.file_col)
+                    .map_err(|e| e.describe("Failed to insert newline during paste"))?;
+                self.file_row += 1;
+                self.file_col = 0;
             } else {
                 // Insert a regular character
-                match self.buffer.insert_char(self.file_row, self.file_col, byte) {
-                    Ok(()) => {
-                        self.file_col += 1;
-                    }
-                    Err(_) => {
-                        return self.print_error("Failed to insert character during paste");
-                    }
-                }
+                self.buffer
+                    .insert_char(self.file_row, self.file_col, byte)
+                    .map_err(|e| e.describe("Failed to insert character during paste"))?;
+                self.file_col += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Kill (cut) from cursor to end of line
+    pub(in crate::editor) fn kill_line(&mut self) -> SysResult {
+        let (Some(pos), Some(line_end)) = (
+            self.position_at(self.file_row, self.file_col),
+            self.buffer.find_line_end(self.file_row),
+        ) else {
+            return self.print_message("Already at end of buffer");
+        };
+
+        // At the end of a line the newline itself is killed
+        let (end, msg) = if pos < line_end {
+            (line_end, "Killed to end of line")
+        } else if line_end < self.buffer.size {
+            (line_end + 1, "Killed newline")
+        } else {
+            return self.print_message("Already at end of buffer");
+        };
+
+        if self.record_kill(pos, end, false).is_err() {
+            return self.print_error("Failed to copy to kill-ring");
+        }
+
+        for _ in pos..end {
+            if let Err(e) = self.buffer.delete_at_position(pos) {
+                return self.print_error(e.describe("Failed to delete character"));
             }
         }
 
         // Update buffer and screen
         self.buffer.modified = true;
-        self.scroll_to_cursor();
         self.draw_screen()?;
 
-        self.print_message("Pasted from kill-ring")
+        self.print_message(msg)
+    }
+
+    // Put killed text into the kill-ring. Right after another kill the text
+    // joins the newest entry, in front of it when killing backwards.
+    fn record_kill(
+        &mut self,
+        start: usize,
+        end: usize,
+        backward: bool,
+    ) -> Result<(), KillRingError> {
+        let text = self.buffer.text_range(start, end);
+        if !self.last_key.is_some_and(Key::is_kill) {
+            self.kill_ring.copy(text)
+        } else if backward {
+            self.kill_ring.prepend(text)
+        } else {
+            self.kill_ring.append(text)
+        }
     }
 
-    // Kill (cut) from cursor to end of line
-    pub(in crate::editor) fn kill_line(&mut self) -> SysResult {
-        let line_opt = self.buffer.get_line(self.file_row);
+    // Undo the last change and move the cursor back to where it was made
+    pub(in crate::editor) fn undo(&mut self) -> SysResult {
+        match self.buffer.undo() {
+            Some((row, col)) => self.finish_history_step(row, col, "Undo"),
+            None => self.print_warning("No further undo information"),
+        }
+    }
 
-        // If no line or at empty line, just add a newline and return
-        if line_opt.is_none() || line_opt.unwrap().is_empty() {
-            return self.print_message("Line is empty");
+    // Reapply the last undone change
+    pub(in crate::editor) fn redo(&mut self) -> SysResult {
+        match self.buffer.redo() {
+            Some((row, col)) => self.finish_history_step(row, col, "Redo"),
+            None => self.print_warning("No further redo information"),
         }
+    }
 
-        let line = line_opt.unwrap();
+    fn finish_history_step(&mut self, row: usize, col: usize, msg: &str) -> SysResult {
+        self.file_row = row;
+        self.file_col = col;
+        self.preferred_col = col;
+        self.mark_active = false;
+        self.scroll_to_cursor();
+        self.draw_screen()?;
+        self.print_message(msg)
+    }
+
+    // Exchange the shown buffer with another one
+    fn swap_buffer(&mut self, other: &mut BufferState) {
+        core::mem::swap(&mut self.buffer, &mut other.buffer);
+        core::mem::swap(&mut self.filename, &mut other.filename);
+        core::mem::swap(&mut self.highlighter, &mut other.highlighter);
+        core::mem::swap(&mut self.file_row, &mut other.file_row);
+        core::mem::swap(&mut self.file_col, &mut other.file_col);
+        core::mem::swap(&mut self.preferred_col, &mut other.preferred_col);
+        core::mem::swap(&mut self.scroll_row, &mut other.scroll_row);
+        core::mem::swap(&mut self.scroll_col, &mut other.scroll_col);
+        core::mem::swap(&mut self.mark_active, &mut other.mark_active);
+        core::mem::swap(&mut self.mark_row, &mut other.mark_row);
+        core::mem::swap(&mut self.mark_col, &mut other.mark_col);
+        self.scroll_to_cursor();
+    }
 
-        // If already at end of line, delete the newline
-        if self.file_col >= line.len() {
-            if let Some(end_pos) = self.buffer.find_line_end(self.file_row) {
-                let result = self.buffer.delete_at_position(end_pos);
-                if result.is_err() {
-                    return self.print_error("Failed to delete newline");
-                }
-                return self.print_message("Killed newline");
+    // Show the buffer at `idx` in the buffer list, keeping the current one in the list
+    pub(in crate::editor) fn switch_to_buffer(&mut self, idx: usize) -> bool {
+        let Some(mut target) = self.buffers.remove(idx) else {
+            return false;
+        };
+        self.swap_buffer(&mut target);
+
+        // The removed entry left room for the previous buffer
+        self.buffers.push_front(target);
+        true
+    }
+
+    // Exchange the shown buffer with a list entry without changing the list order
+    pub(in crate::editor) fn exchange_buffer(&mut self, idx: usize) -> bool {
+        let Some(mut entry) = self.buffers.remove(idx) else {
+            return false;
+        };
+        self.swap_buffer(&mut entry);
+        self.buffers.insert(idx, entry);
+        true
+    }
+
+    // Auto-save every buffer with new edits; false if any of them failed
+    pub(in crate::editor) fn auto_save_buffers(&mut self) -> bool {
+        let mut ok = self.buffer.auto_save(&self.filename).is_ok();
+        for idx in 0..self.buffers.len() {
+            if let Some(entry) = self.buffers.get_mut(idx) {
+                ok &= entry.buffer.auto_save(&entry.filename).is_ok();
             }
-            return self.print_message("Already at end of buffer");
         }
+        ok
+    }
 
-        // Otherwise, copy text from cursor to end of line into kill-ring
-        let text_to_kill = &line[self.file_col..];
-        match self.kill_ring.copy(text_to_kill) {
-            Ok(()) => {
-                // Successfully copied to kill-ring, now delete from buffer
-                let chars_to_delete = line.len() - self.file_col;
-                for _ in 0..chars_to_delete {
-                    let result = self.buffer.delete_char(self.file_row, self.file_col);
-                    if result.is_err() {
-                        return self.print_error("Failed to delete character");
-                    }
-                }
+    // Give the shown buffer a new file name
+    pub(in crate::editor) fn set_filename(&mut self, filename: &[u8]) {
+        let name = name_of(filename);
+        let len = name.len().min(MAX_PATH - 1);
+        self.filename.fill(0);
+        self.filename[..len].copy_from_slice(&name[..len]);
+        self.highlighter.detect_file_type(&self.filename);
+    }
 
-                // Update buffer and screen
-                self.buffer.modified = true;
-                self.draw_screen()?;
+    // Show a newly opened file, keeping the current buffer in the list
+    pub(in crate::editor) fn visit_buffer(&mut self, buffer: FileBuffer, filename: &[u8]) -> bool {
+        if !self.buffers.reserve() {
+            return false;
+        }
+
+        let mut previous = BufferState::new(buffer, filename);
+        self.swap_buffer(&mut previous);
+        self.buffers.push_front(previous);
+        true
+    }
+
+    // Show another buffer in place of the current one, which is dropped
+    pub(in crate::editor) fn replace_buffer(&mut self, mut next: BufferState) {
+        self.swap_buffer(&mut next);
+    }
 
-                self.print_message("Killed to end of line")
+    // Extend a buffer name prefix as far as all matching buffer names agree
+    pub(in crate::editor) fn complete_buffer_name(
+        &self,
+        input: &mut [u8; MAX_PATH],
+        len: usize,
+    ) -> usize {
+        let names = core::iter::once(name_of(&self.filename)).chain(
+            (0..self.buffers.len()).filter_map(|idx| self.buffers.get(idx).map(BufferState::name)),
+        );
+        complete_prefix(input, len, names)
+    }
+
+    // Draw the list of buffers over the text area with one entry highlighted
+    pub(in crate::editor) fn draw_buffer_list(&mut self, selected: usize) -> SysResult {
+        const SIZE_COL: usize = 40;
+
+        self.screen
+            .begin(self.editing_rows(), self.winsize.cols as usize)?;
+        let bold = Glyph {
+            bold: true,
+            ..Glyph::BLANK
+        };
+        self.screen.put_text(0, 0, b"CM Buffer", bold);
+        self.screen.put_text(0, SIZE_COL, b"Size", bold);
+
+        for idx in 0..=self.buffers.len() {
+            let (name, buffer) = if idx == 0 {
+                (name_of(&self.filename), &self.buffer)
+            } else {
+                match self.buffers.get(idx - 1) {
+                    Some(entry) => (entry.name(), &entry.buffer),
+                    None => break,
+                }
+            };
+
+            let row = idx + 1;
+            let attributes = if idx == selected {
+                Glyph {
+                    fg: 0,
+                    bg: 7,
+                    ..Glyph::BLANK
+                }
+            } else {
+                Glyph::BLANK
+            };
+            for col in 0..self.winsize.cols as usize {
+                self.screen.put(row, col, attributes);
             }
-            Err(KillRingError::BufferTooLarge) => self.print_error("Text too large for kill-ring"),
-            Err(_) => self.print_error("Failed to copy to kill-ring"),
+
+            // Flags for the current and modified buffers, then name and size
+            let current = if idx == 0 { b'.' } else { b' ' };
+            let modified = if buffer.is_modified() { b'*' } else { b' ' };
+            self.screen
+                .put_text(row, 0, &[current, modified], attributes);
+            let name: &[u8] = if name.is_empty() { b"*scratch*" } else { name };
+            self.screen.put_text(row, 3, name, attributes);
+            let mut digits = [0u8; 20];
+            let len = write_usize_to_buf(&mut digits, buffer.size);
+            self.screen
+                .put_text(row, SIZE_COL, &digits[..len], attributes);
         }
+
+        self.screen.flush(selected + 1, 0)
     }
 
     // Highlight selection during drawing if mark is active
@@ -1957,4 +2297,122 @@ pub mod tests {
         // Clean up
         disable_test_mode();
     }
+
+    #[test]
+    fn test_selection_bounds() {
+        let mut winsize = Winsize::new();
+        winsize.rows = 10;
+        winsize.cols = 40;
+        let mut state = EditorState::new(winsize, &[0; MAX_PATH]);
+        state.buffer = create_test_file_buffer(b"one\ntwo\nthree\n");
+        assert_eq!(state.selection_bounds(), None, "No mark, no selection");
+
+        // The mark after the cursor still gives ordered offsets
+        state.mark_active = true;
+        state.mark_row = 2;
+        state.mark_col = 2;
+        state.file_row = 0;
+        state.file_col = 1;
+        assert_eq!(state.selection_bounds(), Some((1, 10)));
+        assert_eq!(state.buffer.text_range(1, 10), b"ne\ntwo\nth");
+    }
+
+    #[test]
+    fn test_insert_block() {
+        let mut winsize = Winsize::new();
+        winsize.rows = 10;
+        winsize.cols = 40;
+        let mut state = EditorState::new(winsize, &[0; MAX_PATH]);
+        state.buffer = create_test_file_buffer(b"head\ntail\n");
+        state.file_row = 1;
+
+        assert!(state.insert_block(b"// License\n// text\n").is_ok());
+        assert_eq!(state.buffer.get_line(1), Some(&b"// License"[..]));
+        assert_eq!(state.buffer.get_line(3), Some(&b"tail"[..]));
+        assert_eq!((state.file_row, state.file_col), (1, 0));
+        assert_eq!(
+            state.selection_bounds(),
+            Some((5, 24)),
+            "Inserted text is selected"
+        );
+
+        // The whole block goes away with a single undo
+        state.buffer.undo();
+        assert_eq!(state.buffer.count_lines(), 3);
+        assert_eq!(state.buffer.get_line(1), Some(&b"tail"[..]));
+    }
+
+    #[test]
+    fn test_replace_text() {
+        let mut winsize = Winsize::new();
+        winsize.rows = 10;
+        winsize.cols = 40;
+        let mut state = EditorState::new(winsize, &[0; MAX_PATH]);
+        state.buffer = create_test_file_buffer(b"let foo = 1;\nfoo += foo;\n");
+
+        state.buffer.undo_boundary(0, 0);
+        assert!(state.replace_text(0, 4, 3, b"count").is_ok());
+        assert_eq!((state.file_row, state.file_col), (0, 9));
+        assert!(state.replace_text(1, 0, 3, b"count").is_ok());
+        assert!(state.replace_text(1, 9, 3, b"n").is_ok());
+        assert_eq!(state.buffer.get_line(0), Some(&b"let count = 1;"[..]));
+        assert_eq!(state.buffer.get_line(1), Some(&b"count += n;"[..]));
+
+        // A replacement session is a single undo step
+        state.buffer.undo();
+        assert_eq!(state.buffer.get_line(0), Some(&b"let foo = 1;"[..]));
+        assert_eq!(state.buffer.get_line(1), Some(&b"foo += foo;"[..]));
+        assert!(!state.buffer.is_modified());
+
+        assert!(state.replace_text(1, 9, 50, b"x").is_err(), "Past the end");
+    }
+
+    #[test]
+    fn test_buffer_switching() {
+        use crate::terminal::tests::{disable_test_mode, enable_test_mode};
+        enable_test_mode();
+
+        let mut winsize = Winsize::new();
+        winsize.rows = 10;
+        winsize.cols = 40;
+        let mut first_name = [0u8; MAX_PATH];
+        first_name[..6].copy_from_slice(b"main.c");
+        let mut state = EditorState::new(winsize, &first_name);
+        state.buffer = create_test_file_buffer(b"int x;\nint y;\n");
+        state.file_row = 1;
+        state.file_col = 3;
+        state.set_mark().unwrap();
+
+        // Opening another file keeps the first one in the list
+        assert!(state.visit_buffer(create_test_file_buffer(b"fn f() {}\n"), b"lib.rs\0"));
+        assert_eq!(name_of(&state.filename), b"lib.rs");
+        assert_eq!((state.file_row, state.file_col), (0, 0));
+        assert!(!state.mark_active, "New buffer has no mark");
+        assert_eq!(state.buffers.find(b"main.c"), Some(0));
+
+        // Switching back restores the cursor and mark of the first buffer
+        assert!(state.switch_to_buffer(0));
+        assert_eq!(name_of(&state.filename), b"main.c");
+        assert_eq!(state.buffer.get_line(1), Some(&b"int y;"[..]));
+        assert_eq!((state.file_row, state.file_col), (1, 3));
+        assert!(state.mark_active, "Mark is kept per buffer");
+        assert_eq!(state.buffers.find(b"lib.rs"), Some(0));
+        assert!(!state.switch_to_buffer(1), "Only one other buffer exists");
+
+        // Completion extends a prefix as far as the names agree
+        let mut input = [0u8; MAX_PATH];
+        input[0] = b'l';
+        assert_eq!(state.complete_buffer_name(&mut input, 1), 6);
+        assert_eq!(&input[..6], b"lib.rs");
+        input[0] = b'x';
+        assert_eq!(state.complete_buffer_name(&mut input, 1), 1);
+
+        // Replacing the shown buffer drops it
+        let next = state.buffers.remove(0).unwrap();
+        state.replace_buffer(next);
+        assert_eq!(name_of(&state.filename), b"lib.rs");
+        assert_eq!(state.buffers.len(), 0);
+
+        disable_test_mode();
+    }
 }
@@ -1,65 +1,181 @@
-use crate::syscall::{SysResult, write_unchecked};
-
-#[derive(Debug)]
+use super::auto_save::{auto_save_path, remove_auto_save};
+use super::backup::{BackupMode, Original};
+use super::buffer_list::name_of;
+use super::dired::is_listing;
+use super::line_index::LineIndex;
+use super::undo_log::{UndoLog, UndoRecord};
+use crate::syscall::{
+    ELOOP, ENAMETOOLONG, ENOENT, EROFS, MAX_PATH, S_IFLNK, S_IFMT, Stat, SysResult, fchmod, fchown,
+    fstat, lstat, readlink, write_unchecked,
+};
+use core::cell::Cell;
+
+// Symlinks followed when saving before giving up, as the kernel does
+const MAX_SYMLINKS: usize = 40;
+
+// Temp file names tried when saving before giving up
+const TEMP_ATTEMPTS: u8 = 99;
+
+#[derive(Debug, Clone, Copy)]
 pub(in crate::editor) enum FileBufferError {
     BufferFull,
     InvalidOperation,
+    ReadOnly,
+}
+
+impl FileBufferError {
+    // What to tell the user, with `failed` standing for any other failure
+    pub(in crate::editor) fn describe(self, failed: &'static str) -> &'static str {
+        match self {
+            FileBufferError::ReadOnly => "Buffer is read-only",
+            FileBufferError::BufferFull | FileBufferError::InvalidOperation => failed,
+        }
+    }
+}
+
+// What tells versions of a file on disk apart
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(in crate::editor) struct FileStamp {
+    dev: u64,
+    ino: u64,
+    size: i64,
+    mtime: i64,
+    mtime_nsec: i64,
+}
+
+impl FileStamp {
+    fn from_stat(stat: &Stat) -> Self {
+        Self {
+            dev: stat.dev,
+            ino: stat.ino,
+            size: stat.size,
+            mtime: stat.mtime,
+            mtime_nsec: stat.mtime_nsec,
+        }
+    }
+
+    pub(in crate::editor) fn of_fd(fd: usize) -> Option<Self> {
+        let mut stat = Stat::new();
+        fstat(fd, &mut stat).ok()?;
+        Some(Self::from_stat(&stat))
+    }
+
+    pub(in crate::editor) fn of_path(path: &[u8]) -> Option<Self> {
+        let mut stat = Stat::new();
+        crate::syscall::stat(path, &mut stat).ok()?;
+        Some(Self::from_stat(&stat))
+    }
 }
 
 pub(in crate::editor) struct FileBuffer {
-    pub(in crate::editor) content: *mut u8, // Pointer to file content
+    pub(in crate::editor) content: *mut u8, // Gap buffer: text, free gap, then `tail` bytes of text
     pub(in crate::editor) size: usize,      // Current size of the file
     pub(in crate::editor) capacity: usize,  // Maximum capacity of the buffer
+    pub(in crate::editor) tail: Cell<usize>, // Number of text bytes stored after the gap
     pub(in crate::editor) modified: bool,   // Whether the file has been modified
+    pub(in crate::editor) lines: LineIndex, // Cached line start offsets
+    pub(in crate::editor) undo: UndoLog,    // Edit history for undo/redo
+    pub(in crate::editor) changed: Cell<usize>, // Lowest offset edited since the highlighter last synced
+    pub(in crate::editor) auto_save_pending: bool, // Edits not yet in the auto-save file
+    pub(in crate::editor) stamp: Option<FileStamp>, // Version on disk when last read or written
+    pub(in crate::editor) original: Original,   // Whether the first save still backs the file up
+    pub(in crate::editor) read_only: bool,      // Refuses edits and saves, like directory listings
 }
 
 impl FileBuffer {
     // Insert a character at a specific position
     fn insert_at_position(&mut self, pos: usize, ch: u8) -> Result<(), FileBufferError> {
-        if self.size >= self.capacity {
-            // Instead of returning an error, resize the buffer
-            self.resize_buffer()?;
-        }
+        self.check_writable()?;
+        self.splice_insert(pos, &[ch])?;
 
-        if pos > self.size {
-            return Err(FileBufferError::InvalidOperation);
+        // Update modified status
+        self.modified = true;
+        self.undo.record_insert(pos, &[ch]);
+
+        Ok(())
+    }
+
+    // Logical offset where the gap starts
+    fn gap_start(&self) -> usize {
+        self.size - self.tail.get()
+    }
+
+    // Number of free bytes in the gap
+    fn gap_len(&self) -> usize {
+        self.capacity - self.size
+    }
+
+    // Read a single byte at a logical position
+    pub(in crate::editor) fn byte_at(&self, pos: usize) -> u8 {
+        let physical = if pos < self.gap_start() {
+            pos
+        } else {
+            pos + self.gap_len()
+        };
+        unsafe { *self.content.add(physical) }
+    }
+
+    // Move the gap so that it starts at the given logical position. Only the
+    // bytes between the old and the new gap position are moved.
+    fn move_gap(&self, pos: usize) {
+        let gap_start = self.gap_start();
+        let gap_len = self.gap_len();
+        if pos == gap_start || gap_len == 0 {
+            self.tail.set(self.size - pos);
+            return;
         }
 
-        // Shift content to make space for the new character
         unsafe {
-            if pos < self.size {
-                // Make space by moving everything after the insertion point
-                for i in (pos..self.size).rev() {
-                    *self.content.add(i + 1) = *self.content.add(i);
-                }
+            if pos < gap_start {
+                core::ptr::copy(
+                    self.content.add(pos),
+                    self.content.add(pos + gap_len),
+                    gap_start - pos,
+                );
+            } else {
+                core::ptr::copy(
+                    self.content.add(gap_start + gap_len),
+                    self.content.add(gap_start),
+                    pos - gap_start,
+                );
             }
-
-            // Insert the character
-            *self.content.add(pos) = ch;
         }
+        self.tail.set(self.size - pos);
+    }
 
-        // Update size and modified status
-        self.size += 1;
-        self.modified = true;
+    // Content before and after the gap
+    fn segments(&self) -> (&[u8], &[u8]) {
+        if self.content.is_null() {
+            return (&[], &[]);
+        }
 
-        Ok(())
+        let gap_start = self.gap_start();
+        unsafe {
+            (
+                core::slice::from_raw_parts(self.content, gap_start),
+                core::slice::from_raw_parts(
+                    self.content.add(gap_start + self.gap_len()),
+                    self.tail.get(),
+                ),
+            )
+        }
     }
 
-    fn calculate_new_capacity(&self) -> usize {
+    fn calculate_new_capacity(&self, required: usize) -> usize {
         const PAGE_SIZE: usize = 4096;
         const PAGE_MASK: usize = PAGE_SIZE - 1;
 
-        if self.capacity == 0 {
+        // Grow geometrically so that a run of inserts costs O(1) amortized
+        let mut new_capacity = if self.capacity == 0 {
             PAGE_SIZE
         } else {
-            let aligned = (self.capacity + PAGE_MASK) & !PAGE_MASK;
-            aligned
-                + if self.capacity & PAGE_MASK == 0 {
-                    PAGE_SIZE
-                } else {
-                    0
-                }
+            self.capacity * 2
+        };
+        while new_capacity < required {
+            new_capacity *= 2;
         }
+
+        (new_capacity + PAGE_MASK) & !PAGE_MASK
     }
 
     fn allocate_new_buffer(new_capacity: usize) -> Result<*mut u8, FileBufferError> {
@@ -70,13 +186,16 @@ impl FileBuffer {
             .map_err(|_| FileBufferError::BufferFull)
     }
 
-    fn copy_content_to_new_buffer(&self, new_buffer: *mut u8) {
+    // Copy both halves of the text, leaving the gap at the same position
+    fn copy_content_to_new_buffer(&self, new_buffer: *mut u8, new_capacity: usize) {
+        let (before, after) = self.segments();
         unsafe {
-            if !self.content.is_null() && self.size > 0 {
-                for i in 0..self.size {
-                    *new_buffer.add(i) = *self.content.add(i);
-                }
-            }
+            core::ptr::copy_nonoverlapping(before.as_ptr(), new_buffer, before.len());
+            core::ptr::copy_nonoverlapping(
+                after.as_ptr(),
+                new_buffer.add(new_capacity - after.len()),
+                after.len(),
+            );
         }
     }
 
@@ -86,11 +205,12 @@ impl FileBuffer {
         }
     }
 
-    fn resize_buffer(&mut self) -> Result<(), FileBufferError> {
-        let new_capacity = self.calculate_new_capacity();
+    // Grow the buffer so that at least `additional` more bytes fit
+    fn resize_buffer(&mut self, additional: usize) -> Result<(), FileBufferError> {
+        let new_capacity = self.calculate_new_capacity(self.size + additional);
         let new_buffer = Self::allocate_new_buffer(new_capacity)?;
 
-        self.copy_content_to_new_buffer(new_buffer);
+        self.copy_content_to_new_buffer(new_buffer, new_capacity);
         self.free_old_buffer();
 
         self.content = new_buffer;
@@ -99,25 +219,57 @@ impl FileBuffer {
         Ok(())
     }
 
+    // Insert a block of text as a single edit
+    pub(in crate::editor) fn insert_text(
+        &mut self,
+        pos: usize,
+        bytes: &[u8],
+    ) -> Result<(), FileBufferError> {
+        self.check_writable()?;
+        self.splice_insert(pos, bytes)?;
+
+        self.modified = true;
+        self.undo.record_insert(pos, bytes);
+        Ok(())
+    }
+
+    // Delete a block of text as a single edit
+    pub(in crate::editor) fn delete_text(
+        &mut self,
+        pos: usize,
+        len: usize,
+    ) -> Result<(), FileBufferError> {
+        self.check_writable()?;
+        if pos + len > self.size {
+            return Err(FileBufferError::InvalidOperation);
+        }
+
+        // The undo log copies the text before it goes away
+        let text = self.text_range(pos, pos + len);
+        let text = unsafe { core::slice::from_raw_parts(text.as_ptr(), text.len()) };
+        self.undo.record_delete(pos, text);
+        self.splice_delete(pos, len);
+
+        self.modified = true;
+        Ok(())
+    }
+
     // Delete a character at a specific position
     pub(in crate::editor) fn delete_at_position(
         &mut self,
         pos: usize,
     ) -> Result<(), FileBufferError> {
+        self.check_writable()?;
         if self.size == 0 || pos >= self.size {
             return Err(FileBufferError::InvalidOperation);
         }
 
-        // Shift content to fill the deleted character's space
-        unsafe {
-            for i in pos..(self.size - 1) {
-                *self.content.add(i) = *self.content.add(i + 1);
-            }
-        }
+        let ch = self.byte_at(pos);
+        self.splice_delete(pos, 1);
 
-        // Update size and modified status
-        self.size -= 1;
+        // Update modified status
         self.modified = true;
+        self.undo.record_delete(pos, &[ch]);
 
         Ok(())
     }
@@ -208,130 +360,328 @@ impl FileBuffer {
         self.insert_char(row, col, b'\n')
     }
 
+    // Every edit and save checks here, whichever command asked for it
+    fn check_writable(&self) -> Result<(), FileBufferError> {
+        if self.read_only {
+            Err(FileBufferError::ReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+
     // Check if the file has been modified
     pub(in crate::editor) fn is_modified(&self) -> bool {
         self.modified
     }
 
-    fn write_all_content(&self, fd: usize) -> SysResult {
-        let mut bytes_written = 0;
-        while bytes_written < self.size {
-            let remaining = self.size - bytes_written;
-            let result =
-                unsafe { write_unchecked(fd, self.content.add(bytes_written), remaining) }?;
+    // Whether dropping the buffer would lose edits; an untouched new file would not
+    pub(in crate::editor) fn has_unsaved_changes(&self) -> bool {
+        self.modified && !self.undo.is_at_saved()
+    }
 
-            bytes_written += result;
+    // Start a new undo step, remembering the cursor to restore on undo
+    pub(in crate::editor) fn undo_boundary(&mut self, row: usize, col: usize) {
+        self.undo.boundary(row, col);
+    }
 
-            if result == 0 {
-                break;
+    // Revert the most recent undo step and return the cursor position before it
+    pub(in crate::editor) fn undo(&mut self) -> Option<(usize, usize)> {
+        self.check_writable().ok()?;
+        while let Some(record) = self.undo.step_back() {
+            match record {
+                UndoRecord::Boundary { row, col } => {
+                    self.modified = !self.undo.is_at_saved();
+                    return Some((row, col));
+                }
+                UndoRecord::Insert { pos, len, .. } => self.splice_delete(pos, len),
+                UndoRecord::Delete { pos, data, len } => {
+                    let bytes = unsafe { core::slice::from_raw_parts(data, len) };
+                    if self.splice_insert(pos, bytes).is_err() {
+                        return None;
+                    }
+                }
             }
         }
-        Ok(bytes_written)
+        None
     }
 
-    pub(in crate::editor) fn save_to_file(&mut self, path: &[u8]) -> SysResult {
-        use crate::syscall::{O_CREAT, O_TRUNC, O_WRONLY, close, open};
+    // Reapply the next undone step and return the cursor position after it
+    pub(in crate::editor) fn redo(&mut self) -> Option<(usize, usize)> {
+        self.check_writable().ok()?;
+        let Some(UndoRecord::Boundary { row, col }) = self.undo.step_forward(true) else {
+            return None;
+        };
 
-        let fd = open(path, O_WRONLY | O_CREAT | O_TRUNC)?;
-        let bytes_written = self.write_all_content(fd)?;
-        close(fd)?;
+        let mut cursor = None;
+        while let Some(record) = self.undo.step_forward(false) {
+            match record {
+                UndoRecord::Boundary { .. } => break,
+                UndoRecord::Insert { pos, data, len } => {
+                    let bytes = unsafe { core::slice::from_raw_parts(data, len) };
+                    if self.splice_insert(pos, bytes).is_err() {
+                        return None;
+                    }
+                    cursor = Some(pos + len);
+                }
+                UndoRecord::Delete { pos, len, .. } => {
+                    self.splice_delete(pos, len);
+                    cursor = Some(pos);
+                }
+            }
+        }
 
-        self.modified = false;
-        Ok(bytes_written)
+        self.modified = !self.undo.is_at_saved();
+        Some(cursor.map_or((row, col), |pos| self.position_to_row_col(pos)))
     }
 
-    pub(in crate::editor) fn cleanup(&self) {
-        self.free_old_buffer();
+    // Convert a byte offset to a (row, column) pair
+    pub(in crate::editor) fn position_to_row_col(&self, pos: usize) -> (usize, usize) {
+        let pos = pos.min(self.size);
+        let Some(lines) = self.line_index() else {
+            return (0, pos);
+        };
+
+        let row = lines.line_of(pos, self.size);
+        let line_start = lines.line_start(row, self.size).unwrap_or(0);
+        (row, pos - line_start)
     }
 
-    // Count the number of lines in the file
-    pub(in crate::editor) fn count_lines(&self) -> usize {
-        // Early return for null buffer
+    // Line index, built from the content on first use
+    fn line_index(&self) -> Option<&LineIndex> {
         if self.content.is_null() {
-            return 0;
+            return None;
         }
 
-        // Empty buffer has one line for editing purposes
-        if self.size == 0 {
-            return 1;
+        if !self.lines.is_valid() {
+            let (before, after) = self.segments();
+            self.lines.rebuild(before, after);
         }
 
-        // Create a slice view of the buffer content for more efficient iteration
-        let buffer_slice = unsafe { core::slice::from_raw_parts(self.content, self.size) };
-
-        // Count newlines and add 1 (first line doesn't start with a newline)
-        let newline_count = buffer_slice
-            .iter()
-            .take_while(|&&byte| byte != 0) // Stop at null byte if present
-            .filter(|&&byte| byte == b'\n')
-            .count();
+        self.lines.is_valid().then_some(&self.lines)
+    }
 
-        newline_count + 1
+    // Lowest offset changed since the last call, if any
+    pub(in crate::editor) fn take_changed(&self) -> Option<usize> {
+        let pos = self.changed.replace(usize::MAX);
+        (pos != usize::MAX).then_some(pos)
     }
 
-    pub(in crate::editor) fn find_line_start(&self, line_idx: usize) -> Option<usize> {
-        // For null buffer
-        if self.content.is_null() {
-            return None;
+    // Insert bytes at a position without recording an undo step
+    fn splice_insert(&mut self, pos: usize, bytes: &[u8]) -> Result<(), FileBufferError> {
+        if pos > self.size {
+            return Err(FileBufferError::InvalidOperation);
         }
 
-        // Special case for first line (line 0)
-        if line_idx == 0 {
-            return Some(0);
+        if bytes.len() > self.gap_len() {
+            self.resize_buffer(bytes.len())?;
         }
 
-        // Special case for empty buffer - only line 0 exists
-        if self.size == 0 {
-            return None;
+        self.move_gap(pos);
+        unsafe {
+            core::ptr::copy_nonoverlapping(bytes.as_ptr(), self.content.add(pos), bytes.len());
         }
+        self.lines.insert(pos, bytes, self.size);
+        self.changed.set(self.changed.get().min(pos));
+        self.auto_save_pending = true;
+
+        // The tail is unchanged, so the gap start moves past the new bytes
+        self.size += bytes.len();
+        Ok(())
+    }
 
-        // Find line by counting newlines
-        let mut newlines_found = 0;
-        let mut pos = 0;
+    // Remove bytes at a position without recording an undo step
+    fn splice_delete(&mut self, pos: usize, len: usize) {
+        let len = len.min(self.size.saturating_sub(pos));
+        self.move_gap(pos);
+        self.lines.delete(pos, len, self.size);
+        self.changed.set(self.changed.get().min(pos));
+        self.auto_save_pending = true;
+
+        // Removed bytes right after the gap simply become part of it
+        self.tail.set(self.tail.get() - len);
+        self.size -= len;
+    }
 
-        // Scan the buffer for newline characters
-        unsafe {
-            while pos < self.size {
-                if *self.content.add(pos) == b'\n' {
-                    newlines_found += 1;
-                    if newlines_found == line_idx {
-                        return Some(pos + 1); // Start of line after newline
-                    }
+    // Write the whole text; a write that stops short is an error
+    fn write_all_content(&self, fd: usize) -> SysResult {
+        use crate::syscall::EIO;
+
+        let (before, after) = self.segments();
+        let mut bytes_written = 0;
+
+        for segment in [before, after] {
+            let mut offset = 0;
+            while offset < segment.len() {
+                let remaining = segment.len() - offset;
+                let result =
+                    unsafe { write_unchecked(fd, segment.as_ptr().add(offset), remaining) }?;
+                if result == 0 {
+                    return Err(EIO);
                 }
-                pos += 1;
+
+                offset += result;
+                bytes_written += result;
             }
         }
+        Ok(bytes_written)
+    }
 
-        // Line not found
-        None
+    // Write the text into a fresh file and make it durable
+    fn write_synced(&self, fd: usize) -> SysResult {
+        use crate::syscall::{EIO, fsync};
+
+        let bytes_written = self.write_all_content(fd)?;
+        fsync(fd)?;
+
+        let mut stat = Stat::new();
+        fstat(fd, &mut stat)?;
+        if usize::try_from(stat.size).ok() != Some(self.size) {
+            return Err(EIO);
+        }
+        Ok(bytes_written)
     }
 
-    // Find the end position of a specific line (exclusive of newline)
-    pub(in crate::editor) fn find_line_end(&self, line_idx: usize) -> Option<usize> {
-        // Get start position of the line
-        let start = self.find_line_start(line_idx)?;
+    // The file is replaced atomically: the text goes into a sibling temp
+    // file which is renamed over the original once it is safely on disk.
+    // Symlinks are followed so the link stays and its target gets written.
+    pub(in crate::editor) fn save_to_file(&mut self, path: &[u8], backup: BackupMode) -> SysResult {
+        if self.read_only {
+            return Err(EROFS);
+        }
+        let mut target = [0u8; MAX_PATH];
+        let existing = resolve_symlinks(path, &mut target)?;
+        self.original
+            .write_backup(&target, existing.as_ref(), backup);
+
+        let bytes_written = self.replace_file(&target, existing.as_ref())?;
+
+        // Edits are safe in the real file now
+        remove_auto_save(path);
+        self.auto_save_pending = false;
+        self.stamp = FileStamp::of_path(&target);
+
+        self.modified = false;
+        self.undo.mark_saved();
+        Ok(bytes_written)
+    }
+
+    // Write the text to path as atomically as a save, but as a copy: the
+    // buffer keeps its own file, auto-save file and unsaved state
+    pub(in crate::editor) fn write_copy(&self, path: &[u8]) -> SysResult {
+        let mut target = [0u8; MAX_PATH];
+        let existing = resolve_symlinks(path, &mut target)?;
+        self.replace_file(&target, existing.as_ref())
+    }
 
-        // Special case for empty buffer
-        if self.size == 0 && line_idx == 0 {
-            return Some(0);
+    // Write the text to a new temp file next to target and rename it over
+    // target, then make the rename durable
+    fn replace_file(&self, target: &[u8], existing: Option<&Stat>) -> SysResult {
+        use crate::syscall::{close, rename, unlink};
+
+        let mut temp = [0u8; MAX_PATH];
+        let fd = create_temp_file(target, &mut temp)?;
+        let written = copy_attributes(fd, existing).and_then(|()| self.write_synced(fd));
+        let closed = close(fd);
+        let result = written.and_then(|bytes_written| {
+            closed?;
+            rename(&temp, target)?;
+            Ok(bytes_written)
+        });
+        if result.is_err() {
+            // The temp file was created by this call, so it is ours to remove
+            let _ = unlink(&temp);
         }
+        let bytes_written = result?;
 
-        // Scan forward from start position until we find a newline or end of buffer
-        unsafe {
-            let mut pos = start;
-            while pos < self.size {
-                let byte = *self.content.add(pos);
+        // The rename itself only becomes durable with the directory
+        sync_parent_dir(target)?;
+        Ok(bytes_written)
+    }
 
-                if byte == b'\n' || byte == 0 {
-                    return Some(pos);
-                }
+    // Whether another program wrote the file since it was read or saved.
+    // A file that went away is not a conflict: saving just creates it again.
+    pub(in crate::editor) fn changed_on_disk(&self, path: &[u8]) -> bool {
+        // Directory listings are generated, not read from the file
+        if name_of(path).is_empty() || is_listing(path) {
+            return false;
+        }
+        FileStamp::of_path(path).is_some_and(|now| self.stamp != Some(now))
+    }
 
-                pos += 1;
-            }
+    // Add text at the end without an undo step, for text the editor generates
+    pub(in crate::editor) fn append_text(&mut self, bytes: &[u8]) -> Result<(), FileBufferError> {
+        self.insert_generated(self.size, bytes)
+    }
+
+    // Insert text the editor generates: no undo step, and nothing to auto-save
+    pub(in crate::editor) fn insert_generated(
+        &mut self,
+        pos: usize,
+        bytes: &[u8],
+    ) -> Result<(), FileBufferError> {
+        let pending = self.auto_save_pending;
+        self.splice_insert(pos, bytes)?;
+        self.auto_save_pending = pending;
+        Ok(())
+    }
+
+    // Remove all text without an undo step, for scratch text the editor reuses
+    pub(in crate::editor) fn clear_generated(&mut self) {
+        let pending = self.auto_save_pending;
+        self.splice_delete(0, self.size);
+        self.auto_save_pending = pending;
+    }
+
+    // Write edits made since the last auto-save to #name#; unnamed buffers
+    // are not auto-saved
+    pub(in crate::editor) fn auto_save(&mut self, path: &[u8]) -> SysResult {
+        use crate::syscall::{O_CREAT, O_NOFOLLOW, O_TRUNC, O_WRONLY, close, open_mode};
+
+        if !self.auto_save_pending || self.read_only || name_of(path).is_empty() {
+            return Ok(0);
+        }
 
-            // If we reached end of buffer without finding a newline
-            Some(self.size)
+        let mut auto_save = [0u8; MAX_PATH];
+        if !auto_save_path(path, &mut auto_save) {
+            return Err(ENAMETOOLONG);
         }
+
+        // Unsaved text may be private, so only the owner may read it
+        let fd = open_mode(&auto_save, O_WRONLY | O_CREAT | O_TRUNC | O_NOFOLLOW, 0o600)?;
+        let written = self.write_all_content(fd);
+        let closed = close(fd);
+        let bytes_written = written?;
+        closed?;
+
+        self.auto_save_pending = false;
+        Ok(bytes_written)
+    }
+
+    pub(in crate::editor) fn cleanup(&self) {
+        self.free_old_buffer();
+    }
+
+    // Count the number of lines in the file
+    pub(in crate::editor) fn count_lines(&self) -> usize {
+        // Empty buffer still has one line for editing purposes
+        self.line_index().map_or(0, LineIndex::line_count)
+    }
+
+    pub(in crate::editor) fn find_line_start(&self, line_idx: usize) -> Option<usize> {
+        self.line_index()?.line_start(line_idx, self.size)
+    }
+
+    // Find the end position of a specific line (exclusive of newline)
+    pub(in crate::editor) fn find_line_end(&self, line_idx: usize) -> Option<usize> {
+        let lines = self.line_index()?;
+        lines.line_start(line_idx, self.size)?;
+
+        // A line ends right before the newline preceding the next one
+        Some(
+            lines
+                .line_start(line_idx + 1, self.size)
+                .map_or(self.size, |next| next - 1),
+        )
     }
 
     // Get a specific line from the buffer
@@ -359,13 +709,46 @@ impl FileBuffer {
             return None;
         }
 
+        // A line split by the gap is made contiguous by moving the gap to its
+        // end. Only bytes of this line move, so slices of other lines stay valid.
+        if start < self.gap_start() && self.gap_start() < end {
+            self.move_gap(end);
+        }
+
+        let physical = if start < self.gap_start() {
+            start
+        } else {
+            start + self.gap_len()
+        };
+
         // Create a slice directly from pointers
         unsafe {
             let len = end - start;
-            Some(core::slice::from_raw_parts(self.content.add(start), len))
+            Some(core::slice::from_raw_parts(self.content.add(physical), len))
         }
     }
 
+    // Get the text between two positions as one contiguous slice
+    pub(in crate::editor) fn text_range(&self, start: usize, end: usize) -> &[u8] {
+        let end = end.min(self.size);
+        let start = start.min(end);
+        if self.content.is_null() {
+            return &[];
+        }
+
+        // Like get_line, only a range split by the gap moves it
+        if start < self.gap_start() && self.gap_start() < end {
+            self.move_gap(end);
+        }
+
+        let physical = if start < self.gap_start() {
+            start
+        } else {
+            start + self.gap_len()
+        };
+        unsafe { core::slice::from_raw_parts(self.content.add(physical), end - start) }
+    }
+
     // Get a line's length, treating tabs as the specified number of spaces
     pub(in crate::editor) fn line_length(&self, line_idx: usize, tab_size: usize) -> usize {
         match self.get_line(line_idx) {
@@ -389,6 +772,145 @@ impl FileBuffer {
     }
 }
 
+// Name of a file next to path, with prefix and suffix around the file name
+pub(in crate::editor) fn sibling_path(
+    path: &[u8],
+    prefix: &[u8],
+    suffix: &[u8],
+    out: &mut [u8; MAX_PATH],
+) -> bool {
+    let path = name_of(path);
+    let base = path
+        .iter()
+        .rposition(|&b| b == b'/')
+        .map_or(0, |slash| slash + 1);
+    let (dir, name) = path.split_at(base);
+
+    let len = dir.len() + prefix.len() + name.len() + suffix.len();
+    if len >= MAX_PATH {
+        return false;
+    }
+
+    let mut pos = 0;
+    for part in [dir, prefix, name, suffix] {
+        out[pos..pos + part.len()].copy_from_slice(part);
+        pos += part.len();
+    }
+    out[pos] = 0;
+    true
+}
+
+// Follow symlinks from path to the file they end at, returning its status
+// if it exists already
+fn resolve_symlinks(path: &[u8], target: &mut [u8; MAX_PATH]) -> Result<Option<Stat>, usize> {
+    let path = name_of(path);
+    if path.len() >= MAX_PATH {
+        return Err(ENAMETOOLONG);
+    }
+    target[..path.len()].copy_from_slice(path);
+    target[path.len()] = 0;
+
+    for _ in 0..MAX_SYMLINKS {
+        let mut stat = Stat::new();
+        match lstat(target, &mut stat) {
+            Err(ENOENT) => return Ok(None),
+            Err(e) => return Err(e),
+            Ok(_) if stat.mode & S_IFMT != S_IFLNK => return Ok(Some(stat)),
+            Ok(_) => {}
+        }
+
+        let mut link = [0u8; MAX_PATH];
+        let len = readlink(target, &mut link)?;
+
+        // Relative link targets start from the directory holding the link
+        let dir_len = if link.first() == Some(&b'/') {
+            0
+        } else {
+            name_of(target)
+                .iter()
+                .rposition(|&b| b == b'/')
+                .map_or(0, |slash| slash + 1)
+        };
+        if dir_len + len >= MAX_PATH {
+            return Err(ENAMETOOLONG);
+        }
+        target[dir_len..dir_len + len].copy_from_slice(&link[..len]);
+        target[dir_len + len] = 0;
+    }
+    Err(ELOOP)
+}
+
+// Give a new file the mode and owner of the one it replaces; only root may
+// hand files to other users, so a failed chown keeps our own ownership
+pub(in crate::editor) fn copy_attributes(fd: usize, existing: Option<&Stat>) -> Result<(), usize> {
+    let Some(stat) = existing else {
+        return Ok(());
+    };
+
+    let _ = fchown(fd, stat.uid, stat.gid);
+    // Changing the owner clears setuid bits, so the mode goes last
+    fchmod(fd, stat.mode & !S_IFMT)?;
+    Ok(())
+}
+
+// Create a fresh temp file next to target, named .name.tmp or .name.tmpN
+// when that is taken. Existing files and symlinks are never opened, so a
+// user's file of the same name is neither followed nor truncated.
+fn create_temp_file(target: &[u8], temp: &mut [u8; MAX_PATH]) -> SysResult {
+    use crate::syscall::{EEXIST, O_CREAT, O_EXCL, O_NOFOLLOW, O_WRONLY, open};
+
+    let mut suffix = *b".tmp00";
+    for attempt in 0..=TEMP_ATTEMPTS {
+        let len = match attempt {
+            0 => 4,
+            1..10 => {
+                suffix[4] = b'0' + attempt;
+                5
+            }
+            _ => {
+                suffix[4] = b'0' + attempt / 10;
+                suffix[5] = b'0' + attempt % 10;
+                6
+            }
+        };
+        if !sibling_path(target, b".", &suffix[..len], temp) {
+            return Err(ENAMETOOLONG);
+        }
+        match open(temp, O_WRONLY | O_CREAT | O_EXCL | O_NOFOLLOW) {
+            Err(EEXIST) => {}
+            result => return result,
+        }
+    }
+    Err(EEXIST)
+}
+
+// Flush the directory entry of a saved file. Some filesystems refuse to
+// sync directories with EINVAL, which is not worth failing the save for.
+fn sync_parent_dir(path: &[u8]) -> Result<(), usize> {
+    use crate::syscall::{EINVAL, O_RDONLY, close, fsync, open};
+
+    let path = name_of(path);
+    let dir = match path.iter().rposition(|&b| b == b'/') {
+        Some(0) => &b"/"[..],
+        Some(slash) => &path[..slash],
+        None => b".",
+    };
+
+    let mut dir_path = [0u8; MAX_PATH];
+    let len = dir.len().min(MAX_PATH - 1);
+    dir_path[..len].copy_from_slice(&dir[..len]);
+
+    let fd = open(&dir_path, O_RDONLY)?;
+    let synced = fsync(fd);
+    let closed = close(fd);
+    match synced {
+        Ok(_) | Err(EINVAL) => {}
+        Err(e) => return Err(e),
+    }
+    closed?;
+    Ok(())
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
@@ -401,6 +923,14 @@ pub mod tests {
             size: 0,
             capacity: 5,
             modified: false,
+            tail: Cell::new(0),
+            lines: LineIndex::new(),
+            undo: UndoLog::new(),
+            changed: Cell::new(0),
+            original: Original::none(),
+            read_only: false,
+            auto_save_pending: false,
+            stamp: None,
         };
 
         // Allocate memory for the buffer
@@ -440,20 +970,18 @@ pub mod tests {
         );
 
         // Check that content was preserved during resize
-        unsafe {
-            for i in 0..5 {
-                assert_eq!(
-                    *buffer.content.add(i),
-                    b'A' + u8::try_from(i).unwrap(),
-                    "Content should be preserved after resize"
-                );
-            }
+        for i in 0..5 {
             assert_eq!(
-                *buffer.content.add(5),
-                b'F',
-                "New character should be added after resize"
+                buffer.byte_at(i),
+                b'A' + u8::try_from(i).unwrap(),
+                "Content should be preserved after resize"
             );
         }
+        assert_eq!(
+            buffer.byte_at(5),
+            b'F',
+            "New character should be added after resize"
+        );
 
         // Clean up
         let _ = crate::syscall::munmap(buffer.content as usize, buffer.capacity);
@@ -552,15 +1080,33 @@ pub mod tests {
     }
 
     // Helper function to create a FileBuffer from a byte array for testing
+    // Non-empty text is copied into its own mapping, which the buffer unmaps when dropped
     pub fn create_test_file_buffer(content: &[u8]) -> FileBuffer {
-        let content_ptr = content.as_ptr().cast_mut();
         let size = content.len();
+        let mut content_ptr = content.as_ptr().cast_mut();
+        if size > 0 {
+            let prot = crate::syscall::PROT_READ | crate::syscall::PROT_WRITE;
+            let flags = crate::syscall::MAP_PRIVATE | crate::syscall::MAP_ANONYMOUS;
+            let Ok(addr) = crate::syscall::mmap(0, size, prot, flags, usize::MAX, 0) else {
+                panic!("Failed to allocate test buffer: mmap error");
+            };
+            content_ptr = addr as *mut u8;
+            unsafe { std::ptr::copy_nonoverlapping(content.as_ptr(), content_ptr, size) };
+        }
 
         FileBuffer {
             content: content_ptr,
             size,
             capacity: size,
             modified: false,
+            tail: Cell::new(0),
+            lines: LineIndex::new(),
+            undo: UndoLog::new(),
+            changed: Cell::new(0),
+            original: Original::none(),
+            read_only: false,
+            auto_save_pending: false,
+            stamp: None,
         }
     }
 
@@ -606,6 +1152,14 @@ pub mod tests {
             size: 0,
             capacity: 0,
             modified: false,
+            tail: Cell::new(0),
+            lines: LineIndex::new(),
+            undo: UndoLog::new(),
+            changed: Cell::new(0),
+            original: Original::none(),
+            read_only: false,
+            auto_save_pending: false,
+            stamp: None,
         };
 
         assert_eq!(
@@ -810,6 +1364,14 @@ pub mod tests {
             size: 0,
             capacity: 10,
             modified: false,
+            tail: Cell::new(0),
+            lines: LineIndex::new(),
+            undo: UndoLog::new(),
+            changed: Cell::new(0),
+            original: Original::none(),
+            read_only: false,
+            auto_save_pending: false,
+            stamp: None,
         };
 
         // Allocate memory for the buffer
@@ -825,24 +1387,21 @@ pub mod tests {
         assert!(result.is_ok(), "Should successfully insert at position 0");
         assert_eq!(buffer.size, 1, "Size should be updated after insertion");
         assert!(buffer.is_modified(), "Buffer should be marked as modified");
-        unsafe {
-            assert_eq!(
-                *buffer.content, b'A',
-                "Character should be inserted correctly"
-            );
-        }
+        assert_eq!(
+            buffer.byte_at(0),
+            b'A',
+            "Character should be inserted correctly"
+        );
 
         // Test inserting in the middle
         let result = buffer.insert_at_position(1, b'C');
         assert!(result.is_ok(), "Should successfully insert at position 1");
         assert_eq!(buffer.size, 2, "Size should be updated after insertion");
-        unsafe {
-            assert_eq!(
-                *buffer.content.add(1),
-                b'C',
-                "Character should be inserted correctly"
-            );
-        }
+        assert_eq!(
+            buffer.byte_at(1),
+            b'C',
+            "Character should be inserted correctly"
+        );
 
         // Test inserting in the middle again
         let result = buffer.insert_at_position(1, b'B');
@@ -850,31 +1409,19 @@ pub mod tests {
         assert_eq!(buffer.size, 3, "Size should be updated after insertion");
 
         // Verify the buffer now contains "ABC"
-        unsafe {
-            assert_eq!(*buffer.content, b'A', "First character should be 'A'");
-            assert_eq!(
-                *buffer.content.add(1),
-                b'B',
-                "Second character should be 'B'"
-            );
-            assert_eq!(
-                *buffer.content.add(2),
-                b'C',
-                "Third character should be 'C'"
-            );
-        }
+        assert_eq!(buffer.byte_at(0), b'A', "First character should be 'A'");
+        assert_eq!(buffer.byte_at(1), b'B', "Second character should be 'B'");
+        assert_eq!(buffer.byte_at(2), b'C', "Third character should be 'C'");
 
         // Test inserting at the end
         let result = buffer.insert_at_position(3, b'D');
         assert!(result.is_ok(), "Should successfully insert at end position");
         assert_eq!(buffer.size, 4, "Size should be updated after insertion");
-        unsafe {
-            assert_eq!(
-                *buffer.content.add(3),
-                b'D',
-                "Character should be inserted correctly"
-            );
-        }
+        assert_eq!(
+            buffer.byte_at(3),
+            b'D',
+            "Character should be inserted correctly"
+        );
 
         // Test inserting beyond current size
         let result = buffer.insert_at_position(5, b'X');
@@ -929,6 +1476,14 @@ pub mod tests {
             size: content.len(),
             capacity,
             modified: false,
+            tail: Cell::new(0),
+            lines: LineIndex::new(),
+            undo: UndoLog::new(),
+            changed: Cell::new(0),
+            original: Original::none(),
+            read_only: false,
+            auto_save_pending: false,
+            stamp: None,
         };
 
         // Test deleting from the middle
@@ -938,24 +1493,10 @@ pub mod tests {
         assert!(buffer.is_modified(), "Buffer should be marked as modified");
 
         // Verify the buffer now contains "ABDE"
-        unsafe {
-            assert_eq!(*buffer.content, b'A', "First character should be 'A'");
-            assert_eq!(
-                *buffer.content.add(1),
-                b'B',
-                "Second character should be 'B'"
-            );
-            assert_eq!(
-                *buffer.content.add(2),
-                b'D',
-                "Third character should be 'D'"
-            );
-            assert_eq!(
-                *buffer.content.add(3),
-                b'E',
-                "Fourth character should be 'E'"
-            );
-        }
+        assert_eq!(buffer.byte_at(0), b'A', "First character should be 'A'");
+        assert_eq!(buffer.byte_at(1), b'B', "Second character should be 'B'");
+        assert_eq!(buffer.byte_at(2), b'D', "Third character should be 'D'");
+        assert_eq!(buffer.byte_at(3), b'E', "Fourth character should be 'E'");
 
         // Test deleting from the beginning
         let result = buffer.delete_at_position(0); // Delete 'A'
@@ -963,19 +1504,9 @@ pub mod tests {
         assert_eq!(buffer.size, 3, "Size should be updated after deletion");
 
         // Verify the buffer now contains "BDE"
-        unsafe {
-            assert_eq!(*buffer.content, b'B', "First character should be 'B'");
-            assert_eq!(
-                *buffer.content.add(1),
-                b'D',
-                "Second character should be 'D'"
-            );
-            assert_eq!(
-                *buffer.content.add(2),
-                b'E',
-                "Third character should be 'E'"
-            );
-        }
+        assert_eq!(buffer.byte_at(0), b'B', "First character should be 'B'");
+        assert_eq!(buffer.byte_at(1), b'D', "Second character should be 'D'");
+        assert_eq!(buffer.byte_at(2), b'E', "Third character should be 'E'");
 
         // Test deleting from the end
         let result = buffer.delete_at_position(2); // Delete 'E'
@@ -983,14 +1514,8 @@ pub mod tests {
         assert_eq!(buffer.size, 2, "Size should be updated after deletion");
 
         // Verify the buffer now contains "BD"
-        unsafe {
-            assert_eq!(*buffer.content, b'B', "First character should be 'B'");
-            assert_eq!(
-                *buffer.content.add(1),
-                b'D',
-                "Second character should be 'D'"
-            );
-        }
+        assert_eq!(buffer.byte_at(0), b'B', "First character should be 'B'");
+        assert_eq!(buffer.byte_at(1), b'D', "Second character should be 'D'");
 
         // Test deleting beyond current size
         let result = buffer.delete_at_position(2);
@@ -1020,6 +1545,14 @@ pub mod tests {
             size: 0,
             capacity: 100,
             modified: false,
+            tail: Cell::new(0),
+            lines: LineIndex::new(),
+            undo: UndoLog::new(),
+            changed: Cell::new(0),
+            original: Original::none(),
+            read_only: false,
+            auto_save_pending: false,
+            stamp: None,
         };
 
         // Allocate memory for the buffer
@@ -1102,6 +1635,14 @@ pub mod tests {
             size: 0,
             capacity: 100,
             modified: false,
+            tail: Cell::new(0),
+            lines: LineIndex::new(),
+            undo: UndoLog::new(),
+            changed: Cell::new(0),
+            original: Original::none(),
+            read_only: false,
+            auto_save_pending: false,
+            stamp: None,
         };
 
         // Allocate memory for the buffer
@@ -1161,6 +1702,14 @@ pub mod tests {
             size: 0,
             capacity: 100,
             modified: false,
+            tail: Cell::new(0),
+            lines: LineIndex::new(),
+            undo: UndoLog::new(),
+            changed: Cell::new(0),
+            original: Original::none(),
+            read_only: false,
+            auto_save_pending: false,
+            stamp: None,
         };
 
         // Allocate memory for the buffer
@@ -1245,6 +1794,14 @@ pub mod tests {
             size: 0,
             capacity: 100,
             modified: false,
+            tail: Cell::new(0),
+            lines: LineIndex::new(),
+            undo: UndoLog::new(),
+            changed: Cell::new(0),
+            original: Original::none(),
+            read_only: false,
+            auto_save_pending: false,
+            stamp: None,
         };
 
         // Allocate memory for the buffer
@@ -1334,6 +1891,14 @@ pub mod tests {
             size: 0,
             capacity: 100,
             modified: false,
+            tail: Cell::new(0),
+            lines: LineIndex::new(),
+            undo: UndoLog::new(),
+            changed: Cell::new(0),
+            original: Original::none(),
+            read_only: false,
+            auto_save_pending: false,
+            stamp: None,
         };
 
         // Allocate memory for the buffer
@@ -1356,7 +1921,7 @@ pub mod tests {
 
         // Save the buffer to a test file
         let test_file = b"test_save_file.txt\0";
-        let result = buffer.save_to_file(test_file);
+        let result = buffer.save_to_file(test_file, BackupMode::Off);
         assert!(result.is_ok(), "File should be saved successfully");
         assert!(
             !buffer.is_modified(),
@@ -1399,6 +1964,14 @@ pub mod tests {
             size: 0,
             capacity: 100,
             modified: true, // Marked as modified since it's a new buffer
+            tail: Cell::new(0),
+            lines: LineIndex::new(),
+            undo: UndoLog::new(),
+            changed: Cell::new(0),
+            original: Original::none(),
+            read_only: false,
+            auto_save_pending: false,
+            stamp: None,
         };
 
         // Allocate memory for the buffer
@@ -1419,7 +1992,7 @@ pub mod tests {
         }
 
         // Attempt to save the buffer
-        let result = buffer.save_to_file(&test_file);
+        let result = buffer.save_to_file(&test_file, BackupMode::Off);
         // Clean up buffer immediately to avoid memory issues
         let _ = crate::syscall::munmap(buffer.content as usize, buffer.capacity);
 
@@ -1444,6 +2017,165 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn test_save_replaces_file_atomically() {
+        use std::path::Path;
+
+        let test_file_path = "test_atomic_save_123456.txt";
+        let temp_file_path = ".test_atomic_save_123456.txt.tmp";
+        std::fs::write(test_file_path, "old contents").expect("Failed to create test file");
+
+        let mut buffer = create_test_file_buffer(b"new");
+        let result = buffer.save_to_file(b"test_atomic_save_123456.txt\0", BackupMode::Off);
+        assert_eq!(result, Ok(3));
+        assert!(!Path::new(temp_file_path).exists(), "Temp file is renamed");
+        let contents = std::fs::read_to_string(test_file_path).expect("Failed to read file");
+        assert_eq!(contents, "new", "Old contents are replaced");
+        std::fs::remove_file(test_file_path).expect("Failed to clean up test file");
+
+        // A save that cannot complete leaves nothing behind
+        let result = buffer.save_to_file(b"no_such_dir_123456/file.txt\0", BackupMode::Off);
+        assert!(result.is_err(), "Missing directory is an error");
+
+        // Files already using the temp name are left alone, links included
+        std::fs::write(temp_file_path, "user file").expect("Failed to create test file");
+        std::os::unix::fs::symlink(test_file_path, ".test_atomic_save_123456.txt.tmp1")
+            .expect("Failed to create symlink");
+        let result = buffer.save_to_file(b"test_atomic_save_123456.txt\0", BackupMode::Off);
+        assert_eq!(result, Ok(3));
+        let contents = std::fs::read_to_string(temp_file_path).expect("Failed to read file");
+        assert_eq!(contents, "user file", "Existing temp name is not truncated");
+        let link = std::fs::symlink_metadata(".test_atomic_save_123456.txt.tmp1").unwrap();
+        assert!(
+            link.file_type().is_symlink(),
+            "Planted link is not followed"
+        );
+        assert!(!Path::new(".test_atomic_save_123456.txt.tmp2").exists());
+        assert_eq!(std::fs::read_to_string(test_file_path).unwrap(), "new");
+        for path in [
+            test_file_path,
+            temp_file_path,
+            ".test_atomic_save_123456.txt.tmp1",
+        ] {
+            std::fs::remove_file(path).expect("Failed to clean up test file");
+        }
+
+        let mut temp = [0u8; MAX_PATH];
+        assert!(sibling_path(b"dir/sub/name.rs\0", b".", b".tmp", &mut temp));
+        assert_eq!(name_of(&temp), b"dir/sub/.name.rs.tmp");
+        assert!(sibling_path(b"name.rs\0", b"#", b"#", &mut temp));
+        assert_eq!(name_of(&temp), b"#name.rs#");
+        assert!(
+            !sibling_path(&[b'a'; MAX_PATH - 4], b".", b".tmp", &mut temp),
+            "Too long"
+        );
+    }
+
+    #[test]
+    fn test_save_keeps_mode_and_symlinks() {
+        use std::os::unix::fs::{PermissionsExt, symlink};
+
+        let dir = "test_save_attributes_123456";
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir(dir).expect("Failed to create test directory");
+        let script = format!("{dir}/script.sh");
+        std::fs::write(&script, "old").expect("Failed to create test file");
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o750))
+            .expect("Failed to set mode");
+        symlink("script.sh", format!("{dir}/link")).expect("Failed to create symlink");
+
+        // Saving through the relative link writes the script itself
+        let mut buffer = create_test_file_buffer(b"#!/bin/sh\n");
+        let result = buffer.save_to_file(format!("{dir}/link\0").as_bytes(), BackupMode::Off);
+        assert_eq!(result, Ok(10));
+
+        let link = std::fs::symlink_metadata(format!("{dir}/link")).unwrap();
+        assert!(link.file_type().is_symlink(), "Link is kept");
+        let contents = std::fs::read_to_string(&script).expect("Failed to read file");
+        assert_eq!(contents, "#!/bin/sh\n");
+        let mode = std::fs::metadata(&script).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o750, "Mode is preserved");
+
+        // A dangling link creates its target
+        symlink("new.txt", format!("{dir}/dangling")).expect("Failed to create symlink");
+        let result = buffer.save_to_file(format!("{dir}/dangling\0").as_bytes(), BackupMode::Off);
+        assert!(result.is_ok());
+        assert!(std::path::Path::new(&format!("{dir}/new.txt")).exists());
+
+        // Links pointing at each other never resolve
+        symlink("loop_b", format!("{dir}/loop_a")).expect("Failed to create symlink");
+        symlink("loop_a", format!("{dir}/loop_b")).expect("Failed to create symlink");
+        let result = buffer.save_to_file(format!("{dir}/loop_a\0").as_bytes(), BackupMode::Off);
+        assert_eq!(result, Err(ELOOP));
+
+        std::fs::remove_dir_all(dir).expect("Failed to clean up test directory");
+    }
+
+    #[test]
+    fn test_read_only_refuses_edits_and_saves() {
+        let test_file_path = "test_read_only_123456.txt";
+        let path = format!("{test_file_path}\0");
+        let mut buffer = create_test_file_buffer(b"text");
+        buffer.insert_char(0, 0, b'x').unwrap();
+        buffer.undo_boundary(0, 1);
+        buffer.read_only = true;
+
+        assert!(matches!(
+            buffer.insert_char(0, 0, b'y'),
+            Err(FileBufferError::ReadOnly)
+        ));
+        assert!(buffer.insert_text(0, b"y").is_err());
+        assert!(buffer.delete_char(0, 0).is_err());
+        assert!(buffer.delete_text(0, 1).is_err());
+        assert!(buffer.undo().is_none());
+        assert_eq!(buffer.text_range(0, buffer.size), b"xtext");
+
+        assert_eq!(
+            buffer.save_to_file(path.as_bytes(), BackupMode::Off),
+            Err(EROFS)
+        );
+        assert_eq!(buffer.auto_save(path.as_bytes()), Ok(0));
+        assert!(!std::path::Path::new(test_file_path).exists());
+        assert_eq!(
+            FileBufferError::ReadOnly.describe("Failed"),
+            "Buffer is read-only"
+        );
+    }
+
+    #[test]
+    fn test_changed_on_disk() {
+        let test_file_path = "test_changed_on_disk_123456.txt";
+        let path = format!("{test_file_path}\0");
+        let _ = std::fs::remove_file(test_file_path);
+
+        // A file that does not exist yet cannot conflict
+        let mut buffer = create_test_file_buffer(b"ours");
+        assert!(!buffer.changed_on_disk(path.as_bytes()));
+        assert!(
+            !buffer.changed_on_disk(b"\0"),
+            "Unnamed buffers have no file"
+        );
+
+        // Someone else creating the file is a conflict
+        std::fs::write(test_file_path, "theirs").expect("Failed to create test file");
+        assert!(buffer.changed_on_disk(path.as_bytes()));
+
+        // Our own save is the version on disk
+        buffer
+            .save_to_file(path.as_bytes(), BackupMode::Off)
+            .unwrap();
+        assert!(!buffer.changed_on_disk(path.as_bytes()));
+
+        std::fs::write(test_file_path, "rewritten").expect("Failed to rewrite test file");
+        assert!(buffer.changed_on_disk(path.as_bytes()));
+
+        std::fs::remove_file(test_file_path).expect("Failed to clean up test file");
+        assert!(
+            !buffer.changed_on_disk(path.as_bytes()),
+            "Deleted files are recreated"
+        );
+    }
+
     #[test]
     fn test_file_buffer_insert_and_save() {
         use std::io::Read;
@@ -1454,6 +2186,14 @@ pub mod tests {
             size: 0,
             capacity: 100,
             modified: false,
+            tail: Cell::new(0),
+            lines: LineIndex::new(),
+            undo: UndoLog::new(),
+            changed: Cell::new(0),
+            original: Original::none(),
+            read_only: false,
+            auto_save_pending: false,
+            stamp: None,
         };
 
         // Allocate memory for the buffer
@@ -1489,7 +2229,7 @@ pub mod tests {
 
         // Save the buffer to a test file
         let test_file = b"test_edit_save_file.txt\0";
-        let result = buffer.save_to_file(test_file);
+        let result = buffer.save_to_file(test_file, BackupMode::Off);
         assert!(result.is_ok(), "File should be saved successfully");
 
         // Verify the file was written correctly
@@ -1508,7 +2248,7 @@ pub mod tests {
         buffer.insert_char(0, 4, b'p').unwrap(); // Replace with 'p'
 
         // Save again
-        buffer.save_to_file(test_file).unwrap();
+        buffer.save_to_file(test_file, BackupMode::Off).unwrap();
 
         // Verify the updated content
         let mut file =
@@ -1525,4 +2265,195 @@ pub mod tests {
         let _ = crate::syscall::munmap(buffer.content as usize, buffer.capacity);
         std::fs::remove_file("test_edit_save_file.txt").expect("Failed to clean up test file");
     }
+
+    #[test]
+    fn test_file_buffer_gap_edits() {
+        let mut buffer = FileBuffer {
+            content: std::ptr::null_mut(),
+            size: 0,
+            capacity: 64,
+            modified: false,
+            tail: Cell::new(0),
+            lines: LineIndex::new(),
+            undo: UndoLog::new(),
+            changed: Cell::new(0),
+            original: Original::none(),
+            read_only: false,
+            auto_save_pending: false,
+            stamp: None,
+        };
+
+        let prot = crate::syscall::PROT_READ | crate::syscall::PROT_WRITE;
+        let flags = crate::syscall::MAP_PRIVATE | crate::syscall::MAP_ANONYMOUS;
+        let Ok(addr) = crate::syscall::mmap(0, buffer.capacity, prot, flags, usize::MAX, 0) else {
+            panic!("Failed to allocate test buffer: mmap error");
+        };
+        buffer.content = addr as *mut u8;
+
+        let content = b"one\ntwo\nthree";
+        unsafe {
+            std::ptr::copy_nonoverlapping(content.as_ptr(), buffer.content, content.len());
+        }
+        buffer.size = content.len();
+
+        // Edit in the middle so that text lives on both sides of the gap
+        buffer.insert_char(1, 1, b'X').unwrap();
+        assert_eq!(buffer.get_line(0), Some(&b"one"[..]));
+        assert_eq!(buffer.get_line(1), Some(&b"tXwo"[..]));
+        assert_eq!(buffer.get_line(2), Some(&b"three"[..]));
+
+        // Slices of different lines can be held at the same time
+        buffer.insert_char(1, 2, b'Y').unwrap();
+        let first = buffer.get_line(0).unwrap();
+        let second = buffer.get_line(1).unwrap();
+        let third = buffer.get_line(2).unwrap();
+        assert_eq!(first, b"one");
+        assert_eq!(second, b"tXYwo");
+        assert_eq!(third, b"three");
+
+        // Growing the buffer keeps the text around the gap intact
+        for _ in 0..5000 {
+            buffer.insert_char(0, 0, b'a').unwrap();
+        }
+        assert!(buffer.capacity >= buffer.size, "Buffer should have grown");
+        assert_eq!(buffer.line_length(0, 4), 5003);
+        assert_eq!(buffer.get_line(1), Some(&b"tXYwo"[..]));
+        assert_eq!(buffer.get_line(2), Some(&b"three"[..]));
+
+        // Deleting the newline joins lines across the gap
+        buffer.backspace_at(2, 0).unwrap();
+        assert_eq!(buffer.count_lines(), 2);
+        assert_eq!(buffer.get_line(1), Some(&b"tXYwothree"[..]));
+        assert_eq!(buffer.position_to_row_col(buffer.size), (1, 10));
+
+        // Generated text is cleared and refilled without undo steps
+        buffer.clear_generated();
+        assert_eq!(buffer.count_lines(), 1);
+        buffer.append_text(b"new\ntext").unwrap();
+        assert_eq!(buffer.text_range(0, buffer.size), b"new\ntext");
+        assert_eq!(buffer.get_line(1), Some(&b"text"[..]));
+
+        let _ = crate::syscall::munmap(buffer.content as usize, buffer.capacity);
+    }
+
+    #[test]
+    fn test_file_buffer_undo_redo() {
+        let mut buffer = FileBuffer {
+            content: std::ptr::null_mut(),
+            size: 0,
+            capacity: 100,
+            modified: false,
+            tail: Cell::new(0),
+            lines: LineIndex::new(),
+            undo: UndoLog::new(),
+            changed: Cell::new(0),
+            original: Original::none(),
+            read_only: false,
+            auto_save_pending: false,
+            stamp: None,
+        };
+
+        let prot = crate::syscall::PROT_READ | crate::syscall::PROT_WRITE;
+        let flags = crate::syscall::MAP_PRIVATE | crate::syscall::MAP_ANONYMOUS;
+        let Ok(addr) = crate::syscall::mmap(0, buffer.capacity, prot, flags, usize::MAX, 0) else {
+            panic!("Failed to allocate test buffer: mmap error");
+        };
+        buffer.content = addr as *mut u8;
+
+        assert_eq!(buffer.undo(), None, "Fresh buffer has nothing to undo");
+
+        // Typed characters in one group are undone together
+        buffer.undo_boundary(0, 0);
+        buffer.insert_char(0, 0, b'a').unwrap();
+        buffer.insert_char(0, 1, b'b').unwrap();
+        buffer.insert_char(0, 2, b'c').unwrap();
+
+        // A separate deletion forms its own group
+        buffer.undo_boundary(0, 1);
+        buffer.delete_char(0, 1).unwrap();
+        assert_eq!(buffer.get_line(0), Some(&b"ac"[..]));
+
+        assert_eq!(buffer.undo(), Some((0, 1)), "Cursor restored before delete");
+        assert_eq!(buffer.get_line(0), Some(&b"abc"[..]));
+        assert_eq!(buffer.undo(), Some((0, 0)), "Cursor restored before typing");
+        assert_eq!(buffer.size, 0, "Whole typed group should be undone");
+        assert!(!buffer.is_modified(), "Back at the original state");
+        assert_eq!(buffer.undo(), None, "No further undo information");
+
+        // Redo replays the groups in order
+        assert_eq!(buffer.redo(), Some((0, 3)), "Cursor after retyped text");
+        assert_eq!(buffer.get_line(0), Some(&b"abc"[..]));
+        assert!(buffer.is_modified(), "Redo modifies the buffer again");
+        assert_eq!(buffer.redo(), Some((0, 1)));
+        assert_eq!(buffer.get_line(0), Some(&b"ac"[..]));
+        assert_eq!(buffer.redo(), None, "No further redo information");
+
+        let _ = crate::syscall::munmap(buffer.content as usize, buffer.capacity);
+    }
+
+    #[test]
+    fn test_file_buffer_unsaved_changes() {
+        let mut buffer = create_test_file_buffer(b"");
+        buffer.modified = true;
+        assert!(
+            !buffer.has_unsaved_changes(),
+            "A new file nobody typed into has nothing to lose"
+        );
+
+        buffer.undo_boundary(0, 0);
+        buffer.insert_char(0, 0, b'x').unwrap();
+        assert!(buffer.has_unsaved_changes());
+
+        buffer.undo();
+        assert!(
+            !buffer.has_unsaved_changes(),
+            "Undo back to the saved state"
+        );
+    }
+
+    #[test]
+    fn test_file_buffer_undo_multiline() {
+        let mut buffer = FileBuffer {
+            content: std::ptr::null_mut(),
+            size: 0,
+            capacity: 100,
+            modified: false,
+            tail: Cell::new(0),
+            lines: LineIndex::new(),
+            undo: UndoLog::new(),
+            changed: Cell::new(0),
+            original: Original::none(),
+            read_only: false,
+            auto_save_pending: false,
+            stamp: None,
+        };
+
+        let prot = crate::syscall::PROT_READ | crate::syscall::PROT_WRITE;
+        let flags = crate::syscall::MAP_PRIVATE | crate::syscall::MAP_ANONYMOUS;
+        let Ok(addr) = crate::syscall::mmap(0, buffer.capacity, prot, flags, usize::MAX, 0) else {
+            panic!("Failed to allocate test buffer: mmap error");
+        };
+        buffer.content = addr as *mut u8;
+
+        let content = b"first\nsecond\n";
+        unsafe {
+            std::ptr::copy_nonoverlapping(content.as_ptr(), buffer.content, content.len());
+        }
+        buffer.size = content.len();
+
+        // Join the lines and then undo the join
+        buffer.undo_boundary(1, 0);
+        buffer.backspace_at(1, 0).unwrap();
+        assert_eq!(buffer.get_line(0), Some(&b"firstsecond"[..]));
+
+        assert_eq!(buffer.undo(), Some((1, 0)));
+        assert_eq!(buffer.get_line(0), Some(&b"first"[..]));
+        assert_eq!(buffer.get_line(1), Some(&b"second"[..]));
+
+        // Redo places the cursor where the deletion happened
+        assert_eq!(buffer.redo(), Some((0, 5)));
+        assert_eq!(buffer.get_line(0), Some(&b"firstsecond"[..]));
+
+        let _ = crate::syscall::munmap(buffer.content as usize, buffer.capacity);
+    }
 }
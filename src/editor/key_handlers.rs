@@ -1,4 +1,4 @@
-use crate::syscall::{STDIN, read};
+use crate::syscall::{POLLIN, PollFd, STDIN, poll, read};
 
 fn read_char() -> Option<u8> {
     let mut buf = [0u8; 1];
@@ -8,6 +8,20 @@ fn read_char() -> Option<u8> {
     }
 }
 
+// Like read_char, but give up after `timeout_ms` milliseconds without input
+#[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)] // STDIN is 0
+fn read_char_timeout(timeout_ms: usize) -> Option<u8> {
+    let mut fds = [PollFd {
+        fd: STDIN as _,
+        events: POLLIN,
+        revents: 0,
+    }];
+    match poll(&mut fds, timeout_ms) {
+        Ok(ready) if ready > 0 => read_char(),
+        _ => None,
+    }
+}
+
 #[derive(PartialEq, Copy, Clone)]
 pub(in crate::editor) enum Key {
     Char(u8),
@@ -36,14 +50,37 @@ pub(in crate::editor) enum Key {
     WordForward,
     WordBackward,
     ToggleCase,
-    SetMark,  // For selecting text with Ctrl+Space
-    Cut,      // Cut selected text with Ctrl+w
-    Copy,     // Copy selected text with Alt+w
-    Paste,    // Paste text with Ctrl+y
-    KillLine, // Kill to end of line with Ctrl+k
+    SetMark,            // For selecting text with Ctrl+Space
+    Cut,                // Cut selected text with Ctrl+w
+    Copy,               // Copy selected text with Alt+w
+    Paste,              // Paste text with Ctrl+y
+    YankPop,            // Replace the last yank with an older kill with Alt+y
+    KillLine,           // Kill to end of line with Ctrl+k
+    Undo,               // Undo last change with Ctrl+/, Ctrl+_ or Ctrl+x u
+    Redo,               // Redo undone change with Ctrl+Alt+_
+    SwitchBuffer,       // Switch to another buffer with Ctrl+x b
+    KillBuffer,         // Close a buffer with Ctrl+x k
+    ListBuffers,        // Show all buffers with Ctrl+x Ctrl+b
+    ExecuteCommand,     // Run a command by name with Alt+x
+    WriteFile,          // Save under a new name with Ctrl+x Ctrl+w
+    InsertFile,         // Insert a file at the cursor with Ctrl+x i
+    QueryReplace,       // Replace matches one by one with Alt+%
+    RegexSearch,        // Search for a regular expression with Ctrl+Alt+s
+    RegexReverseSearch, // Search backward for a regular expression with Ctrl+Alt+r
+    HistoryPrev,        // Recall an older search query with Alt+p
+    HistoryNext,        // Recall a newer search query with Alt+n
+    WordSearch,         // Search for whole words, or toggle that while searching, with Alt+s w
+    // A prefix and the key after it, bound to nothing
     Combination([u8; 2]),
 }
 
+impl Key {
+    // Whether the key runs a command that kills text into the kill-ring
+    pub(in crate::editor) fn is_kill(self) -> bool {
+        matches!(self, Key::Cut | Key::KillLine)
+    }
+}
+
 fn process_escape_sequence() -> Key {
     let Some(second_ch) = read_char() else {
         return Key::Escape; // ESC pressed without a sequence
@@ -57,113 +94,140 @@ fn process_escape_sequence() -> Key {
         b'b' => Key::WordBackward,
         b'c' => Key::ToggleCase,
         b'w' => Key::Copy,
+        b'y' => Key::YankPop,
+        b'x' => Key::ExecuteCommand,
+        b'%' => Key::QueryReplace,
+        b'p' => Key::HistoryPrev,
+        b'n' => Key::HistoryNext,
+        // An unbound or cut-off Alt+s sequence does nothing rather than type
+        b's' => match read_char() {
+            Some(b'w') => Key::WordSearch,
+            Some(third_ch) => Key::Combination([second_ch, third_ch]),
+            None => Key::Combination([27, second_ch]),
+        },
+        31 => Key::Redo,               // C-M-_ (undo-redo)
+        19 => Key::RegexSearch,        // C-M-s (isearch-forward-regexp)
+        18 => Key::RegexReverseSearch, // C-M-r (isearch-backward-regexp)
+
+        b'[' => process_csi_sequence(),
 
-        b'[' => {
+        b'O' => {
             let Some(third_ch) = read_char() else {
                 return Key::Char(second_ch);
             };
 
             match third_ch {
-                b'A' => Key::ArrowUp,
-                b'B' => Key::ArrowDown,
-                b'C' => Key::ArrowRight,
-                b'D' => Key::ArrowLeft,
-                b'H' => Key::Home, // Home key
-                b'F' => Key::End,  // End key
-
-                b'5' => {
-                    let Some(fourth_ch) = read_char() else {
-                        return Key::Char(third_ch);
-                    };
-
-                    if fourth_ch == b'~' {
-                        return Key::PageUp;
-                    }
-                    Key::Char(fourth_ch)
-                }
+                b'A' => Key::ArrowUp,    // Up arrow
+                b'B' => Key::ArrowDown,  // Down arrow
+                b'C' => Key::ArrowRight, // Right arrow
+                b'D' => Key::ArrowLeft,  // Left arrow
+                b'H' => Key::Home,       // Home
+                b'F' => Key::End,        // End
+                _ => Key::Char(third_ch),
+            }
+        }
 
-                b'6' => {
-                    let Some(fourth_ch) = read_char() else {
-                        return Key::Char(third_ch);
-                    };
+        _ => Key::Char(second_ch),
+    }
+}
 
-                    if fourth_ch == b'~' {
-                        return Key::PageDown;
-                    }
-                    Key::Char(fourth_ch)
-                }
+// Keys sent as ESC [ sequences: arrows, Home, End, Page Up/Down and Delete
+fn process_csi_sequence() -> Key {
+    let Some(third_ch) = read_char() else {
+        return Key::Char(b'[');
+    };
 
-                b'3' => {
-                    let Some(fourth_ch) = read_char() else {
-                        return Key::Char(third_ch);
-                    };
+    match third_ch {
+        b'A' => Key::ArrowUp,
+        b'B' => Key::ArrowDown,
+        b'C' => Key::ArrowRight,
+        b'D' => Key::ArrowLeft,
+        b'H' => Key::Home, // Home key
+        b'F' => Key::End,  // End key
 
-                    if fourth_ch == b'~' {
-                        return Key::Delete;
-                    }
-                    Key::Char(fourth_ch)
-                }
+        b'5' => {
+            let Some(fourth_ch) = read_char() else {
+                return Key::Char(third_ch);
+            };
 
-                b'1' => {
-                    let Some(fourth_ch) = read_char() else {
-                        return Key::Char(third_ch);
-                    };
-
-                    if fourth_ch == b'~' {
-                        return Key::Home; // Home key on some terminals
-                    } else if fourth_ch == b';' {
-                        let _ = read_char();
-
-                        if let Some(code) = read_char() {
-                            match code {
-                                b'A' => return Key::ArrowUp,
-                                b'B' => return Key::ArrowDown,
-                                b'C' => return Key::ArrowRight,
-                                b'D' => return Key::ArrowLeft,
-                                _ => return Key::Char(code),
-                            }
-                        }
-                    }
-                    Key::Char(fourth_ch)
-                }
+            if fourth_ch == b'~' {
+                return Key::PageUp;
+            }
+            Key::Char(fourth_ch)
+        }
+
+        b'6' => {
+            let Some(fourth_ch) = read_char() else {
+                return Key::Char(third_ch);
+            };
+
+            if fourth_ch == b'~' {
+                return Key::PageDown;
+            }
+            Key::Char(fourth_ch)
+        }
+
+        b'3' => {
+            let Some(fourth_ch) = read_char() else {
+                return Key::Char(third_ch);
+            };
 
-                b'4' => {
-                    let Some(fourth_ch) = read_char() else {
-                        return Key::Char(third_ch);
-                    };
+            if fourth_ch == b'~' {
+                return Key::Delete;
+            }
+            Key::Char(fourth_ch)
+        }
+
+        b'1' => {
+            let Some(fourth_ch) = read_char() else {
+                return Key::Char(third_ch);
+            };
 
-                    if fourth_ch == b'~' {
-                        return Key::End; // End key on some terminals
+            if fourth_ch == b'~' {
+                return Key::Home; // Home key on some terminals
+            } else if fourth_ch == b';' {
+                let _ = read_char();
+
+                if let Some(code) = read_char() {
+                    match code {
+                        b'A' => return Key::ArrowUp,
+                        b'B' => return Key::ArrowDown,
+                        b'C' => return Key::ArrowRight,
+                        b'D' => return Key::ArrowLeft,
+                        _ => return Key::Char(code),
                     }
-                    Key::Char(fourth_ch)
                 }
-
-                _ => Key::Char(third_ch),
             }
+            Key::Char(fourth_ch)
         }
 
-        b'O' => {
-            let Some(third_ch) = read_char() else {
-                return Key::Char(second_ch);
+        b'4' => {
+            let Some(fourth_ch) = read_char() else {
+                return Key::Char(third_ch);
             };
 
-            match third_ch {
-                b'A' => Key::ArrowUp,    // Up arrow
-                b'B' => Key::ArrowDown,  // Down arrow
-                b'C' => Key::ArrowRight, // Right arrow
-                b'D' => Key::ArrowLeft,  // Left arrow
-                b'H' => Key::Home,       // Home
-                b'F' => Key::End,        // End
-                _ => Key::Char(third_ch),
+            if fourth_ch == b'~' {
+                return Key::End; // End key on some terminals
             }
+            Key::Char(fourth_ch)
         }
 
-        _ => Key::Char(second_ch),
+        _ => Key::Char(third_ch),
     }
 }
 
 pub(in crate::editor) fn read_key() -> Option<Key> {
-    let ch = read_char()?;
+    decode_key(read_char())
+}
+
+// Read a key, or None if nothing was typed within `timeout_ms` milliseconds
+pub(in crate::editor) fn read_key_timeout(timeout_ms: usize) -> Option<Key> {
+    decode_key(read_char_timeout(timeout_ms))
+}
+
+// Turn the first byte of a key and whatever follows it into a key
+fn decode_key(first: Option<u8>) -> Option<Key> {
+    let ch = first?;
 
     match ch {
         b'\r' => Some(Key::Enter),
@@ -186,6 +250,7 @@ pub(in crate::editor) fn read_key() -> Option<Key> {
         22 => Some(Key::PageDown),      // C-v (page-down)
         23 => Some(Key::Cut),           // C-w (kill-region/cut)
         25 => Some(Key::Paste),         // C-y (yank/paste)
+        31 => Some(Key::Undo),          // C-/ or C-_ (undo)
         0 => Some(Key::SetMark),        // C-space (set-mark, ASCII 0 = NUL)
 
         24 => {
@@ -196,6 +261,18 @@ pub(in crate::editor) fn read_key() -> Option<Key> {
                     return Some(Key::OpenFile);
                 } else if next_ch == 19 {
                     return Some(Key::SaveFile);
+                } else if next_ch == b'u' {
+                    return Some(Key::Undo);
+                } else if next_ch == b'b' {
+                    return Some(Key::SwitchBuffer);
+                } else if next_ch == b'k' {
+                    return Some(Key::KillBuffer);
+                } else if next_ch == 2 {
+                    return Some(Key::ListBuffers);
+                } else if next_ch == 23 {
+                    return Some(Key::WriteFile);
+                } else if next_ch == b'i' {
+                    return Some(Key::InsertFile);
                 }
 
                 return Some(Key::Combination([ch, next_ch]));
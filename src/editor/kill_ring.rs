@@ -1,23 +1,28 @@
 use crate::syscall::{MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE, mmap, munmap};
 
+const PAGE_SIZE: usize = 4096;
+
 // Error types for kill ring operations
 #[derive(Debug)]
 pub(in crate::editor) enum KillRingError {
     AllocationFailed,
-    BufferTooLarge,
 }
 
-// KillRing manages the clipboard buffer
+// Number of kills remembered before the oldest one is dropped
+const KILL_RING_MAX: usize = 16;
+
+// KillRing keeps the most recent kills, stored back to back from oldest to newest
 pub(in crate::editor) struct KillRing {
-    buffer: *mut u8, // Raw buffer for kill-ring content
-    size: usize,     // Current size of content
-    capacity: usize, // Fixed capacity (one page = 4096 bytes)
+    buffer: *mut u8,              // Raw buffer for kill-ring content
+    size: usize,                  // Total size of all entries
+    capacity: usize,              // Size of the mapping, grown on demand
+    lens: [usize; KILL_RING_MAX], // Entry lengths, oldest first
+    count: usize,                 // Number of entries
+    yank: usize,                  // Entry to yank, counted back from the newest
 }
 
 impl KillRing {
     pub(in crate::editor) fn new() -> Result<Self, KillRingError> {
-        const PAGE_SIZE: usize = 4096;
-
         let prot = PROT_READ | PROT_WRITE;
         let flags = MAP_PRIVATE | MAP_ANONYMOUS;
 
@@ -29,39 +34,133 @@ impl KillRing {
             buffer: buffer as *mut u8,
             size: 0,
             capacity: PAGE_SIZE,
+            lens: [0; KILL_RING_MAX],
+            count: 0,
+            yank: 0,
         })
     }
 
-    // Copy text to the kill ring
+    // Add text as the newest kill-ring entry
     pub(in crate::editor) fn copy(&mut self, text: &[u8]) -> Result<(), KillRingError> {
-        // Check if the text fits in the buffer
-        if text.len() > self.capacity {
-            return Err(KillRingError::BufferTooLarge);
+        // Make room by forgetting the oldest kill
+        if self.count == KILL_RING_MAX {
+            self.drop_oldest();
         }
 
-        // Copy text to buffer
-        for (i, &byte) in text.iter().enumerate() {
-            unsafe {
-                *self.buffer.add(i) = byte;
-            }
+        if self.size + text.len() > self.capacity {
+            self.resize_buffer(self.size + text.len())?;
         }
-        self.size = text.len();
 
+        unsafe {
+            core::ptr::copy_nonoverlapping(text.as_ptr(), self.buffer.add(self.size), text.len());
+        }
+        self.size += text.len();
+        self.lens[self.count] = text.len();
+        self.count += 1;
+
+        // The next yank starts from the new entry
+        self.yank = 0;
+        Ok(())
+    }
+
+    // Add text to the end of the newest entry
+    pub(in crate::editor) fn append(&mut self, text: &[u8]) -> Result<(), KillRingError> {
+        if self.count == 0 {
+            return self.copy(text);
+        }
+
+        if self.size + text.len() > self.capacity {
+            self.resize_buffer(self.size + text.len())?;
+        }
+
+        unsafe {
+            core::ptr::copy_nonoverlapping(text.as_ptr(), self.buffer.add(self.size), text.len());
+        }
+        self.size += text.len();
+        self.lens[self.count - 1] += text.len();
+        self.yank = 0;
         Ok(())
     }
 
-    // Get current content of the kill ring
+    // Add text to the start of the newest entry
+    pub(in crate::editor) fn prepend(&mut self, text: &[u8]) -> Result<(), KillRingError> {
+        if self.count == 0 {
+            return self.copy(text);
+        }
+
+        if self.size + text.len() > self.capacity {
+            self.resize_buffer(self.size + text.len())?;
+        }
+
+        // The newest entry is last, so only it has to move
+        let newest = self.lens[self.count - 1];
+        let start = self.size - newest;
+        unsafe {
+            core::ptr::copy(
+                self.buffer.add(start),
+                self.buffer.add(start + text.len()),
+                newest,
+            );
+            core::ptr::copy_nonoverlapping(text.as_ptr(), self.buffer.add(start), text.len());
+        }
+        self.size += text.len();
+        self.lens[self.count - 1] += text.len();
+        self.yank = 0;
+        Ok(())
+    }
+
+    // Get the entry the next yank inserts
     pub(in crate::editor) fn content(&self) -> &[u8] {
-        unsafe { core::slice::from_raw_parts(self.buffer, self.size) }
+        if self.count == 0 {
+            return &[];
+        }
+
+        let idx = self.count - 1 - self.yank;
+        let start: usize = self.lens[..idx].iter().sum();
+        unsafe { core::slice::from_raw_parts(self.buffer.add(start), self.lens[idx]) }
+    }
+
+    // Move the yank position to the previous entry, wrapping around to the newest
+    pub(in crate::editor) fn rotate(&mut self) {
+        if self.count > 0 {
+            self.yank = (self.yank + 1) % self.count;
+        }
     }
 
-    // Get the capacity of the kill ring
-    pub(in crate::editor) fn capacity(&self) -> usize {
-        self.capacity
+    fn drop_oldest(&mut self) {
+        let len = self.lens[0];
+        unsafe { core::ptr::copy(self.buffer.add(len), self.buffer, self.size - len) };
+        self.size -= len;
+
+        self.lens.copy_within(1..self.count, 0);
+        self.count -= 1;
+        self.yank = self.yank.min(self.count.saturating_sub(1));
     }
 
-    // Delete these API compatibility methods since they're handled in EditorState
-    // We don't need them anymore since they're properly implemented in EditorState
+    // Grow the mapping geometrically so that at least `required` bytes fit
+    fn resize_buffer(&mut self, required: usize) -> Result<(), KillRingError> {
+        const PAGE_MASK: usize = PAGE_SIZE - 1;
+
+        let mut new_capacity = self.capacity.max(PAGE_SIZE) * 2;
+        while new_capacity < required {
+            new_capacity *= 2;
+        }
+        new_capacity = (new_capacity + PAGE_MASK) & !PAGE_MASK;
+
+        let prot = PROT_READ | PROT_WRITE;
+        let flags = MAP_PRIVATE | MAP_ANONYMOUS;
+        let Ok(new_buffer) = mmap(0, new_capacity, prot, flags, usize::MAX, 0) else {
+            return Err(KillRingError::AllocationFailed);
+        };
+        let new_buffer = new_buffer as *mut u8;
+
+        unsafe { core::ptr::copy_nonoverlapping(self.buffer, new_buffer, self.size) };
+        let _ = munmap(self.buffer as usize, self.capacity);
+
+        self.buffer = new_buffer;
+        self.capacity = new_capacity;
+        Ok(())
+    }
 }
 
 impl Drop for KillRing {
@@ -75,11 +174,31 @@ impl Drop for KillRing {
 #[cfg(test)]
 mod kill_ring_tests {
     use super::*;
-    use crate::editor::EditorState;
     use crate::editor::file_buffer::tests::create_test_file_buffer;
+    use crate::editor::{EditorState, Key};
     use crate::termios::Winsize;
     use std::vec::Vec;
 
+    // Editor over a writable copy of the text
+    fn editor_with(text: &[u8]) -> EditorState {
+        let mut winsize = Winsize::new();
+        winsize.rows = 10;
+        winsize.cols = 40;
+        let mut state = EditorState::new(winsize, &[0; crate::syscall::MAX_PATH]);
+        state.buffer = create_test_file_buffer(text);
+
+        let prot = PROT_READ | PROT_WRITE;
+        let flags = MAP_PRIVATE | MAP_ANONYMOUS;
+        let capacity = (text.len() / PAGE_SIZE + 1) * PAGE_SIZE;
+        let Ok(addr) = mmap(0, capacity, prot, flags, usize::MAX, 0) else {
+            panic!("Failed to allocate test buffer: mmap error");
+        };
+        state.buffer.content = addr as *mut u8;
+        state.buffer.capacity = capacity;
+        unsafe { core::ptr::copy_nonoverlapping(text.as_ptr(), state.buffer.content, text.len()) };
+        state
+    }
+
     // Only testing basic kill ring functionality directly, not the integration with EditorState
     #[test]
     fn test_kill_ring_basic() {
@@ -102,21 +221,183 @@ mod kill_ring_tests {
     }
 
     #[test]
-    fn test_buffer_too_large() {
-        // Create a killring with small capacity for testing
+    fn test_kill_ring_rotate() {
+        let Ok(mut kill_ring) = KillRing::new() else {
+            panic!("Failed to create kill-ring");
+        };
+        assert!(kill_ring.content().is_empty(), "New kill ring is empty");
+
+        kill_ring.copy(b"first").unwrap();
+        kill_ring.copy(b"second").unwrap();
+        kill_ring.copy(b"third").unwrap();
+        assert_eq!(
+            kill_ring.content(),
+            b"third",
+            "Yank starts at the newest kill"
+        );
+
+        // Rotation walks back through older kills and wraps around
+        kill_ring.rotate();
+        assert_eq!(kill_ring.content(), b"second");
+        kill_ring.rotate();
+        assert_eq!(kill_ring.content(), b"first");
+        kill_ring.rotate();
+        assert_eq!(kill_ring.content(), b"third");
+
+        // A new kill resets the yank position
+        kill_ring.rotate();
+        kill_ring.copy(b"fourth").unwrap();
+        assert_eq!(kill_ring.content(), b"fourth");
+    }
+
+    #[test]
+    fn test_kill_ring_append_prepend() {
         let Ok(mut kill_ring) = KillRing::new() else {
             panic!("Failed to create kill-ring");
         };
 
-        // Try to copy text larger than capacity
-        let large_text = std::iter::repeat_n(b'X', kill_ring.capacity() + 1).collect::<Vec<u8>>();
-        let result = kill_ring.copy(&large_text);
+        // Appending to an empty ring starts a new entry
+        kill_ring.append(b"one").unwrap();
+        kill_ring.copy(b"middle").unwrap();
+        kill_ring.append(b" end").unwrap();
+        kill_ring.prepend(b"start ").unwrap();
+        assert_eq!(kill_ring.content(), b"start middle end");
+
+        // Older entries are left alone
+        kill_ring.rotate();
+        assert_eq!(kill_ring.content(), b"one");
+    }
+
+    #[test]
+    fn test_kill_ring_drops_oldest() {
+        let Ok(mut kill_ring) = KillRing::new() else {
+            panic!("Failed to create kill-ring");
+        };
 
-        // Verify the error
-        assert!(result.is_err(), "Copy should fail with buffer too large");
-        assert!(
-            matches!(result, Err(KillRingError::BufferTooLarge)),
-            "Expected BufferTooLarge error"
+        for i in 0..=KILL_RING_MAX {
+            kill_ring.copy(&[b'a' + u8::try_from(i).unwrap()]).unwrap();
+        }
+
+        // The very first kill fell off the end of the ring
+        for _ in 0..KILL_RING_MAX - 1 {
+            kill_ring.rotate();
+        }
+        assert_eq!(kill_ring.content(), b"b", "Oldest remaining entry");
+        kill_ring.rotate();
+        let newest = b'a' + u8::try_from(KILL_RING_MAX).unwrap();
+        assert_eq!(
+            kill_ring.content(),
+            [newest],
+            "Rotation wraps to the newest"
         );
+
+        // Kills far larger than a page are kept alongside the others
+        let big = std::iter::repeat_n(b'X', 3 * PAGE_SIZE + 1).collect::<Vec<u8>>();
+        kill_ring.copy(&big).unwrap();
+        assert_eq!(kill_ring.content(), big.as_slice());
+        kill_ring.rotate();
+        assert_eq!(kill_ring.content(), [newest], "Older kills survive growth");
+    }
+
+    #[test]
+    fn test_yank_pop() {
+        use crate::terminal::tests::{disable_test_mode, enable_test_mode};
+        enable_test_mode();
+
+        let mut state = editor_with(b"");
+        state.kill_ring.copy(b"old\nkill").unwrap();
+        state.kill_ring.copy(b"new").unwrap();
+
+        // C-y inserts the newest kill
+        state.paste_from_kill_ring().unwrap();
+        state.last_key = Some(Key::Paste);
+        assert_eq!(state.buffer.get_line(0), Some(&b"new"[..]));
+
+        // M-y replaces it with the previous one
+        state.yank_pop().unwrap();
+        state.last_key = Some(Key::YankPop);
+        assert_eq!(state.buffer.get_line(0), Some(&b"old"[..]));
+        assert_eq!(state.buffer.get_line(1), Some(&b"kill"[..]));
+        assert_eq!((state.file_row, state.file_col), (1, 4));
+
+        // And wraps back around to the newest
+        state.yank_pop().unwrap();
+        assert_eq!(state.buffer.count_lines(), 1);
+        assert_eq!(state.buffer.get_line(0), Some(&b"new"[..]));
+
+        // M-y does nothing unless it follows a yank
+        state.last_key = Some(Key::ArrowLeft);
+        state.yank_pop().unwrap();
+        assert_eq!(state.buffer.get_line(0), Some(&b"new"[..]));
+
+        disable_test_mode();
+    }
+
+    #[test]
+    fn test_copy_large_selection() {
+        use crate::terminal::tests::{disable_test_mode, enable_test_mode};
+        enable_test_mode();
+
+        let mut text = std::iter::repeat_n(b'a', 2 * PAGE_SIZE).collect::<Vec<u8>>();
+        text.extend_from_slice(b"\nbcd\n");
+        let mut state = editor_with(&text);
+
+        // Select everything up to the middle of the second line
+        state.set_mark().unwrap();
+        state.file_row = 1;
+        state.file_col = 2;
+        state.copy_selection().unwrap();
+
+        assert_eq!(state.kill_ring.content(), &text[..2 * PAGE_SIZE + 3]);
+        assert!(!state.mark_active, "Copy deactivates the mark");
+
+        disable_test_mode();
+    }
+
+    #[test]
+    fn test_consecutive_kills_append() {
+        use crate::terminal::tests::{disable_test_mode, enable_test_mode};
+        enable_test_mode();
+
+        let mut state = editor_with(b"first\n\nthird\nrest");
+
+        // Each C-k kills either the rest of a line or its newline
+        for _ in 0..5 {
+            state.kill_line().unwrap();
+            state.last_key = Some(Key::KillLine);
+        }
+        assert_eq!(state.kill_ring.content(), b"first\n\nthird\n");
+        assert_eq!(state.buffer.get_line(0), Some(&b"rest"[..]));
+
+        // Another command in between starts a new entry
+        state.last_key = Some(Key::ArrowRight);
+        state.file_col = 2;
+        state.kill_line().unwrap();
+        assert_eq!(state.kill_ring.content(), b"st");
+        state.kill_ring.rotate();
+        assert_eq!(state.kill_ring.content(), b"first\n\nthird\n");
+
+        disable_test_mode();
+    }
+
+    #[test]
+    fn test_backward_cut_prepends() {
+        use crate::terminal::tests::{disable_test_mode, enable_test_mode};
+        enable_test_mode();
+
+        let mut state = editor_with(b"abc def");
+
+        // Kill "def" forwards, then select "abc " with the cursor at its start
+        state.file_col = 4;
+        state.kill_line().unwrap();
+        state.last_key = Some(Key::KillLine);
+        state.set_mark().unwrap();
+        state.file_col = 0;
+        state.cut_selection().unwrap();
+
+        assert_eq!(state.kill_ring.content(), b"abc def");
+        assert_eq!(state.buffer.size, 0);
+
+        disable_test_mode();
     }
 }
@@ -0,0 +1,281 @@
+use crate::syscall::{MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE, mmap, munmap};
+use core::cell::Cell;
+
+const PAGE_SIZE: usize = 4096;
+const WORD: usize = core::mem::size_of::<usize>();
+
+// LineIndex keeps the start offset of every line in a gap array. Entries
+// before the gap hold absolute offsets, entries after it hold the distance
+// from the end of the text, so an edit only touches entries next to the gap.
+// The index is built lazily on first lookup, which is why all state lives in
+// cells: lookups happen through shared references.
+pub(in crate::editor) struct LineIndex {
+    starts: Cell<*mut usize>, // Raw mmap-backed entry storage
+    capacity: Cell<usize>,    // Number of entries that fit into the mapping
+    count: Cell<usize>,       // Number of lines
+    gap: Cell<usize>,         // Index of the first entry after the gap
+    valid: Cell<bool>,        // Whether the entries match the text
+}
+
+impl LineIndex {
+    pub(in crate::editor) const fn new() -> Self {
+        Self {
+            starts: Cell::new(core::ptr::null_mut()),
+            capacity: Cell::new(0),
+            count: Cell::new(0),
+            gap: Cell::new(0),
+            valid: Cell::new(false),
+        }
+    }
+
+    pub(in crate::editor) fn is_valid(&self) -> bool {
+        self.valid.get()
+    }
+
+    // Drop the entries; they are rebuilt on the next lookup
+    pub(in crate::editor) fn invalidate(&self) {
+        self.valid.set(false);
+    }
+
+    pub(in crate::editor) fn line_count(&self) -> usize {
+        self.count.get()
+    }
+
+    // Rebuild all entries by scanning the text once
+    pub(in crate::editor) fn rebuild(&self, before: &[u8], after: &[u8]) {
+        self.valid.set(false);
+        let lines = 1 + before.iter().chain(after).filter(|&&b| b == b'\n').count();
+        if !self.reserve(lines) {
+            return;
+        }
+
+        self.count.set(0);
+        self.gap.set(0);
+        self.push(0);
+        for (pos, &byte) in before.iter().chain(after).enumerate() {
+            if byte == b'\n' {
+                self.push(pos + 1);
+            }
+        }
+        self.valid.set(true);
+    }
+
+    // Start offset of a line in a text of `size` bytes
+    pub(in crate::editor) fn line_start(&self, line_idx: usize, size: usize) -> Option<usize> {
+        if line_idx >= self.count.get() {
+            return None;
+        }
+
+        if line_idx < self.gap.get() {
+            Some(self.read(line_idx))
+        } else {
+            Some(size - self.read(line_idx + self.gap_len()))
+        }
+    }
+
+    // Line containing the given offset
+    pub(in crate::editor) fn line_of(&self, pos: usize, size: usize) -> usize {
+        // Binary search for the last line starting at or before pos
+        let mut low = 0;
+        let mut high = self.count.get();
+        while high - low > 1 {
+            let mid = low + (high - low) / 2;
+            match self.line_start(mid, size) {
+                Some(start) if start <= pos => low = mid,
+                _ => high = mid,
+            }
+        }
+        low
+    }
+
+    // Account for `bytes` inserted at `pos` into a text of `size` bytes
+    pub(in crate::editor) fn insert(&self, pos: usize, bytes: &[u8], size: usize) {
+        if !self.valid.get() {
+            return;
+        }
+
+        // Lines starting after pos move with the text, which their
+        // end-relative entries already do
+        self.move_gap(pos, size);
+        for (offset, &byte) in bytes.iter().enumerate() {
+            if byte != b'\n' {
+                continue;
+            }
+            if !self.reserve(self.count.get() + 1) {
+                self.invalidate();
+                return;
+            }
+            self.push(pos + offset + 1);
+        }
+    }
+
+    // Account for `len` bytes removed at `pos` from a text of `size` bytes
+    pub(in crate::editor) fn delete(&self, pos: usize, len: usize, size: usize) {
+        if !self.valid.get() {
+            return;
+        }
+
+        self.move_gap(pos, size);
+
+        // Lines starting inside the removed range lost their newline
+        while self.gap.get() < self.count.get() {
+            let start = size - self.read(self.gap.get() + self.gap_len());
+            if start > pos + len {
+                break;
+            }
+            self.count.set(self.count.get() - 1);
+        }
+    }
+
+    fn gap_len(&self) -> usize {
+        self.capacity.get() - self.count.get()
+    }
+
+    // Move the gap so that exactly the lines starting at or before pos are in front of it
+    fn move_gap(&self, pos: usize, size: usize) {
+        let gap_len = self.gap_len();
+
+        // Line 0 always starts at 0 and never leaves the front part
+        while self.gap.get() > 1 && self.read(self.gap.get() - 1) > pos {
+            let gap = self.gap.get() - 1;
+            self.write(gap + gap_len, size - self.read(gap));
+            self.gap.set(gap);
+        }
+
+        while self.gap.get() < self.count.get() {
+            let gap = self.gap.get();
+            let start = size - self.read(gap + gap_len);
+            if start > pos {
+                break;
+            }
+            self.write(gap, start);
+            self.gap.set(gap + 1);
+        }
+    }
+
+    // Append an absolute entry in front of the gap
+    fn push(&self, start: usize) {
+        let gap = self.gap.get();
+        self.write(gap, start);
+        self.gap.set(gap + 1);
+        self.count.set(self.count.get() + 1);
+    }
+
+    // Make sure `entries` entries fit into the mapping
+    fn reserve(&self, entries: usize) -> bool {
+        let capacity = self.capacity.get();
+        if entries <= capacity {
+            return true;
+        }
+
+        let mut new_capacity = if capacity == 0 {
+            PAGE_SIZE / WORD
+        } else {
+            capacity * 2
+        };
+        while new_capacity < entries {
+            new_capacity *= 2;
+        }
+
+        let prot = PROT_READ | PROT_WRITE;
+        let flags = MAP_PRIVATE | MAP_ANONYMOUS;
+        let Ok(addr) = mmap(0, new_capacity * WORD, prot, flags, usize::MAX, 0) else {
+            return false;
+        };
+        let new_starts = addr as *mut usize;
+
+        // Keep the gap at the same index, moving the entries after it to the new end
+        let old_starts = self.starts.get();
+        if !old_starts.is_null() {
+            let gap = self.gap.get();
+            let tail = self.count.get() - gap;
+            unsafe {
+                core::ptr::copy_nonoverlapping(old_starts, new_starts, gap);
+                core::ptr::copy_nonoverlapping(
+                    old_starts.add(capacity - tail),
+                    new_starts.add(new_capacity - tail),
+                    tail,
+                );
+            }
+            let _ = munmap(old_starts as usize, capacity * WORD);
+        }
+
+        self.starts.set(new_starts);
+        self.capacity.set(new_capacity);
+        true
+    }
+
+    fn read(&self, idx: usize) -> usize {
+        unsafe { *self.starts.get().add(idx) }
+    }
+
+    fn write(&self, idx: usize, value: usize) {
+        unsafe { *self.starts.get().add(idx) = value };
+    }
+}
+
+impl Drop for LineIndex {
+    fn drop(&mut self) {
+        let starts = self.starts.get();
+        if !starts.is_null() && self.capacity.get() > 0 {
+            let _ = munmap(starts as usize, self.capacity.get() * WORD);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn starts(index: &LineIndex, size: usize) -> Vec<usize> {
+        (0..index.line_count())
+            .map(|i| index.line_start(i, size).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_line_index_edits() {
+        let index = LineIndex::new();
+        assert!(!index.is_valid(), "Index starts out unbuilt");
+
+        let text = b"ab\ncd\nef";
+        index.rebuild(text, &[]);
+        assert!(index.is_valid());
+        assert_eq!(starts(&index, text.len()), vec![0, 3, 6]);
+
+        // "ab\nc\nXd\nef": a newline typed inside the second line
+        index.insert(4, b"\nX", 8);
+        assert_eq!(starts(&index, 10), vec![0, 3, 5, 8]);
+        assert_eq!(index.line_of(0, 10), 0);
+        assert_eq!(index.line_of(4, 10), 1);
+        assert_eq!(index.line_of(9, 10), 3);
+
+        // Insert at the very start of a line keeps that line's start
+        index.insert(3, b"zz", 10);
+        assert_eq!(starts(&index, 12), vec![0, 3, 7, 10]);
+
+        // Removing "b\nzzc\n" joins three lines into one
+        index.delete(1, 6, 12);
+        assert_eq!(starts(&index, 6), vec![0, 4]);
+
+        // Edits before earlier lines move the gap back
+        index.insert(0, b"\n", 6);
+        assert_eq!(starts(&index, 7), vec![0, 1, 5]);
+        assert_eq!(index.line_start(3, 7), None);
+    }
+
+    #[test]
+    fn test_line_index_grows() {
+        let index = LineIndex::new();
+        index.rebuild(b"", &[]);
+        assert_eq!(index.line_count(), 1);
+
+        let lines = 3 * PAGE_SIZE / WORD;
+        for i in 0..lines {
+            index.insert(i * 2, b"x\n", i * 2);
+        }
+        assert_eq!(index.line_count(), lines + 1);
+        assert_eq!(index.line_start(lines, lines * 2), Some(lines * 2));
+        assert_eq!(index.line_of(lines, lines * 2), lines / 2);
+    }
+}
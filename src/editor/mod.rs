@@ -1,26 +1,44 @@
+mod auto_save;
+mod backup;
+mod buffer_list;
+mod completion;
+mod diff;
+mod directory;
+mod dired;
 mod editor_state;
 mod file_buffer;
 mod key_handlers;
 mod kill_ring;
+mod line_index;
+mod regex;
+mod screen;
+mod search_history;
 mod search_state;
 mod syntax_highlight;
+mod undo_log;
 
+pub(in crate::editor) use backup::{BackupMode, Original};
+pub(in crate::editor) use buffer_list::{BufferList, BufferState};
+pub(in crate::editor) use completion::Candidates;
 pub(in crate::editor) use editor_state::EditorState;
-pub(in crate::editor) use file_buffer::{FileBuffer, FileBufferError};
-pub(in crate::editor) use key_handlers::{Key, read_key};
+pub(in crate::editor) use file_buffer::{FileBuffer, FileBufferError, FileStamp};
+pub(in crate::editor) use key_handlers::{Key, read_key, read_key_timeout};
 pub(in crate::editor) use kill_ring::{KillRing, KillRingError};
+pub(in crate::editor) use line_index::LineIndex;
+pub(in crate::editor) use screen::Screen;
 pub(in crate::editor) use search_state::SearchState;
 pub(in crate::editor) use syntax_highlight::SyntaxHighlighter;
+pub(in crate::editor) use undo_log::UndoLog;
+
+use core::cell::Cell;
 
 use crate::syscall::{
     MAP_ANONYMOUS, MAP_PRIVATE, O_RDONLY, PROT_READ, PROT_WRITE, SEEK_END, SEEK_SET, STDOUT, close,
-    lseek, mmap, open,
+    lseek, mmap, munmap, open,
 };
 use crate::syscall::{SysResult, read};
-use crate::terminal::move_cursor;
-use crate::terminal::{
-    clear_screen, enter_alternate_screen, exit_alternate_screen, get_winsize, save_cursor,
-};
+use crate::terminal::{clear_screen, enter_alternate_screen, exit_alternate_screen, get_winsize};
+use crate::terminal::{move_cursor, write_usize_to_buf};
 use crate::termios::Winsize;
 use crate::{
     syscall::{MAX_PATH, putchar, write_buf},
@@ -31,6 +49,7 @@ pub enum EditorError {
     LoadFile,
     MMapFile,
     FileBuffer,
+    ReadOnly,
     SysError(usize),
 }
 
@@ -41,8 +60,13 @@ impl From<usize> for EditorError {
 }
 
 impl From<FileBufferError> for EditorError {
-    fn from(_: FileBufferError) -> Self {
-        EditorError::FileBuffer
+    fn from(error: FileBufferError) -> Self {
+        match error {
+            FileBufferError::ReadOnly => EditorError::ReadOnly,
+            FileBufferError::BufferFull | FileBufferError::InvalidOperation => {
+                EditorError::FileBuffer
+            }
+        }
     }
 }
 
@@ -63,6 +87,14 @@ fn map_existing_file(fd: usize, file_size: usize) -> Result<FileBuffer, EditorEr
         size: file_size,
         capacity: file_size,
         modified: false,
+        tail: Cell::new(0),
+        lines: LineIndex::new(),
+        undo: UndoLog::new(),
+        changed: Cell::new(0),
+        original: Original::loaded(),
+        read_only: false,
+        auto_save_pending: false,
+        stamp: None,
     })
 }
 
@@ -70,9 +102,12 @@ fn open_file(file_path: &[u8]) -> Result<FileBuffer, EditorError> {
     match open(file_path, O_RDONLY) {
         Ok(0) => Err(EditorError::LoadFile),
         Ok(fd) => {
+            let stamp = FileStamp::of_fd(fd);
             let file_size = lseek(fd, 0, SEEK_END)?;
             lseek(fd, 0, SEEK_SET)?;
-            map_existing_file(fd, file_size)
+            let mut buffer = map_existing_file(fd, file_size)?;
+            buffer.stamp = stamp;
+            Ok(buffer)
         }
         Err(_) => create_empty_buffer(4096),
     }
@@ -83,6 +118,7 @@ fn insert_newline(state: &mut EditorState, row: usize, col: usize) -> Result<(),
         let result = state.print_error(match e {
             FileBufferError::BufferFull => "Buffer is full",
             FileBufferError::InvalidOperation => "Failed to insert newline",
+            FileBufferError::ReadOnly => "Buffer is read-only",
         });
 
         if result.is_err() {
@@ -191,11 +227,13 @@ fn process_backspace(state: &mut EditorState) -> SysResult {
 
     let result = state.buffer.backspace_at(state.file_row, state.file_col);
     if let Err(e) = result {
-        state.print_error(if matches!(e, FileBufferError::InvalidOperation) {
-            "Can't delete at this position"
-        } else {
-            "Error deleting character"
-        })?;
+        state.print_error(
+            e.describe(if matches!(e, FileBufferError::InvalidOperation) {
+                "Can't delete at this position"
+            } else {
+                "Error deleting character"
+            }),
+        )?;
         return Ok(0);
     }
 
@@ -216,21 +254,25 @@ fn process_delete(state: &mut EditorState) -> SysResult {
     if state.file_col < current_line_len {
         let result = state.buffer.delete_char(state.file_row, state.file_col);
         if let Err(e) = result {
-            state.print_error(if matches!(e, FileBufferError::InvalidOperation) {
-                "Can't delete at this position"
-            } else {
-                "Error deleting character"
-            })?;
+            state.print_error(
+                e.describe(if matches!(e, FileBufferError::InvalidOperation) {
+                    "Can't delete at this position"
+                } else {
+                    "Error deleting character"
+                }),
+            )?;
         }
     } else if state.file_row + 1 < line_count {
         if let Some(line_end) = state.buffer.find_line_end(state.file_row) {
             let result = state.buffer.delete_at_position(line_end);
             if let Err(e) = result {
-                state.print_error(if matches!(e, FileBufferError::InvalidOperation) {
-                    "Can't join lines"
-                } else {
-                    "Error deleting newline"
-                })?;
+                state.print_error(e.describe(
+                    if matches!(e, FileBufferError::InvalidOperation) {
+                        "Can't join lines"
+                    } else {
+                        "Error deleting newline"
+                    },
+                ))?;
             }
         }
     }
@@ -241,11 +283,11 @@ fn process_delete(state: &mut EditorState) -> SysResult {
 fn process_char(state: &mut EditorState, ch: u8) -> SysResult {
     let result = state.buffer.insert_char(state.file_row, state.file_col, ch);
     if let Err(e) = result {
-        state.print_error(if matches!(e, FileBufferError::BufferFull) {
+        state.print_error(e.describe(if matches!(e, FileBufferError::BufferFull) {
             "Buffer is full"
         } else {
             "Failed to insert character"
-        })?;
+        }))?;
         return Ok(0);
     }
 
@@ -278,76 +320,631 @@ fn process_cursor_key(key: Key, state: &mut EditorState) -> SysResult {
     state.draw_screen()
 }
 
-#[cfg(not(tarpaulin_include))]
-fn read_filename_input(prompt: &str, state: &EditorState) -> Result<[u8; MAX_PATH], EditorError> {
-    let mut filename = [0u8; MAX_PATH];
-    let mut len: usize = 0;
+// Completion hook for prompts: extends the input in place and returns its new
+// length, collecting the candidates to show when there is more than one
+type Completer = fn(&EditorState, &mut [u8; MAX_PATH], usize, &mut Candidates) -> usize;
 
-    loop {
-        if let Some(key) = read_key() {
-            match key {
-                Key::Enter if len > 0 => {
-                    filename[len] = 0;
-                    break;
-                }
-                Key::Char(ch) if len < 62 && (ch.is_ascii_graphic() || ch == b' ') => {
-                    filename[len] = ch;
-                    len += 1;
-                    putchar(ch)?;
+// Read a line of input on the message line, starting with `initial`; None if
+// cancelled with C-g
+#[cfg(not(tarpaulin_include))]
+fn read_prompt_input(
+    state: &mut EditorState,
+    prompt: &[&[u8]],
+    initial: &[u8],
+    complete: Option<Completer>,
+) -> Result<Option<[u8; MAX_PATH]>, EditorError> {
+    let row = state.winsize.rows as usize - 1;
+    let prompt_len = prompt.iter().map(|part| part.len()).sum::<usize>();
+    state.print_prompt(prompt)?;
+
+    let mut input = [0u8; MAX_PATH];
+    let mut len = initial.len().min(MAX_PATH - 1);
+    input[..len].copy_from_slice(&initial[..len]);
+    write_buf(&input[..len])?;
+
+    let mut candidates = Candidates::new();
+    let mut listed = false;
+    let result = loop {
+        let Some(key) = read_key() else {
+            continue;
+        };
+        match key {
+            Key::Enter => break Some(input),
+            Key::ExitSearch | Key::Escape => break None,
+            Key::Char(b'\t') => {
+                if let Some(complete) = complete {
+                    candidates.clear();
+                    len = complete(state, &mut input, len, &mut candidates);
+                    if candidates.total() > 1 {
+                        state.draw_candidates(&candidates)?;
+                        listed = true;
+                    }
+                    move_cursor(row, prompt_len)?;
+                    write_buf(&input[..len])?;
+                    clear_line()?;
                 }
-                Key::Backspace if len > 0 => {
+            }
+            Key::Char(ch)
+                if len < MAX_PATH - 1 && (ch == b' ' || ch.is_ascii_graphic() || ch >= 0x80) =>
+            {
+                input[len] = ch;
+                len += 1;
+                putchar(ch)?;
+            }
+            Key::Backspace if len > 0 => {
+                // Remove a whole UTF-8 sequence
+                len -= 1;
+                while len > 0 && input[len] & 0xC0 == 0x80 {
                     len -= 1;
-                    move_cursor(state.winsize.rows as usize - 1, prompt.len())?;
-                    write_buf(&filename[..len])?;
-                    clear_line()?;
                 }
-                _ => {}
+                move_cursor(row, prompt_len)?;
+                write_buf(&input[..len])?;
+                clear_line()?;
             }
+            _ => {}
         }
+    };
+
+    if listed {
+        state.draw_screen()?;
     }
+    let Some(mut input) = result else {
+        state.print_message("Quit")?;
+        return Ok(None);
+    };
+    input[len] = 0;
+    move_cursor(row, 0)?;
+    clear_line()?;
+    Ok(Some(input))
+}
 
-    Ok(filename)
+// Read a file name with completion, starting in the directory of the shown
+// file; a leading ~/ stands for the home directory
+#[cfg(not(tarpaulin_include))]
+fn read_file_name(
+    state: &mut EditorState,
+    prompt: &[&[u8]],
+) -> Result<Option<[u8; MAX_PATH]>, EditorError> {
+    let mut dir = [0u8; MAX_PATH];
+    let current = buffer_list::name_of(&state.filename);
+    let dir_len = current
+        .iter()
+        .rposition(|&b| b == b'/')
+        .map_or(0, |slash| slash + 1);
+    dir[..dir_len].copy_from_slice(&current[..dir_len]);
+
+    let Some(input) = read_prompt_input(state, prompt, &dir[..dir_len], Some(complete_file_name))?
+    else {
+        return Ok(None);
+    };
+
+    let mut home = [0u8; MAX_PATH];
+    let home_len = completion::home_dir(&mut home);
+    let mut filename = [0u8; MAX_PATH];
+    if completion::expand_home(
+        buffer_list::name_of(&input),
+        &home[..home_len],
+        &mut filename,
+    )
+    .is_err()
+    {
+        state.print_error("File name too long")?;
+        return Ok(None);
+    }
+    Ok(Some(filename))
+}
+
+#[cfg(not(tarpaulin_include))]
+fn complete_file_name(
+    _state: &EditorState,
+    input: &mut [u8; MAX_PATH],
+    len: usize,
+    candidates: &mut Candidates,
+) -> usize {
+    let mut home = [0u8; MAX_PATH];
+    let home_len = completion::home_dir(&mut home);
+    completion::complete_file_name(input, len, &home[..home_len], candidates)
+}
+
+fn complete_buffer_name(
+    state: &EditorState,
+    input: &mut [u8; MAX_PATH],
+    len: usize,
+    _candidates: &mut Candidates,
+) -> usize {
+    state.complete_buffer_name(input, len)
+}
+
+// Answers to the question whether a modified buffer should be saved
+enum SaveAnswer {
+    Save,    // y: save this buffer
+    Discard, // n: leave it unsaved
+    SaveAll, // !: save this and every following buffer without asking
+    Cancel,  // C-g: abort the whole operation
+}
+
+// Ask whether the modified shown buffer should be saved
+#[cfg(not(tarpaulin_include))]
+fn ask_save(state: &mut EditorState) -> Result<SaveAnswer, EditorError> {
+    let mut name = [0u8; MAX_PATH];
+    name.copy_from_slice(&state.filename);
+    let name = match buffer_list::name_of(&name) {
+        b"" => &b"*scratch*"[..],
+        name => name,
+    };
+
+    state.draw_screen()?;
+    state.draw_status_bar()?;
+    state.print_prompt(&[b"Buffer ", name, b" modified; save? (y/n/!) "])?;
+    loop {
+        match read_key() {
+            Some(Key::Char(b'y' | b'Y')) => return Ok(SaveAnswer::Save),
+            Some(Key::Char(b'n' | b'N')) => return Ok(SaveAnswer::Discard),
+            Some(Key::Char(b'!')) => return Ok(SaveAnswer::SaveAll),
+            Some(Key::ExitSearch | Key::Escape) => return Ok(SaveAnswer::Cancel),
+            _ => {}
+        }
+    }
+}
+
+// Ask a yes or no question on the message line; C-g counts as no
+#[cfg(not(tarpaulin_include))]
+fn ask_yes_no(state: &EditorState, prompt: &[&[u8]]) -> Result<bool, EditorError> {
+    state.print_prompt(prompt)?;
+    loop {
+        match read_key() {
+            Some(Key::Char(b'y' | b'Y')) => return Ok(true),
+            Some(Key::Char(b'n' | b'N') | Key::ExitSearch | Key::Escape) => return Ok(false),
+            _ => {}
+        }
+    }
+}
+
+// Offer to load the auto-save data of the file given on the command line
+// when it is newer than the file
+#[cfg(not(tarpaulin_include))]
+fn offer_recovery(state: &mut EditorState) -> Result<bool, EditorError> {
+    if !auto_save::has_newer_auto_save(&state.filename) {
+        return Ok(false);
+    }
+
+    let mut auto_save = [0u8; MAX_PATH];
+    if !auto_save::auto_save_path(&state.filename, &mut auto_save) {
+        return Ok(false);
+    }
+    let name = buffer_list::name_of(&auto_save);
+    if !ask_yes_no(
+        state,
+        &[b"Auto-save file ", name, b" is newer; recover? (y/n) "],
+    )? {
+        return Ok(false);
+    }
+
+    // The recovered text still replaces the original file on the first save
+    let mut recovered = open_file(&auto_save)?;
+    recovered.original = core::mem::replace(&mut state.buffer.original, Original::none());
+    recovered.stamp = state.buffer.stamp;
+    recovered.modified = true;
+    recovered.undo.forget_saved();
+    state.buffer = recovered;
+
+    state.screen.invalidate();
+    state.draw_screen()?;
+    Ok(true)
+}
+
+// Make sure the shown buffer may be dropped, saving it if the user wants to.
+// Returns false when the operation should be cancelled.
+#[cfg(not(tarpaulin_include))]
+fn confirm_save(state: &mut EditorState, save_all: &mut bool) -> Result<bool, EditorError> {
+    if !state.buffer.has_unsaved_changes() {
+        return Ok(true);
+    }
+
+    let answer = if *save_all {
+        SaveAnswer::SaveAll
+    } else {
+        ask_save(state)?
+    };
+    match answer {
+        SaveAnswer::Cancel => {
+            state.print_message("Quit")?;
+            Ok(false)
+        }
+        SaveAnswer::Discard => Ok(true),
+        SaveAnswer::Save => save_current_buffer(state),
+        SaveAnswer::SaveAll => {
+            *save_all = true;
+            save_current_buffer(state)
+        }
+    }
+}
+
+// Save the shown buffer, asking for a file name if it has none yet
+#[cfg(not(tarpaulin_include))]
+fn save_current_buffer(state: &mut EditorState) -> Result<bool, EditorError> {
+    if buffer_list::name_of(&state.filename).is_empty() {
+        let Some(filename) = read_file_name(state, &[b"File to save in: "])? else {
+            return Ok(false);
+        };
+        if buffer_list::name_of(&filename).is_empty() {
+            return Ok(false);
+        }
+        state.set_filename(&filename);
+    }
+
+    if !resolve_disk_change(state)? {
+        return Ok(false);
+    }
+    write_current_buffer(state)
+}
+
+#[cfg(not(tarpaulin_include))]
+fn write_current_buffer(state: &mut EditorState) -> Result<bool, EditorError> {
+    if state
+        .buffer
+        .save_to_file(&state.filename, state.backup_mode)
+        .is_err()
+    {
+        state.print_error(save_error(state))?;
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+// Confirm a save, warning when the old version could not be backed up
+fn print_saved(state: &mut EditorState) -> SysResult {
+    if state.buffer.original.take_failure() {
+        state.print_warning("File saved, but the backup could not be written")
+    } else {
+        state.print_message("File saved successfully")
+    }
+}
+
+fn save_error(state: &EditorState) -> &'static str {
+    if state.buffer.read_only {
+        "Buffer is read-only"
+    } else {
+        "Error saving file"
+    }
 }
 
+enum DiskAnswer {
+    Revert,
+    Overwrite,
+    Diff,
+    Cancel,
+}
+
+// Another program rewrote the visited file; returns true when the buffer
+// should be written over it anyway
 #[cfg(not(tarpaulin_include))]
-fn setup_file_open_prompt(state: &mut EditorState, prompt: &str) -> Result<(), EditorError> {
-    save_cursor()?;
-    state.print_message(prompt)?;
-    move_cursor(state.winsize.rows as usize - 1, prompt.len())?;
+fn resolve_disk_change(state: &mut EditorState) -> Result<bool, EditorError> {
+    if !state.buffer.changed_on_disk(&state.filename) {
+        return Ok(true);
+    }
+
+    let mut name = [0u8; MAX_PATH];
+    name.copy_from_slice(&state.filename);
+    let name = buffer_list::name_of(&name);
+    state.print_prompt(&[
+        name,
+        b" changed on disk; revert, overwrite or diff? (r/o/d) ",
+    ])?;
+    let answer = loop {
+        match read_key() {
+            Some(Key::Char(b'r' | b'R')) => break DiskAnswer::Revert,
+            Some(Key::Char(b'o' | b'O')) => break DiskAnswer::Overwrite,
+            Some(Key::Char(b'd' | b'D')) => break DiskAnswer::Diff,
+            Some(Key::ExitSearch | Key::Escape) => break DiskAnswer::Cancel,
+            _ => {}
+        }
+    };
+
+    match answer {
+        DiskAnswer::Overwrite => return Ok(true),
+        DiskAnswer::Revert => revert_buffer(state)?,
+        DiskAnswer::Diff => show_disk_diff(state)?,
+        DiskAnswer::Cancel => {
+            state.print_message("Quit")?;
+        }
+    }
+    Ok(false)
+}
+
+// Replace the text with the file on disk, keeping the cursor as close as
+// possible to where it was
+fn reload_buffer(state: &mut EditorState) -> Result<(), EditorError> {
+    state.buffer = if dired::is_listing(&state.filename) {
+        read_listing(&state.filename)?
+    } else {
+        open_file(&state.filename)?
+    };
+    state.mark_active = false;
+
+    let last_row = state.buffer.count_lines().saturating_sub(1);
+    state.file_row = state.file_row.min(last_row);
+    state.file_col = state
+        .file_col
+        .min(state.buffer.line_length(state.file_row, state.tab_size));
+    state.preferred_col = state.file_col;
+    state.scroll_to_cursor();
+    Ok(())
+}
+
+#[cfg(not(tarpaulin_include))]
+fn revert_buffer(state: &mut EditorState) -> Result<(), EditorError> {
+    reload_buffer(state)?;
+    state.screen.invalidate();
+    show_switched_buffer(state, "Reverted from disk")
+}
+
+// Reload buffers without edits of their own whose files changed on disk
+#[cfg(not(tarpaulin_include))]
+fn auto_revert_buffers(state: &mut EditorState) -> Result<(), EditorError> {
+    if !state.buffer.has_unsaved_changes() && state.buffer.changed_on_disk(&state.filename) {
+        revert_buffer(state)?;
+    }
+
+    for idx in 0..state.buffers.len() {
+        let outdated = state.buffers.get(idx).is_some_and(|entry| {
+            !entry.buffer.has_unsaved_changes() && entry.buffer.changed_on_disk(&entry.filename)
+        });
+        if outdated {
+            state.exchange_buffer(idx);
+            let reloaded = reload_buffer(state);
+            state.exchange_buffer(idx);
+            reloaded?;
+        }
+    }
+    Ok(())
+}
+
+// Show how the buffer differs from the file on disk in a *Diff* buffer. The
+// diff is a single hunk running from the first changed line to the last one.
+#[cfg(not(tarpaulin_include))]
+fn show_disk_diff(state: &mut EditorState) -> Result<(), EditorError> {
+    const DIFF_NAME: &[u8] = b"*Diff*";
+
+    // Unnamed buffers and the diff itself have no file to compare with
+    let name = buffer_list::name_of(&state.filename);
+    if name.is_empty() || name == DIFF_NAME {
+        state.print_error("Buffer is not visiting a file")?;
+        return Ok(());
+    }
+
+    let disk = open_file(&state.filename)?;
+    let mut diff = create_empty_buffer(4096)?;
+    let written = diff::write_diff(
+        disk.text_range(0, disk.size),
+        state.buffer.text_range(0, state.buffer.size),
+        name,
+        &mut diff,
+    );
+    if written.is_err() {
+        state.print_error("Failed to compare with the file on disk")?;
+        return Ok(());
+    }
+    diff.modified = false;
+
+    // A fresh diff takes the place of the previous one
+    if let Some(idx) = state.buffers.find(DIFF_NAME) {
+        state.switch_to_buffer(idx);
+        state.replace_buffer(BufferState::new(diff, DIFF_NAME));
+    } else if !state.visit_buffer(diff, DIFF_NAME) {
+        state.print_error("Too many buffers open")?;
+        return Ok(());
+    }
+    show_switched_buffer(state, "C-x b switches back to the file")
+}
+
+// On C-l, offer to deal with the visited file having changed on disk
+#[cfg(not(tarpaulin_include))]
+fn check_disk_change(state: &mut EditorState) -> Result<(), EditorError> {
+    if state.buffer.changed_on_disk(&state.filename)
+        && resolve_disk_change(state)?
+        && write_current_buffer(state)?
+    {
+        print_saved(state)?;
+    }
+    Ok(())
+}
+
+// Offer to save every modified buffer before leaving; false cancels quitting
+#[cfg(not(tarpaulin_include))]
+fn confirm_quit(state: &mut EditorState) -> Result<bool, EditorError> {
+    let mut save_all = false;
+    if !confirm_save(state, &mut save_all)? {
+        return Ok(false);
+    }
+
+    // Other buffers are shown in turn while asking about them
+    for idx in 0..state.buffers.len() {
+        let modified = state
+            .buffers
+            .get(idx)
+            .is_some_and(|entry| entry.buffer.has_unsaved_changes());
+        if !modified {
+            continue;
+        }
+
+        state.exchange_buffer(idx);
+        let confirmed = confirm_save(state, &mut save_all);
+        state.exchange_buffer(idx);
+        if !confirmed? {
+            state.draw_screen()?;
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+// Redraw the text area after the shown buffer changed
+fn show_switched_buffer(state: &mut EditorState, message: &str) -> Result<(), EditorError> {
+    state.draw_screen()?;
+    state.print_message(message)?;
     Ok(())
 }
 
+const LISTING_HELP: &str = "Enter: open, ^: up, g: refresh";
+
+// Build the listing of a directory as a buffer without edits
+fn read_listing(dir: &[u8]) -> Result<FileBuffer, EditorError> {
+    let mut listing = create_empty_buffer(4096)?;
+    dired::write_listing(dir, &mut listing)?;
+    listing.modified = false;
+    listing.read_only = true;
+    Ok(listing)
+}
+
+// Show the listing of a directory with the cursor on the entry `select`, or
+// on the first entry
+#[cfg(not(tarpaulin_include))]
+fn visit_directory(state: &mut EditorState, dir: &[u8], select: &[u8]) -> Result<(), EditorError> {
+    let mut name = [0u8; MAX_PATH];
+    dired::listing_name(dir, &mut name)?;
+    let name = buffer_list::name_of(&name);
+
+    if name == buffer_list::name_of(&state.filename) {
+        reload_buffer(state)?;
+    } else if let Some(idx) = state.buffers.find(name) {
+        state.switch_to_buffer(idx);
+        reload_buffer(state)?;
+    } else {
+        let Ok(listing) = read_listing(name) else {
+            state.print_error("Cannot read directory")?;
+            return Ok(());
+        };
+        if !state.visit_buffer(listing, name) {
+            state.print_error("Error: Too many buffers")?;
+            return Ok(());
+        }
+    }
+
+    let row = (dired::FIRST_ENTRY..state.buffer.count_lines())
+        .find(|&row| state.buffer.get_line(row).and_then(dired::entry_name) == Some(select))
+        .unwrap_or(dired::FIRST_ENTRY);
+    state.file_row = row;
+    state.file_col = dired::NAME_COL;
+    state.preferred_col = state.file_col;
+    state.scroll_to_cursor();
+    show_switched_buffer(state, LISTING_HELP)
+}
+
+// Open the file or directory on the cursor line of a listing
+#[cfg(not(tarpaulin_include))]
+fn open_listing_entry(state: &mut EditorState) -> Result<(), EditorError> {
+    let mut dir = [0u8; MAX_PATH];
+    dir.copy_from_slice(&state.filename);
+    let dir = buffer_list::name_of(&dir);
+
+    let entry = state
+        .buffer
+        .get_line(state.file_row)
+        .filter(|_| state.file_row > 0)
+        .and_then(dired::entry_name);
+    let mut path = [0u8; MAX_PATH];
+    let joined = match entry {
+        Some(b"..") => return listing_parent(state),
+        Some(name) => backup::join(&mut path, &[dir, name]),
+        None => {
+            state.print_message("No file on this line")?;
+            return Ok(());
+        }
+    };
+    if joined.is_err() {
+        state.print_error("File name too long")?;
+        return Ok(());
+    }
+
+    if dired::is_directory(&path) {
+        visit_directory(state, &path, b"")
+    } else {
+        finalize_file_open(state, path)
+    }
+}
+
+// Go up to the listing of the parent directory, with the cursor on the one
+// just left
+#[cfg(not(tarpaulin_include))]
+fn listing_parent(state: &mut EditorState) -> Result<(), EditorError> {
+    let mut dir = [0u8; MAX_PATH];
+    dir.copy_from_slice(&state.filename);
+    let dir = buffer_list::name_of(&dir);
+    let mut parent = [0u8; MAX_PATH];
+    dired::parent_dir(dir, &mut parent)?;
+
+    // The entry for a/b/ is b, unless going up added another ../
+    let trimmed = dir.strip_suffix(b"/").unwrap_or(dir);
+    let child = trimmed
+        .iter()
+        .rposition(|&b| b == b'/')
+        .map_or(trimmed, |slash| &trimmed[slash + 1..]);
+    let child = if matches!(child, b"." | b"..") {
+        &b""[..]
+    } else {
+        child
+    };
+    visit_directory(state, &parent, child)
+}
+
+// Keys of a directory listing; editing keys fall through to the normal
+// handlers, where the read-only buffer refuses them
+#[cfg(not(tarpaulin_include))]
+fn process_listing_key(key: Key, state: &mut EditorState) -> Option<SysResult> {
+    let result = match key {
+        Key::Enter => open_listing_entry(state),
+        Key::Char(b'^') => listing_parent(state),
+        Key::Char(b'g') => {
+            reload_buffer(state).and_then(|()| show_switched_buffer(state, "Listing refreshed"))
+        }
+        Key::Char(b'n') => return Some(process_cursor_key(Key::ArrowDown, state)),
+        Key::Char(b'p') => return Some(process_cursor_key(Key::ArrowUp, state)),
+        _ => return None,
+    };
+    if result.is_err() {
+        return Some(state.print_error("Cannot open that entry"));
+    }
+    Some(Ok(0))
+}
+
 #[cfg(not(tarpaulin_include))]
 fn finalize_file_open(
     state: &mut EditorState,
     filename: [u8; MAX_PATH],
 ) -> Result<(), EditorError> {
-    use crate::terminal::{clear_screen, restore_cursor};
+    let name = buffer_list::name_of(&filename);
+    if name.is_empty() {
+        state.print_message("No file name given")?;
+        return Ok(());
+    }
+    if dired::is_directory(&filename) {
+        return visit_directory(state, &filename, b"");
+    }
 
-    move_cursor(state.winsize.rows as usize - 1, 0)?;
-    clear_line()?;
-    restore_cursor()?;
+    // A file that is already open is shown instead of being loaded again
+    if name == buffer_list::name_of(&state.filename) {
+        state.print_message("File is already open")?;
+        return Ok(());
+    }
+    if let Some(idx) = state.buffers.find(name) {
+        state.switch_to_buffer(idx);
+        return show_switched_buffer(state, "Switched to existing buffer");
+    }
 
     match open_file(&filename) {
         Ok(new_buffer) => {
-            state.file_row = 0;
-            state.file_col = 0;
-            state.scroll_row = 0;
-            state.scroll_col = 0;
-            state.buffer = new_buffer;
-            clear_screen()?;
-            state.draw_screen()?;
-            state.filename.fill(0);
-            state.filename[..filename.len()].copy_from_slice(&filename);
+            if !state.visit_buffer(new_buffer, &filename) {
+                state.print_error("Error: Too many buffers")?;
+                return Ok(());
+            }
 
             let message = if state.buffer.is_modified() {
                 "New file created"
             } else {
                 "File opened successfully"
             };
-            state.print_message(message)?;
-            move_cursor(0, 0)?;
-            Ok(())
+            show_switched_buffer(state, message)
         }
         Err(e) => {
             state.print_error("Error: Failed to create buffer")?;
@@ -358,17 +955,496 @@ fn finalize_file_open(
 
 #[cfg(not(tarpaulin_include))]
 fn handle_open_file(state: &mut EditorState) -> Result<(), EditorError> {
-    let prompt = "Enter filename: ";
-    setup_file_open_prompt(state, prompt)?;
-    let filename = read_filename_input(prompt, state)?;
+    let Some(filename) = read_file_name(state, &[b"Enter filename: "])? else {
+        return Ok(());
+    };
     finalize_file_open(state, filename)
 }
 
+#[cfg(not(tarpaulin_include))]
+fn handle_switch_buffer(state: &mut EditorState) -> Result<(), EditorError> {
+    let default = state.buffers.get(0).map_or(&b""[..], BufferState::name);
+    let mut default_name = [0u8; MAX_PATH];
+    default_name[..default.len()].copy_from_slice(default);
+    let default = &default_name[..default.len()];
+
+    let prompt: &[&[u8]] = if default.is_empty() {
+        &[b"Switch to buffer: "]
+    } else {
+        &[b"Switch to buffer (default ", default, b"): "]
+    };
+    let Some(input) = read_prompt_input(state, prompt, b"", Some(complete_buffer_name))? else {
+        return Ok(());
+    };
+
+    let name = buffer_list::name_of(&input);
+    let name = if name.is_empty() { default } else { name };
+    if name == buffer_list::name_of(&state.filename) {
+        return Ok(());
+    }
+
+    let Some(idx) = state.buffers.find(name) else {
+        state.print_error("No such buffer")?;
+        return Ok(());
+    };
+    state.switch_to_buffer(idx);
+    show_switched_buffer(state, "Switched buffer")
+}
+
+#[cfg(not(tarpaulin_include))]
+fn handle_kill_buffer(state: &mut EditorState) -> Result<(), EditorError> {
+    let mut current = [0u8; MAX_PATH];
+    current.copy_from_slice(&state.filename);
+    let current = buffer_list::name_of(&current);
+
+    let Some(input) = read_prompt_input(
+        state,
+        &[b"Kill buffer (default ", current, b"): "],
+        b"",
+        Some(complete_buffer_name),
+    )?
+    else {
+        return Ok(());
+    };
+    let name = buffer_list::name_of(&input);
+    let name = if name.is_empty() { current } else { name };
+
+    // None stands for the shown buffer
+    let target = if name == current {
+        None
+    } else if let Some(idx) = state.buffers.find(name) {
+        Some(idx)
+    } else {
+        state.print_error("No such buffer")?;
+        return Ok(());
+    };
+
+    // Modified buffers are offered for saving first
+    let mut save_all = false;
+    let confirmed = match target {
+        None => confirm_save(state, &mut save_all),
+        Some(idx) => {
+            state.exchange_buffer(idx);
+            let confirmed = confirm_save(state, &mut save_all);
+            state.exchange_buffer(idx);
+            confirmed
+        }
+    };
+    if !confirmed? {
+        state.draw_screen()?;
+        return Ok(());
+    }
+
+    if let Some(idx) = target {
+        state.buffers.remove(idx);
+    } else {
+        // Show the most recently used buffer, or a fresh one if this was the last
+        let next = match state.buffers.remove(0) {
+            Some(next) => next,
+            None => BufferState::new(create_empty_buffer(4096)?, b""),
+        };
+        state.replace_buffer(next);
+    }
+    show_switched_buffer(state, "Buffer killed")
+}
+
+#[cfg(not(tarpaulin_include))]
+fn handle_list_buffers(state: &mut EditorState) -> Result<(), EditorError> {
+    let mut selected = 0;
+    loop {
+        state.draw_buffer_list(selected)?;
+        state.print_message("Enter: switch, C-g: close")?;
+        move_cursor(selected + 1, 0)?;
+
+        match read_key() {
+            Some(Key::ArrowUp) => selected = selected.saturating_sub(1),
+            Some(Key::ArrowDown) if selected < state.buffers.len() => selected += 1,
+            Some(Key::Enter) => {
+                if selected > 0 {
+                    state.switch_to_buffer(selected - 1);
+                }
+                break;
+            }
+            Some(Key::ExitSearch | Key::Escape | Key::Char(b'q')) => break,
+            _ => {}
+        }
+    }
+    show_switched_buffer(state, "")
+}
+
+// Commands without a key of their own, run by name with M-x
+type Command = fn(&mut EditorState) -> Result<(), EditorError>;
+const COMMANDS: [(&[u8], Command); 5] = [
+    (b"auto-revert-mode", toggle_auto_revert),
+    (b"backup-mode", cycle_backup_mode),
+    (b"query-replace-regexp", query_replace_regexp),
+    (b"revert-buffer", revert_buffer_command),
+    (b"write-region", write_region),
+];
+
+fn complete_command(
+    _state: &EditorState,
+    input: &mut [u8; MAX_PATH],
+    len: usize,
+    _candidates: &mut Candidates,
+) -> usize {
+    buffer_list::complete_prefix(input, len, COMMANDS.iter().map(|&(name, _)| name))
+}
+
+#[cfg(not(tarpaulin_include))]
+fn handle_execute_command(state: &mut EditorState) -> Result<(), EditorError> {
+    let Some(input) = read_prompt_input(state, &[b"M-x "], b"", Some(complete_command))? else {
+        return Ok(());
+    };
+    let name = buffer_list::name_of(&input);
+    let Some((_, command)) = COMMANDS.iter().find(|&&(command, _)| command == name) else {
+        state.print_error("No such command")?;
+        return Ok(());
+    };
+    command(state)
+}
+
+// Confirm writing over a file that is not the one being visited
+#[cfg(not(tarpaulin_include))]
+fn confirm_overwrite(state: &EditorState, filename: &[u8]) -> Result<bool, EditorError> {
+    if FileStamp::of_path(filename).is_none() {
+        return Ok(true);
+    }
+    let name = buffer_list::name_of(filename);
+    if ask_yes_no(state, &[b"File ", name, b" exists; overwrite? (y/n) "])? {
+        return Ok(true);
+    }
+    state.print_message("Quit")?;
+    Ok(false)
+}
+
+// Save the buffer under a new name and visit that file from now on
+#[cfg(not(tarpaulin_include))]
+fn handle_write_file(state: &mut EditorState) -> Result<(), EditorError> {
+    let Some(filename) = read_file_name(state, &[b"Write file: "])? else {
+        return Ok(());
+    };
+    let name = buffer_list::name_of(&filename);
+    if name.is_empty() {
+        return Ok(());
+    }
+
+    if name == buffer_list::name_of(&state.filename) {
+        if save_current_buffer(state)? {
+            print_saved(state)?;
+        }
+        return Ok(());
+    }
+    if state.buffers.find(name).is_some() {
+        state.print_error("Another buffer is visiting that file")?;
+        return Ok(());
+    }
+    if !confirm_overwrite(state, &filename)? {
+        return Ok(());
+    }
+
+    // The backup belongs to the old file, which keeps its name until the
+    // new one is written
+    let original = core::mem::replace(&mut state.buffer.original, Original::none());
+    if state
+        .buffer
+        .save_to_file(&filename, state.backup_mode)
+        .is_err()
+    {
+        state.buffer.original = original;
+        state.print_error(save_error(state))?;
+        return Ok(());
+    }
+    auto_save::remove_auto_save(&state.filename);
+    state.set_filename(&filename);
+    state.draw_screen()?;
+    print_saved(state)?;
+    Ok(())
+}
+
+// Insert the contents of a file at the cursor, leaving them selected
+#[cfg(not(tarpaulin_include))]
+fn handle_insert_file(state: &mut EditorState) -> Result<(), EditorError> {
+    let Some(filename) = read_file_name(state, &[b"Insert file: "])? else {
+        return Ok(());
+    };
+    if buffer_list::name_of(&filename).is_empty() {
+        return Ok(());
+    }
+    let Ok(fd) = open(&filename, O_RDONLY) else {
+        state.print_error("Cannot open file")?;
+        return Ok(());
+    };
+
+    let inserted = insert_file_contents(state, fd);
+    let _ = close(fd);
+    match inserted {
+        Ok(0) => state.print_message("File is empty")?,
+        Ok(_) => {
+            state.draw_screen()?;
+            state.print_message("Inserted file")?
+        }
+        Err(EditorError::ReadOnly) => state.print_error("Buffer is read-only")?,
+        Err(_) => state.print_error("Failed to insert file")?,
+    };
+    Ok(())
+}
+
+// Map the whole file and insert it in one go
+fn insert_file_contents(state: &mut EditorState, fd: usize) -> Result<usize, EditorError> {
+    let size = lseek(fd, 0, SEEK_END)?;
+    if size == 0 {
+        return Ok(0);
+    }
+
+    let addr = mmap(0, size, PROT_READ, MAP_PRIVATE, fd, 0)?;
+    let bytes = unsafe { core::slice::from_raw_parts(addr as *const u8, size) };
+    let inserted = state.insert_block(bytes);
+    let _ = munmap(addr, size);
+    inserted?;
+    Ok(size)
+}
+
+// Answers while stepping through the matches of query-replace
+enum ReplaceAnswer {
+    Replace, // y or SPC: replace and go on
+    Skip,    // n or DEL: keep this one and go on
+    All,     // !: replace this and all the rest without asking
+    Last,    // .: replace this one and stop
+    Quit,    // q, RET or C-g: stop here
+}
+
+#[cfg(not(tarpaulin_include))]
+fn read_replace_answer(
+    state: &EditorState,
+    prompt: &[&[u8]],
+) -> Result<ReplaceAnswer, EditorError> {
+    state.print_prompt(prompt)?;
+    loop {
+        match read_key() {
+            Some(Key::Char(b'y' | b' ')) => return Ok(ReplaceAnswer::Replace),
+            Some(Key::Char(b'n') | Key::Backspace) => return Ok(ReplaceAnswer::Skip),
+            Some(Key::Char(b'!')) => return Ok(ReplaceAnswer::All),
+            Some(Key::Char(b'.')) => return Ok(ReplaceAnswer::Last),
+            Some(Key::Char(b'q') | Key::Enter | Key::ExitSearch | Key::Escape) => {
+                return Ok(ReplaceAnswer::Quit);
+            }
+            _ => {}
+        }
+    }
+}
+
+// Replace matches of a string, or a regex with \1 and the like standing for
+// its groups, from the cursor to the end of the buffer, asking about each
+// one. All replacements are undone together.
+#[cfg(not(tarpaulin_include))]
+fn handle_query_replace(state: &mut EditorState, regex: bool) -> Result<(), EditorError> {
+    let kind: &[u8] = if regex { b" regexp" } else { b"" };
+    let Some(from) = read_prompt_input(state, &[b"Query replace", kind, b": "], b"", None)? else {
+        return Ok(());
+    };
+    let from = buffer_list::name_of(&from);
+    if from.is_empty() {
+        return Ok(());
+    }
+    let prompt: &[&[u8]] = &[b"Query replace", kind, b" ", from, b" with: "];
+    let Some(to) = read_prompt_input(state, prompt, b"", None)? else {
+        return Ok(());
+    };
+    let to = buffer_list::name_of(&to);
+
+    // The matches are shown with the search highlight
+    let mut search = SearchState::new();
+    search.query[..from.len()].copy_from_slice(from);
+    search.query_len = from.len();
+    search.case_sensitive = state.search.case_sensitive;
+    search.regex = regex;
+    search.mode = true;
+    if let Some(error) = search.regex_error() {
+        state.print_error(error.message())?;
+        return Ok(());
+    }
+    core::mem::swap(&mut state.search, &mut search);
+
+    let prompt: &[&[u8]] = &[
+        b"Query replacing",
+        kind,
+        b" ",
+        from,
+        b" with ",
+        to,
+        b": (y, n, !, ., q) ",
+    ];
+    let replaced = query_replace(state, prompt, to);
+    core::mem::swap(&mut state.search, &mut search);
+    let replaced = replaced?;
+
+    state.scroll_to_cursor();
+    state.draw_screen()?;
+    let mut digits = [0u8; 20];
+    let len = write_usize_to_buf(&mut digits, replaced);
+    let noun: &[u8] = if replaced == 1 {
+        b" occurrence"
+    } else {
+        b" occurrences"
+    };
+    state.print_prompt(&[b"Replaced ", &digits[..len], noun])?;
+    Ok(())
+}
+
+// Step through the matches of the search state, returning how many were replaced
+#[cfg(not(tarpaulin_include))]
+fn query_replace(
+    state: &mut EditorState,
+    prompt: &[&[u8]],
+    to: &[u8],
+) -> Result<usize, EditorError> {
+    let mut replaced = 0;
+    let mut ask = true;
+    let (mut row, mut col) = (state.file_row, state.file_col);
+    let mut matcher = state.search.matcher();
+    let mut text = create_empty_buffer(4096)?;
+
+    while let Some((match_row, match_col, len)) =
+        state.search.find_forward_no_wrap(&state.buffer, row, col)
+    {
+        let answer = if ask {
+            state.search.match_row = match_row;
+            state.search.match_col = match_col;
+            state.search.match_len = len;
+            state.file_row = match_row;
+            state.file_col = match_col;
+            state.scroll_to_cursor();
+            state.draw_screen()?;
+            read_replace_answer(state, prompt)?
+        } else {
+            ReplaceAnswer::Replace
+        };
+
+        // An empty regex match moves on by one so the loop ends
+        match answer {
+            ReplaceAnswer::Skip => {
+                (row, col) = (match_row, match_col + len.max(1));
+                continue;
+            }
+            ReplaceAnswer::Quit => break,
+            ReplaceAnswer::All => ask = false,
+            ReplaceAnswer::Replace | ReplaceAnswer::Last => {}
+        }
+
+        // The expansion is built apart since it reads the line it replaces
+        let line = state.buffer.get_line(match_row).unwrap_or_default();
+        let mut expanded = Ok(());
+        text.clear_generated();
+        state
+            .search
+            .replacement(&mut matcher, line, match_col, to, |piece| {
+                expanded = expanded.and_then(|()| text.append_text(piece));
+            });
+        if let Err(e) = expanded {
+            state.print_error(e.describe("Failed to replace"))?;
+            return Err(e.into());
+        }
+        let replacement = text.text_range(0, text.size);
+        if let Err(e) = state.replace_text(match_row, match_col, len, replacement) {
+            state.print_error(e.describe("Failed to replace"))?;
+            return Err(e.into());
+        }
+        replaced += 1;
+        (row, col) = (state.file_row, state.file_col + usize::from(len == 0));
+        if matches!(answer, ReplaceAnswer::Last) {
+            break;
+        }
+    }
+    Ok(replaced)
+}
+
+// Query-replace reading the string to replace as a regex
+#[cfg(not(tarpaulin_include))]
+fn query_replace_regexp(state: &mut EditorState) -> Result<(), EditorError> {
+    handle_query_replace(state, true)
+}
+
+// Write the selected text to a file
+#[cfg(not(tarpaulin_include))]
+fn write_region(state: &mut EditorState) -> Result<(), EditorError> {
+    let Some((start, end)) = state.selection_bounds() else {
+        state.print_message("No selection (mark not active)")?;
+        return Ok(());
+    };
+    let Some(filename) = read_file_name(state, &[b"Write region to file: "])? else {
+        return Ok(());
+    };
+    if buffer_list::name_of(&filename).is_empty() || !confirm_overwrite(state, &filename)? {
+        return Ok(());
+    }
+
+    let mut region = create_empty_buffer(4096)?;
+    let written = region
+        .append_text(state.buffer.text_range(start, end))
+        .is_ok()
+        && region.write_copy(&filename).is_ok();
+    if written {
+        state.print_message("Wrote region")?;
+    } else {
+        state.print_error("Error writing region")?;
+    }
+    Ok(())
+}
+
+// Switch between no backups, name~ and numbered backups
+fn cycle_backup_mode(state: &mut EditorState) -> Result<(), EditorError> {
+    state.backup_mode = state.backup_mode.next();
+    state.print_message(state.backup_mode.describe())?;
+    Ok(())
+}
+
+fn toggle_auto_revert(state: &mut EditorState) -> Result<(), EditorError> {
+    state.auto_revert = !state.auto_revert;
+    state.print_message(if state.auto_revert {
+        "Auto-revert mode enabled"
+    } else {
+        "Auto-revert mode disabled"
+    })?;
+    Ok(())
+}
+
+// Throw away the edits and read the file again, asking first if there are any
+#[cfg(not(tarpaulin_include))]
+fn revert_buffer_command(state: &mut EditorState) -> Result<(), EditorError> {
+    let mut name = [0u8; MAX_PATH];
+    name.copy_from_slice(&state.filename);
+    let name = buffer_list::name_of(&name);
+    if FileStamp::of_path(&state.filename).is_none() {
+        state.print_error("Buffer is not visiting an existing file")?;
+        return Ok(());
+    }
+
+    if state.buffer.has_unsaved_changes()
+        && !ask_yes_no(state, &[b"Discard edits and reread ", name, b"? (y/n) "])?
+    {
+        state.print_message("Quit")?;
+        return Ok(());
+    }
+    revert_buffer(state)
+}
+
 fn handle_save_file(state: &mut EditorState) -> SysResult {
-    match state.buffer.save_to_file(&state.filename) {
-        Ok(_) => Ok(state.print_message("File saved successfully")?),
+    if buffer_list::name_of(&state.filename).is_empty() {
+        let saved = save_current_buffer(state).unwrap_or(false);
+        return if saved { print_saved(state) } else { Ok(0) };
+    }
+
+    if !resolve_disk_change(state).unwrap_or(false) {
+        return Ok(0);
+    }
+    match state
+        .buffer
+        .save_to_file(&state.filename, state.backup_mode)
+    {
+        Ok(_) => print_saved(state),
         Err(e) => {
-            state.print_error("Error saving file")?;
+            state.print_error(save_error(state))?;
             Err(e)
         }
     }
@@ -390,6 +1466,7 @@ fn check_terminal_resize(state: &mut EditorState) -> SysResult {
 
         // Redraw everything
         clear_screen()?;
+        state.screen.invalidate();
         state.draw_screen()?;
         state.draw_status_bar()?;
         state.print_message("Terminal resized")?;
@@ -405,9 +1482,14 @@ fn process_search_key(state: &mut EditorState, key: Key) -> SysResult {
         Key::Escape | Key::ExitSearch => state.cancel_search(),
         Key::Enter => state.accept_search(),
         Key::Backspace => state.remove_search_char(),
-        Key::Search => handle_search_direction(state, true),
-        Key::ReverseSearch => handle_search_direction(state, false),
+        Key::Search | Key::RegexSearch => handle_search_direction(state, true),
+        Key::ReverseSearch | Key::RegexReverseSearch => handle_search_direction(state, false),
         Key::ToggleCase => state.toggle_search_case_sensitivity(),
+        Key::Cut => state.yank_word_into_search(),
+        Key::YankPop => state.yank_kill_into_search(),
+        Key::HistoryPrev => state.walk_search_history(true),
+        Key::HistoryNext => state.walk_search_history(false),
+        Key::WordSearch => state.toggle_search_whole_word(),
         Key::Char(ch) => {
             if ch.is_ascii_graphic() || ch == b' ' {
                 state.add_search_char(ch)
@@ -433,14 +1515,21 @@ fn handle_search_direction(state: &mut EditorState, forward: bool) -> SysResult
 fn process_command_key(key: Key, state: &mut EditorState, running: &mut bool) -> Option<SysResult> {
     match key {
         Key::Quit => {
-            *running = false;
+            // Unsaved changes are offered for saving; C-g keeps the editor open
+            if let Ok(quit) = confirm_quit(state) {
+                *running = !quit;
+            }
             Some(Ok(0))
         }
         Key::Refresh => {
+            let _ = check_disk_change(state);
             let resize_result = check_terminal_resize(state);
             if resize_result.is_err() {
                 match clear_screen() {
-                    Ok(_) => Some(state.draw_screen()),
+                    Ok(_) => {
+                        state.screen.invalidate();
+                        Some(state.draw_screen())
+                    }
                     Err(e) => Some(Err(e)),
                 }
             } else {
@@ -451,14 +1540,48 @@ fn process_command_key(key: Key, state: &mut EditorState, running: &mut bool) ->
             let _ = handle_open_file(state);
             Some(Ok(0))
         }
+        Key::SwitchBuffer => {
+            let _ = handle_switch_buffer(state);
+            Some(Ok(0))
+        }
+        Key::KillBuffer => {
+            let _ = handle_kill_buffer(state);
+            Some(Ok(0))
+        }
+        Key::ListBuffers => {
+            let _ = handle_list_buffers(state);
+            Some(Ok(0))
+        }
+        Key::ExecuteCommand => {
+            let _ = handle_execute_command(state);
+            Some(Ok(0))
+        }
+        Key::WriteFile => {
+            let _ = handle_write_file(state);
+            Some(Ok(0))
+        }
+        Key::InsertFile => {
+            let _ = handle_insert_file(state);
+            Some(Ok(0))
+        }
+        Key::QueryReplace => {
+            let _ = handle_query_replace(state, false);
+            Some(Ok(0))
+        }
         Key::SaveFile => Some(handle_save_file(state)),
         Key::Search => Some(state.start_search(false)),
         Key::ReverseSearch => Some(state.start_search(true)),
+        Key::RegexSearch => Some(state.start_regex_search(false)),
+        Key::RegexReverseSearch => Some(state.start_regex_search(true)),
+        Key::WordSearch => Some(state.start_word_search()),
         Key::SetMark => Some(state.set_mark()),
         Key::Cut => Some(state.cut_selection()),
         Key::Copy => Some(state.copy_selection()),
         Key::Paste => Some(state.paste_from_kill_ring()),
+        Key::YankPop => Some(state.yank_pop()),
         Key::KillLine => Some(state.kill_line()),
+        Key::Undo => Some(state.undo()),
+        Key::Redo => Some(state.redo()),
         Key::Escape => {
             if state.mark_active {
                 Some(state.clear_mark())
@@ -476,6 +1599,21 @@ fn process_command_key(key: Key, state: &mut EditorState, running: &mut bool) ->
 fn process_normal_key(state: &mut EditorState, key: Key, running: &mut bool) -> SysResult {
     state.print_message("")?;
 
+    // Consecutive self-inserted characters are undone as a single step
+    let typing = matches!(key, Key::Char(_)) && matches!(state.last_key, Some(Key::Char(_)));
+    if !typing {
+        state.buffer.undo_boundary(state.file_row, state.file_col);
+    }
+
+    let listing_result = if dired::is_listing(&state.filename) {
+        process_listing_key(key, state)
+    } else {
+        None
+    };
+    if let Some(result) = listing_result {
+        return result;
+    }
+
     // First try processing it as a command key
     if let Some(result) = process_command_key(key, state, running) {
         return result;
@@ -540,6 +1678,14 @@ fn create_empty_buffer(capacity: usize) -> Result<FileBuffer, EditorError> {
         size: 0,
         capacity,
         modified: true,
+        tail: Cell::new(0),
+        lines: LineIndex::new(),
+        undo: UndoLog::new(),
+        changed: Cell::new(0),
+        original: Original::none(),
+        read_only: false,
+        auto_save_pending: false,
+        stamp: None,
     })
 }
 
@@ -556,6 +1702,13 @@ fn setup_editor_state() -> Result<(EditorState, bool), EditorError> {
 
     state.buffer = if is_empty_filename {
         create_empty_buffer(4096)?
+    } else if dired::is_directory(&filename) {
+        let mut name = [0u8; MAX_PATH];
+        dired::listing_name(&filename, &mut name)?;
+        state.set_filename(&name);
+        state.file_row = dired::FIRST_ENTRY;
+        state.file_col = dired::NAME_COL;
+        read_listing(&name)?
     } else {
         open_file(&filename)?
     };
@@ -566,9 +1719,18 @@ fn setup_editor_state() -> Result<(EditorState, bool), EditorError> {
 // Main editor loop
 fn editor_loop(mut state: EditorState) -> Result<(), EditorError> {
     let mut running = true;
+    let mut keys_since_auto_save = 0;
+    let mut idle_ms = 0;
 
     while running {
-        if let Some(key) = read_key() {
+        // Auto-revert mode wakes up more often to look at the files
+        let timeout = if state.auto_revert {
+            auto_save::AUTO_REVERT_INTERVAL_MS
+        } else {
+            auto_save::AUTO_SAVE_IDLE_MS
+        };
+        let key = read_key_timeout(timeout);
+        if let Some(key) = key {
             // Handle keys based on mode
             let result = if key == Key::ExitSearch && state.mark_active {
                 // Special handling for Ctrl+G to cancel selection when mark is active
@@ -584,6 +1746,25 @@ fn editor_loop(mut state: EditorState) -> Result<(), EditorError> {
             if let Err(e) = result {
                 return Err(e.into());
             }
+            state.last_key = Some(key);
+            keys_since_auto_save += 1;
+            idle_ms = 0;
+        } else {
+            idle_ms += timeout;
+            if state.auto_revert && auto_revert_buffers(&mut state).is_err() {
+                state.print_error("Auto-revert failed")?;
+            }
+        }
+
+        // Edits are auto-saved after a burst of typing or once the user pauses
+        if idle_ms >= auto_save::AUTO_SAVE_IDLE_MS
+            || keys_since_auto_save >= auto_save::AUTO_SAVE_KEYS
+        {
+            keys_since_auto_save = 0;
+            idle_ms = 0;
+            if !state.auto_save_buffers() {
+                state.print_error("Auto-saving failed")?;
+            }
         }
 
         if let Err(e) = state.draw_status_bar() {
@@ -619,6 +1800,10 @@ pub fn run_editor() -> Result<(), EditorError> {
     // Show appropriate message
     let message = if is_empty_filename {
         "Empty buffer created"
+    } else if dired::is_listing(&state.filename) {
+        LISTING_HELP
+    } else if offer_recovery(&mut state)? {
+        "Recovered auto-save data; C-x C-s saves it to the file"
     } else if state.buffer.is_modified() {
         "New file created"
     } else {
@@ -642,6 +1827,7 @@ pub mod tests {
     use super::*;
     use crate::editor::file_buffer::tests::create_test_file_buffer;
     use crate::syscall::{O_CREAT, O_RDONLY, O_TRUNC, O_WRONLY, close, write};
+    use crate::terminal::save_cursor;
     use crate::terminal::tests::{disable_test_mode, enable_test_mode};
 
     // Helper function for testing
@@ -1312,4 +2498,78 @@ pub mod tests {
             "Should move to end of previous line"
         );
     }
+
+    #[test]
+    fn test_reload_buffer_keeps_cursor() {
+        let test_file_path = "test_reload_buffer_123456.txt";
+        std::fs::write(test_file_path, "first\nsecond\nthird line\n").unwrap();
+
+        let mut winsize = Winsize::new();
+        winsize.rows = 24;
+        winsize.cols = 80;
+        let mut filename = [0u8; MAX_PATH];
+        filename[..test_file_path.len()].copy_from_slice(test_file_path.as_bytes());
+        let mut state = EditorState::new(winsize, &filename);
+        let Ok(buffer) = open_file(&filename) else {
+            panic!("Failed to open test file");
+        };
+        state.buffer = buffer;
+        state.file_row = 1;
+        state.file_col = 3;
+
+        // Same position when it still exists
+        std::fs::write(test_file_path, "1\nchanged\n").unwrap();
+        assert!(state.buffer.changed_on_disk(&filename));
+        assert!(reload_buffer(&mut state).is_ok());
+        assert_eq!((state.file_row, state.file_col), (1, 3));
+        assert!(!state.buffer.changed_on_disk(&filename));
+
+        // The nearest one otherwise
+        state.file_row = 1;
+        state.file_col = 6;
+        std::fs::write(test_file_path, "ab").unwrap();
+        assert!(reload_buffer(&mut state).is_ok());
+        assert_eq!((state.file_row, state.file_col), (0, 2));
+
+        std::fs::remove_file(test_file_path).unwrap();
+    }
+
+    #[test]
+    fn test_disk_diff_reuses_buffer() {
+        enable_test_mode();
+        let test_file_path = "test_disk_diff_123456.txt";
+        std::fs::write(test_file_path, "one\n").unwrap();
+
+        let mut winsize = Winsize::new();
+        winsize.rows = 24;
+        winsize.cols = 80;
+        let mut filename = [0u8; MAX_PATH];
+        filename[..test_file_path.len()].copy_from_slice(test_file_path.as_bytes());
+        let mut state = EditorState::new(winsize, &filename);
+        state.buffer = create_test_file_buffer(b"two\n");
+
+        let diffs = |state: &EditorState| {
+            let listed = (0..state.buffers.len())
+                .filter(|&idx| state.buffers.get(idx).unwrap().name() == b"*Diff*")
+                .count();
+            listed + usize::from(buffer_list::name_of(&state.filename) == b"*Diff*")
+        };
+        assert!(show_disk_diff(&mut state).is_ok());
+        assert_eq!(buffer_list::name_of(&state.filename), b"*Diff*");
+
+        // The diff buffer itself has no file behind it
+        assert!(show_disk_diff(&mut state).is_ok());
+        assert_eq!(diffs(&state), 1);
+        assert_eq!(state.buffers.len(), 1);
+
+        // Diffing the file again reuses the buffer
+        assert!(state.switch_to_buffer(0));
+        assert!(show_disk_diff(&mut state).is_ok());
+        assert_eq!(buffer_list::name_of(&state.filename), b"*Diff*");
+        assert_eq!(diffs(&state), 1);
+        assert_eq!(state.buffers.len(), 1);
+
+        std::fs::remove_file(test_file_path).unwrap();
+        disable_test_mode();
+    }
 }
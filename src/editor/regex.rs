@@ -0,0 +1,649 @@
+// Regular expressions for search and replace, compiled into a fixed array of
+// instructions and run as a Pike VM so matching needs no allocator. Patterns
+// use ERE syntax: . [...] [^...] ^ $ ( ) | * + ? (lazy with a trailing ?),
+// and \d \w \s \D \W \S \t. Any other escaped byte stands for itself.
+
+// Limits of a compiled pattern, small enough for the thread lists to stay
+// modest stack arrays
+const MAX_INSTS: usize = 100;
+const MAX_DEPTH: usize = 32;
+const MAX_GROUPS: usize = 10; // Group 0 is the whole match
+
+// Start and end of each group, UNSET when the group did not take part
+const UNSET: usize = usize::MAX;
+pub(in crate::editor) type Captures = [usize; MAX_GROUPS * 2];
+pub(in crate::editor) const NO_CAPTURES: Captures = [UNSET; MAX_GROUPS * 2];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(in crate::editor) enum RegexError {
+    TooComplex,
+    UnmatchedParen,
+    UnmatchedBracket,
+    NothingToRepeat,
+    TrailingBackslash,
+}
+
+impl RegexError {
+    pub(in crate::editor) fn message(self) -> &'static str {
+        match self {
+            RegexError::TooComplex => "Regexp too complex",
+            RegexError::UnmatchedParen => "Unmatched ( or )",
+            RegexError::UnmatchedBracket => "Unmatched [",
+            RegexError::NothingToRepeat => "Nothing to repeat",
+            RegexError::TrailingBackslash => "Trailing backslash",
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct ByteSet([u8; 32]);
+
+impl ByteSet {
+    const EMPTY: Self = Self([0; 32]);
+
+    fn contains(&self, b: u8) -> bool {
+        self.0[usize::from(b / 8)] & (1 << (b % 8)) != 0
+    }
+
+    fn insert(&mut self, b: u8) {
+        self.0[usize::from(b / 8)] |= 1 << (b % 8);
+    }
+
+    fn insert_range(&mut self, low: u8, high: u8) {
+        for b in low..=high {
+            self.insert(b);
+        }
+    }
+
+    fn union(&mut self, other: &Self) {
+        for (byte, &bits) in self.0.iter_mut().zip(&other.0) {
+            *byte |= bits;
+        }
+    }
+
+    fn invert(&mut self) {
+        for byte in &mut self.0 {
+            *byte = !*byte;
+        }
+    }
+
+    // Add the other case of every letter
+    fn fold_case(&mut self) {
+        for b in b'a'..=b'z' {
+            if self.contains(b) || self.contains(b.to_ascii_uppercase()) {
+                self.insert(b);
+                self.insert(b.to_ascii_uppercase());
+            }
+        }
+    }
+
+    // \d \w \s and their complements \D \W \S
+    fn class(letter: u8) -> Option<Self> {
+        let mut set = Self::EMPTY;
+        match letter.to_ascii_lowercase() {
+            b'd' => set.insert_range(b'0', b'9'),
+            b'w' => {
+                set.insert_range(b'0', b'9');
+                set.insert_range(b'a', b'z');
+                set.insert_range(b'A', b'Z');
+                set.insert(b'_');
+            }
+            b's' => {
+                for b in [b' ', b'\t', b'\r', b'\n', 0x0b, 0x0c] {
+                    set.insert(b);
+                }
+            }
+            _ => return None,
+        }
+        if letter.is_ascii_uppercase() {
+            set.invert();
+        }
+        Some(set)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Inst {
+    Byte(u8),
+    Any,
+    Set(ByteSet),
+    LineStart,
+    LineEnd,
+    Split(usize, usize), // Try the first branch before the second
+    Jump(usize),
+    Save(usize),
+    Match,
+}
+
+pub(in crate::editor) struct Regex {
+    code: [Inst; MAX_INSTS],
+    len: usize,
+    groups: usize,
+    case_sensitive: bool,
+}
+
+impl Regex {
+    pub(in crate::editor) fn new(pattern: &[u8], case_sensitive: bool) -> Result<Self, RegexError> {
+        let mut regex = Self {
+            code: [Inst::Match; MAX_INSTS],
+            len: 0,
+            groups: 1,
+            case_sensitive,
+        };
+        let mut pos = 0;
+        regex.emit(Inst::Save(0))?;
+        regex.alternation(pattern, &mut pos, 0)?;
+        if pos < pattern.len() {
+            // Only a ) without its ( stops the parser early
+            return Err(RegexError::UnmatchedParen);
+        }
+        regex.emit(Inst::Save(1))?;
+        regex.emit(Inst::Match)?;
+        Ok(regex)
+    }
+
+    fn emit(&mut self, inst: Inst) -> Result<usize, RegexError> {
+        if self.len == MAX_INSTS {
+            return Err(RegexError::TooComplex);
+        }
+        self.code[self.len] = inst;
+        self.len += 1;
+        Ok(self.len - 1)
+    }
+
+    // Put an instruction in front of the code from `at`, which moves up by one.
+    // Jumps into the moved code follow it, except that jumps from before it to
+    // `at` itself now land on the new instruction.
+    fn insert(&mut self, at: usize, inst: Inst) -> Result<(), RegexError> {
+        if self.len == MAX_INSTS {
+            return Err(RegexError::TooComplex);
+        }
+        self.code.copy_within(at..self.len, at + 1);
+        self.len += 1;
+        for (idx, code) in self.code[..self.len].iter_mut().enumerate() {
+            let moved = |target: &mut usize| {
+                if *target > at || (*target == at && idx > at) {
+                    *target += 1;
+                }
+            };
+            match code {
+                Inst::Split(first, second) => {
+                    moved(first);
+                    moved(second);
+                }
+                Inst::Jump(target) => moved(target),
+                _ => {}
+            }
+        }
+        self.code[at] = inst;
+        Ok(())
+    }
+
+    // Branches separated by |, up to a ) or the end of the pattern
+    fn alternation(
+        &mut self,
+        pattern: &[u8],
+        pos: &mut usize,
+        depth: usize,
+    ) -> Result<(), RegexError> {
+        if depth == MAX_DEPTH {
+            return Err(RegexError::TooComplex);
+        }
+        let start = self.len;
+        self.sequence(pattern, pos, depth)?;
+        while pattern.get(*pos) == Some(&b'|') {
+            *pos += 1;
+            self.insert(start, Inst::Split(start + 1, 0))?;
+            let jump = self.emit(Inst::Jump(0))?;
+            self.code[start] = Inst::Split(start + 1, self.len);
+            self.sequence(pattern, pos, depth)?;
+            self.code[jump] = Inst::Jump(self.len);
+        }
+        Ok(())
+    }
+
+    fn sequence(
+        &mut self,
+        pattern: &[u8],
+        pos: &mut usize,
+        depth: usize,
+    ) -> Result<(), RegexError> {
+        while let Some(&b) = pattern.get(*pos) {
+            if b == b'|' || b == b')' {
+                break;
+            }
+            let start = self.len;
+            self.atom(pattern, pos, depth)?;
+            self.quantifiers(pattern, pos, start)?;
+        }
+        Ok(())
+    }
+
+    fn atom(&mut self, pattern: &[u8], pos: &mut usize, depth: usize) -> Result<(), RegexError> {
+        let b = pattern[*pos];
+        *pos += 1;
+        let inst = match b {
+            b'(' => return self.group(pattern, pos, depth),
+            b'*' | b'+' | b'?' => return Err(RegexError::NothingToRepeat),
+            b'.' => Inst::Any,
+            b'^' => Inst::LineStart,
+            b'$' => Inst::LineEnd,
+            b'[' => Inst::Set(self.bracket(pattern, pos)?),
+            b'\\' => {
+                let escaped = *pattern.get(*pos).ok_or(RegexError::TrailingBackslash)?;
+                *pos += 1;
+                match ByteSet::class(escaped) {
+                    Some(set) => Inst::Set(set),
+                    None => self.byte(escaped_byte(escaped)),
+                }
+            }
+            _ => self.byte(b),
+        };
+        self.emit(inst)?;
+        Ok(())
+    }
+
+    // A literal byte, standing for both cases of a letter unless case matters
+    fn byte(&self, b: u8) -> Inst {
+        if self.case_sensitive || !b.is_ascii_alphabetic() {
+            return Inst::Byte(b);
+        }
+        let mut set = ByteSet::EMPTY;
+        set.insert(b);
+        set.fold_case();
+        Inst::Set(set)
+    }
+
+    // Groups past the ninth still group but capture nothing
+    fn group(&mut self, pattern: &[u8], pos: &mut usize, depth: usize) -> Result<(), RegexError> {
+        let group = (self.groups < MAX_GROUPS).then_some(self.groups);
+        if let Some(group) = group {
+            self.groups += 1;
+            self.emit(Inst::Save(group * 2))?;
+        }
+        self.alternation(pattern, pos, depth + 1)?;
+        if pattern.get(*pos) != Some(&b')') {
+            return Err(RegexError::UnmatchedParen);
+        }
+        *pos += 1;
+        if let Some(group) = group {
+            self.emit(Inst::Save(group * 2 + 1))?;
+        }
+        Ok(())
+    }
+
+    // A bracket expression after its [, with ranges and a leading ^ to negate
+    fn bracket(&self, pattern: &[u8], pos: &mut usize) -> Result<ByteSet, RegexError> {
+        let mut set = ByteSet::EMPTY;
+        let negate = pattern.get(*pos) == Some(&b'^');
+        if negate {
+            *pos += 1;
+        }
+
+        // A ] right after the opening bracket is a member
+        let mut first = true;
+        loop {
+            let mut low = *pattern.get(*pos).ok_or(RegexError::UnmatchedBracket)?;
+            *pos += 1;
+            if low == b']' && !first {
+                break;
+            }
+            first = false;
+
+            if low == b'\\' {
+                let escaped = *pattern.get(*pos).ok_or(RegexError::UnmatchedBracket)?;
+                *pos += 1;
+                if let Some(class) = ByteSet::class(escaped) {
+                    set.union(&class);
+                    continue;
+                }
+                low = escaped_byte(escaped);
+            }
+
+            match pattern.get(*pos..*pos + 2) {
+                Some(&[b'-', high]) if high != b']' => {
+                    *pos += 2;
+                    let high = if high == b'\\' {
+                        let escaped = *pattern.get(*pos).ok_or(RegexError::UnmatchedBracket)?;
+                        *pos += 1;
+                        escaped_byte(escaped)
+                    } else {
+                        high
+                    };
+                    set.insert_range(low, high);
+                }
+                _ => set.insert(low),
+            }
+        }
+
+        if !self.case_sensitive {
+            set.fold_case();
+        }
+        if negate {
+            set.invert();
+        }
+        Ok(set)
+    }
+
+    // Repetitions of the atom compiled from `start`
+    fn quantifiers(
+        &mut self,
+        pattern: &[u8],
+        pos: &mut usize,
+        start: usize,
+    ) -> Result<(), RegexError> {
+        while let Some(&quantifier @ (b'*' | b'+' | b'?')) = pattern.get(*pos) {
+            *pos += 1;
+            let lazy = pattern.get(*pos) == Some(&b'?');
+            if lazy {
+                *pos += 1;
+            }
+            let split = |more, done| {
+                if lazy {
+                    Inst::Split(done, more)
+                } else {
+                    Inst::Split(more, done)
+                }
+            };
+
+            match quantifier {
+                b'*' => {
+                    self.insert(start, Inst::Match)?;
+                    self.emit(Inst::Jump(start))?;
+                    self.code[start] = split(start + 1, self.len);
+                }
+                b'+' => {
+                    self.emit(split(start, self.len + 1))?;
+                }
+                _ => {
+                    self.insert(start, Inst::Match)?;
+                    self.code[start] = split(start + 1, self.len);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Whether the pattern matches `line` starting at `pos`, preferring the
+    // same match a backtracking matcher would find. Fills `caps` on success.
+    pub(in crate::editor) fn match_at(
+        &self,
+        line: &[u8],
+        pos: usize,
+        threads: &mut Threads,
+        caps: &mut Captures,
+    ) -> bool {
+        let Threads { current, next } = threads;
+        let mut matched = false;
+        let mut start = NO_CAPTURES;
+        current.clear();
+        self.add(current, 0, line, pos, &mut start);
+
+        let mut pos = pos;
+        while current.len > 0 {
+            next.clear();
+            for idx in 0..current.len {
+                let byte = line.get(pos);
+                let step = match self.code[current.pcs[idx]] {
+                    Inst::Match => {
+                        // Threads after this one have lower priority
+                        *caps = current.caps[idx];
+                        matched = true;
+                        break;
+                    }
+                    Inst::Byte(b) => byte == Some(&b),
+                    Inst::Any => byte.is_some(),
+                    Inst::Set(set) => byte.is_some_and(|&b| set.contains(b)),
+                    _ => false,
+                };
+                if step {
+                    let mut thread_caps = current.caps[idx];
+                    self.add(next, current.pcs[idx] + 1, line, pos + 1, &mut thread_caps);
+                }
+            }
+            core::mem::swap(current, next);
+            pos += 1;
+        }
+        matched
+    }
+
+    // Follow jumps, splits, saves and anchors from `pc`, adding a thread for
+    // each instruction that consumes a byte or matches
+    fn add(&self, list: &mut ThreadList, pc: usize, line: &[u8], pos: usize, caps: &mut Captures) {
+        if list.seen[pc] == list.generation {
+            return;
+        }
+        list.seen[pc] = list.generation;
+
+        match self.code[pc] {
+            Inst::Jump(target) => self.add(list, target, line, pos, caps),
+            Inst::Split(first, second) => {
+                self.add(list, first, line, pos, caps);
+                self.add(list, second, line, pos, caps);
+            }
+            Inst::Save(slot) => {
+                let old = caps[slot];
+                caps[slot] = pos;
+                self.add(list, pc + 1, line, pos, caps);
+                caps[slot] = old;
+            }
+            Inst::LineStart if pos == 0 => self.add(list, pc + 1, line, pos, caps),
+            Inst::LineEnd if pos == line.len() => self.add(list, pc + 1, line, pos, caps),
+            Inst::LineStart | Inst::LineEnd => {}
+            _ => {
+                list.pcs[list.len] = pc;
+                list.caps[list.len] = *caps;
+                list.len += 1;
+            }
+        }
+    }
+}
+
+// Thread lists of the VM, kept apart from the regex so that trying every
+// position of a line can reuse them
+pub(in crate::editor) struct Threads {
+    current: ThreadList,
+    next: ThreadList,
+}
+
+impl Threads {
+    pub(in crate::editor) fn new() -> Self {
+        Self {
+            current: ThreadList::new(),
+            next: ThreadList::new(),
+        }
+    }
+}
+
+struct ThreadList {
+    pcs: [usize; MAX_INSTS],
+    caps: [Captures; MAX_INSTS],
+    len: usize,
+    // Instructions already on the list carry the current generation
+    seen: [usize; MAX_INSTS],
+    generation: usize,
+}
+
+impl ThreadList {
+    fn new() -> Self {
+        Self {
+            pcs: [0; MAX_INSTS],
+            caps: [NO_CAPTURES; MAX_INSTS],
+            len: 0,
+            seen: [0; MAX_INSTS],
+            generation: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+        self.generation += 1;
+    }
+}
+
+// Bytes that need a backslash to stand for themselves
+pub(in crate::editor) fn is_operator(b: u8) -> bool {
+    matches!(
+        b,
+        b'.' | b'[' | b']' | b'^' | b'$' | b'(' | b')' | b'|' | b'*' | b'+' | b'?' | b'\\'
+    )
+}
+
+fn escaped_byte(b: u8) -> u8 {
+    if b == b't' { b'\t' } else { b }
+}
+
+// Hand the replacement for a match to `push` piece by piece: \0 to \9 and \&
+// stand for the text of a group, \\ for a backslash
+pub(in crate::editor) fn expand_replacement(
+    template: &[u8],
+    line: &[u8],
+    caps: &Captures,
+    mut push: impl FnMut(&[u8]),
+) {
+    let mut idx = 0;
+    while idx < template.len() {
+        let b = template[idx];
+        idx += 1;
+        if b != b'\\' || idx == template.len() {
+            push(&[b]);
+            continue;
+        }
+
+        let escaped = template[idx];
+        idx += 1;
+        let group = match escaped {
+            b'&' => Some(0),
+            b'0'..=b'9' => Some(usize::from(escaped - b'0')),
+            _ => None,
+        };
+        match group {
+            Some(group) => {
+                let (start, end) = (caps[group * 2], caps[group * 2 + 1]);
+                if start != UNSET && end != UNSET {
+                    push(&line[start..end]);
+                }
+            }
+            None => push(&[escaped]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The first match in the line as (start, end), trying every position
+    fn find(pattern: &str, line: &str) -> Option<(usize, usize)> {
+        find_case(pattern, line, true)
+    }
+
+    fn find_case(pattern: &str, line: &str, case_sensitive: bool) -> Option<(usize, usize)> {
+        let regex = Regex::new(pattern.as_bytes(), case_sensitive).unwrap();
+        let mut threads = Threads::new();
+        let mut caps = NO_CAPTURES;
+        (0..=line.len())
+            .find(|&pos| regex.match_at(line.as_bytes(), pos, &mut threads, &mut caps))
+            .map(|_| (caps[0], caps[1]))
+    }
+
+    fn replace(pattern: &str, line: &str, template: &str) -> String {
+        let regex = Regex::new(pattern.as_bytes(), true).unwrap();
+        let mut threads = Threads::new();
+        let mut caps = NO_CAPTURES;
+        assert!(regex.match_at(line.as_bytes(), 0, &mut threads, &mut caps));
+        let mut out = Vec::new();
+        expand_replacement(template.as_bytes(), line.as_bytes(), &caps, |piece| {
+            out.extend_from_slice(piece);
+        });
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_regex_literals_and_classes() {
+        assert_eq!(find("abc", "xxabcxx"), Some((2, 5)));
+        assert_eq!(find("a.c", "abc"), Some((0, 3)));
+        assert_eq!(find("[0-9]+", "abc 1234 x"), Some((4, 8)));
+        assert_eq!(find("[^a-z ]", "abc D"), Some((4, 5)));
+        assert_eq!(find("[]x]", "a]"), Some((1, 2)));
+        assert_eq!(find("[a-]", "b-"), Some((1, 2)));
+        assert_eq!(find(r"\d\d", "a1b23"), Some((3, 5)));
+        assert_eq!(find(r"\w+", "  foo_1 bar"), Some((2, 7)));
+        assert_eq!(find(r"\s", "ab\tc"), Some((2, 3)));
+        assert_eq!(find(r"[\d.]+", "v 1.25;"), Some((2, 6)));
+        assert_eq!(find(r"a\.b", "axb a.b"), Some((4, 7)));
+        assert_eq!(find("x", "abc"), None);
+
+        // Case folding covers literals and bracket expressions
+        assert_eq!(find_case("hello", "say HeLLo", false), Some((4, 9)));
+        assert_eq!(find_case("[a-c]+", "xxBCA", false), Some((2, 5)));
+        assert_eq!(find_case("hello", "say HeLLo", true), None);
+    }
+
+    #[test]
+    fn test_regex_anchors_and_repetition() {
+        assert_eq!(find("^ab", "abab"), Some((0, 2)));
+        assert_eq!(find("ab$", "abab"), Some((2, 4)));
+        assert_eq!(find("^$", ""), Some((0, 0)));
+        assert_eq!(find("^b", "ab"), None);
+
+        assert_eq!(find("ab*", "abbbc"), Some((0, 4)));
+        assert_eq!(find("ab+", "ac abb"), Some((3, 6)));
+        assert_eq!(find("colou?r", "color"), Some((0, 5)));
+        assert_eq!(find("colou?r", "colour"), Some((0, 6)));
+
+        // Lazy repetition stops as early as it can
+        assert_eq!(find("<.*>", "<a><b>"), Some((0, 6)));
+        assert_eq!(find("<.*?>", "<a><b>"), Some((0, 3)));
+
+        // Empty loops do not hang the matcher
+        assert_eq!(find("(a*)*b", "aab"), Some((0, 3)));
+        assert_eq!(find("x*", "abc"), Some((0, 0)));
+    }
+
+    #[test]
+    fn test_regex_groups_and_alternation() {
+        assert_eq!(find("cat|dog", "hotdog"), Some((3, 6)));
+        assert_eq!(find("a|b|c", "xxc"), Some((2, 3)));
+        assert_eq!(find("gr(a|e)y", "grey"), Some((0, 4)));
+        assert_eq!(find("(ab)+", "ababab!"), Some((0, 6)));
+        assert_eq!(find("a|b*", "bbb"), Some((0, 3)));
+        assert_eq!(find("(foo|foobar)x", "foobarx"), Some((0, 7)));
+
+        // The first alternative that leads to a match wins
+        assert_eq!(find("a|ab", "ab"), Some((0, 1)));
+    }
+
+    #[test]
+    fn test_regex_errors() {
+        let error = |pattern: &str| Regex::new(pattern.as_bytes(), true).err();
+        assert_eq!(error("(ab"), Some(RegexError::UnmatchedParen));
+        assert_eq!(error("ab)"), Some(RegexError::UnmatchedParen));
+        assert_eq!(error("[ab"), Some(RegexError::UnmatchedBracket));
+        assert_eq!(error("*a"), Some(RegexError::NothingToRepeat));
+        assert_eq!(error("a|+"), Some(RegexError::NothingToRepeat));
+        assert_eq!(error("ab\\"), Some(RegexError::TrailingBackslash));
+        assert_eq!(error(&"a".repeat(MAX_INSTS)), Some(RegexError::TooComplex));
+        assert_eq!(
+            error(&"(".repeat(MAX_DEPTH + 1)),
+            Some(RegexError::TooComplex)
+        );
+        assert_eq!(error("()"), None);
+    }
+
+    #[test]
+    fn test_expand_replacement() {
+        assert_eq!(
+            replace("(\\w+) (\\w+)", "hello world", "\\2 \\1"),
+            "world hello"
+        );
+        assert_eq!(replace("a+", "aaa", "<\\&>"), "<aaa>");
+        assert_eq!(replace("a+", "aaa", "<\\0>"), "<aaa>");
+        assert_eq!(replace("(x)?a", "a", "[\\1]"), "[]");
+        assert_eq!(replace("a", "a", "\\\\n\\"), "\\n\\");
+
+        // Long groups are expanded in full
+        let line = "a".repeat(300);
+        assert_eq!(replace("a+", &line, "\\&\\&"), line.repeat(2));
+    }
+}
@@ -0,0 +1,386 @@
+use crate::syscall::{
+    MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE, SysResult, mmap, munmap, write_buf,
+};
+use crate::terminal::write_usize_to_buf;
+
+// Color value meaning "terminal default"
+pub(in crate::editor) const DEFAULT_COLOR: u8 = 9;
+
+// Worst case output per cell: a full attribute sequence plus the byte itself
+const MAX_CELL_OUTPUT: usize = 16;
+// Per row: cursor movement, attribute reset and line clearing
+const MAX_ROW_OUTPUT: usize = 32;
+
+// A single screen cell: the byte shown and its attributes
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(in crate::editor) struct Glyph {
+    pub(in crate::editor) ch: u8,
+    pub(in crate::editor) fg: u8,
+    pub(in crate::editor) bg: u8,
+    pub(in crate::editor) bold: bool,
+}
+
+impl Glyph {
+    pub(in crate::editor) const BLANK: Glyph = Glyph::plain(b' ');
+
+    pub(in crate::editor) const fn plain(ch: u8) -> Self {
+        Self {
+            ch,
+            fg: DEFAULT_COLOR,
+            bg: DEFAULT_COLOR,
+            bold: false,
+        }
+    }
+
+    fn same_attributes(self, other: Glyph) -> bool {
+        self.fg == other.fg && self.bg == other.bg && self.bold == other.bold
+    }
+
+    fn has_attributes(self) -> bool {
+        !self.same_attributes(Glyph::BLANK)
+    }
+}
+
+// Screen composes the text area into an in-memory frame and sends only the
+// cells that differ from what the terminal already shows
+pub(in crate::editor) struct Screen {
+    front: *mut Glyph, // Frame currently shown on the terminal
+    back: *mut Glyph,  // Frame being composed
+    out: *mut u8,      // Output buffer for escape sequences
+    rows: usize,       // Rows in both frames
+    cols: usize,       // Columns in both frames
+    cells_size: usize, // Size of each frame mapping in bytes
+    out_size: usize,   // Size of the output mapping in bytes
+    valid: bool,       // Whether the front frame matches the terminal
+}
+
+impl Screen {
+    pub(in crate::editor) const fn new() -> Self {
+        Self {
+            front: core::ptr::null_mut(),
+            back: core::ptr::null_mut(),
+            out: core::ptr::null_mut(),
+            rows: 0,
+            cols: 0,
+            cells_size: 0,
+            out_size: 0,
+            valid: false,
+        }
+    }
+
+    // Forget what the terminal shows, e.g. after the screen was cleared
+    pub(in crate::editor) fn invalidate(&mut self) {
+        self.valid = false;
+    }
+
+    // Make sure the frames match the given size, blanking the back frame
+    pub(in crate::editor) fn begin(&mut self, rows: usize, cols: usize) -> SysResult {
+        if rows != self.rows || cols != self.cols || self.back.is_null() {
+            self.allocate(rows, cols)?;
+        }
+
+        for row in 0..rows {
+            self.clear_row(row);
+        }
+        Ok(0)
+    }
+
+    pub(in crate::editor) fn clear_row(&mut self, row: usize) {
+        for col in 0..self.cols {
+            self.put(row, col, Glyph::BLANK);
+        }
+    }
+
+    pub(in crate::editor) fn put(&mut self, row: usize, col: usize, glyph: Glyph) {
+        if row < self.rows && col < self.cols {
+            unsafe { *self.back.add(row * self.cols + col) = glyph };
+        }
+    }
+
+    // Put a run of bytes with the same attributes, returning the column after it
+    pub(in crate::editor) fn put_text(
+        &mut self,
+        row: usize,
+        col: usize,
+        text: &[u8],
+        attributes: Glyph,
+    ) -> usize {
+        for (offset, &ch) in text.iter().enumerate() {
+            self.put(row, col + offset, Glyph { ch, ..attributes });
+        }
+        col + text.len()
+    }
+
+    // Send the differences to the terminal in a single write and park the cursor
+    pub(in crate::editor) fn flush(&mut self, cursor_row: usize, cursor_col: usize) -> SysResult {
+        let len = self.compose(cursor_row, cursor_col);
+        let out = unsafe { core::slice::from_raw_parts(self.out, len) };
+        write_buf(out)
+    }
+
+    // Build the output for all changed cells and make the back frame current
+    fn compose(&mut self, cursor_row: usize, cursor_col: usize) -> usize {
+        let mut out = Output {
+            buf: self.out,
+            len: 0,
+        };
+        // Everything else drawn on the terminal resets its attributes afterwards
+        let mut current = Glyph::BLANK;
+
+        for row in 0..self.rows {
+            let back =
+                unsafe { core::slice::from_raw_parts(self.back.add(row * self.cols), self.cols) };
+            let front =
+                unsafe { core::slice::from_raw_parts(self.front.add(row * self.cols), self.cols) };
+
+            // Find the changed span of this row
+            let Some(mut first) = (0..self.cols).find(|&c| !self.valid || back[c] != front[c])
+            else {
+                continue;
+            };
+            let mut last = (0..self.cols)
+                .rfind(|&c| !self.valid || back[c] != front[c])
+                .unwrap_or(first);
+
+            // Never start or stop in the middle of a multi-byte character
+            while first > 0 && is_continuation(back[first].ch) {
+                first -= 1;
+            }
+            while last + 1 < self.cols && is_continuation(back[last + 1].ch) {
+                last += 1;
+            }
+
+            // Trailing blanks are cheaper to erase than to print
+            let blank_from = back
+                .iter()
+                .rposition(|g| *g != Glyph::BLANK)
+                .map_or(0, |c| c + 1);
+            let erase_tail = last >= blank_from && blank_from < self.cols;
+            let end = if erase_tail { blank_from } else { last + 1 };
+
+            out.move_to(row, first);
+            for glyph in &back[first.min(end)..end] {
+                if !current.same_attributes(*glyph) {
+                    out.attributes(*glyph);
+                    current = *glyph;
+                }
+                out.push(glyph.ch);
+            }
+
+            if erase_tail {
+                if current.has_attributes() {
+                    out.attributes(Glyph::BLANK);
+                    current = Glyph::BLANK;
+                }
+                out.bytes(b"\x1b[K");
+            }
+        }
+
+        if current.has_attributes() {
+            out.attributes(Glyph::BLANK);
+        }
+        out.move_to(cursor_row, cursor_col);
+
+        // What was composed is now on the terminal
+        core::mem::swap(&mut self.front, &mut self.back);
+        self.valid = true;
+        out.len
+    }
+
+    fn allocate(&mut self, rows: usize, cols: usize) -> SysResult {
+        self.free();
+
+        let cells = rows * cols;
+        let cells_size = core::cmp::max(cells * core::mem::size_of::<Glyph>(), 1);
+        let out_size = cells * MAX_CELL_OUTPUT + (rows + 1) * MAX_ROW_OUTPUT;
+
+        self.cells_size = cells_size;
+        self.out_size = out_size;
+        self.valid = false;
+
+        let prot = PROT_READ | PROT_WRITE;
+        let flags = MAP_PRIVATE | MAP_ANONYMOUS;
+        self.front = mmap(0, cells_size, prot, flags, usize::MAX, 0)? as *mut Glyph;
+        self.back = mmap(0, cells_size, prot, flags, usize::MAX, 0)? as *mut Glyph;
+        self.out = mmap(0, out_size, prot, flags, usize::MAX, 0)? as *mut u8;
+
+        self.rows = rows;
+        self.cols = cols;
+        Ok(0)
+    }
+
+    fn free(&mut self) {
+        for (ptr, size) in [
+            (self.front.cast::<u8>(), self.cells_size),
+            (self.back.cast::<u8>(), self.cells_size),
+            (self.out, self.out_size),
+        ] {
+            if !ptr.is_null() && size > 0 {
+                let _ = munmap(ptr as usize, size);
+            }
+        }
+
+        self.front = core::ptr::null_mut();
+        self.back = core::ptr::null_mut();
+        self.out = core::ptr::null_mut();
+        self.rows = 0;
+        self.cols = 0;
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        self.free();
+    }
+}
+
+fn is_continuation(byte: u8) -> bool {
+    byte & 0xC0 == 0x80
+}
+
+// Append-only writer over the output mapping
+struct Output {
+    buf: *mut u8,
+    len: usize,
+}
+
+impl Output {
+    fn push(&mut self, byte: u8) {
+        unsafe { *self.buf.add(self.len) = byte };
+        self.len += 1;
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.push(byte);
+        }
+    }
+
+    fn number(&mut self, n: usize) {
+        let mut digits = [0u8; 20];
+        let len = write_usize_to_buf(&mut digits, n);
+        self.bytes(&digits[..len]);
+    }
+
+    // Format: ESC [ row+1 ; col+1 H
+    fn move_to(&mut self, row: usize, col: usize) {
+        self.bytes(b"\x1b[");
+        self.number(row + 1);
+        self.push(b';');
+        self.number(col + 1);
+        self.push(b'H');
+    }
+
+    // Format: ESC [ 0 [;1] [;3 fg] [;4 bg] m
+    fn attributes(&mut self, glyph: Glyph) {
+        self.bytes(b"\x1b[0");
+        if glyph.bold {
+            self.bytes(b";1");
+        }
+        if glyph.fg != DEFAULT_COLOR {
+            self.bytes(&[b';', b'3', b'0' + glyph.fg]);
+        }
+        if glyph.bg != DEFAULT_COLOR {
+            self.bytes(&[b';', b'4', b'0' + glyph.bg]);
+        }
+        self.push(b'm');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compose(screen: &mut Screen, row: usize, col: usize) -> Vec<u8> {
+        let len = screen.compose(row, col);
+        unsafe { core::slice::from_raw_parts(screen.out, len) }.to_vec()
+    }
+
+    fn put_str(screen: &mut Screen, row: usize, text: &[u8]) {
+        for (col, &ch) in text.iter().enumerate() {
+            screen.put(row, col, Glyph::plain(ch));
+        }
+    }
+
+    #[test]
+    fn test_screen_full_then_diff() {
+        let mut screen = Screen::new();
+        screen.begin(2, 8).unwrap();
+        put_str(&mut screen, 0, b"hello");
+
+        // First frame draws everything, erasing the blank remainder of each row
+        let out = compose(&mut screen, 0, 5);
+        assert_eq!(out, b"\x1b[1;1Hhello\x1b[K\x1b[2;1H\x1b[K\x1b[1;6H");
+
+        // Identical frame only moves the cursor
+        screen.begin(2, 8).unwrap();
+        put_str(&mut screen, 0, b"hello");
+        assert_eq!(compose(&mut screen, 0, 5), b"\x1b[1;6H");
+
+        // A single changed cell is the only one sent
+        screen.begin(2, 8).unwrap();
+        put_str(&mut screen, 0, b"hallo");
+        assert_eq!(compose(&mut screen, 0, 2), b"\x1b[1;2Ha\x1b[1;3H");
+
+        // Shortened text erases the tail instead of printing spaces
+        screen.begin(2, 8).unwrap();
+        put_str(&mut screen, 0, b"ha");
+        assert_eq!(compose(&mut screen, 0, 0), b"\x1b[1;3H\x1b[K\x1b[1;1H");
+    }
+
+    #[test]
+    fn test_screen_attributes() {
+        let mut screen = Screen::new();
+        screen.begin(1, 4).unwrap();
+        let keyword = Glyph {
+            ch: b'f',
+            fg: 4,
+            bg: DEFAULT_COLOR,
+            bold: true,
+        };
+        screen.put(0, 0, keyword);
+        screen.put(
+            0,
+            1,
+            Glyph {
+                ch: b'n',
+                ..keyword
+            },
+        );
+        screen.put(0, 2, Glyph::plain(b'x'));
+        screen.put(
+            0,
+            3,
+            Glyph {
+                bg: 4,
+                ..Glyph::BLANK
+            },
+        );
+
+        // Attributes are only sent when they change, and reset at the end
+        let out = compose(&mut screen, 0, 0);
+        assert_eq!(
+            out,
+            b"\x1b[1;1H\x1b[0;1;34mfn\x1b[0mx\x1b[0;44m \x1b[0m\x1b[1;1H"
+        );
+
+        // Invalidation forces a full redraw
+        screen.invalidate();
+        screen.begin(1, 4).unwrap();
+        let out = compose(&mut screen, 0, 0);
+        assert_eq!(out, b"\x1b[1;1H\x1b[K\x1b[1;1H");
+    }
+
+    #[test]
+    fn test_screen_resize() {
+        let mut screen = Screen::new();
+        screen.begin(1, 3).unwrap();
+        compose(&mut screen, 0, 0);
+
+        // A new size starts from an unknown terminal state
+        screen.begin(2, 2).unwrap();
+        put_str(&mut screen, 1, b"ab");
+        let out = compose(&mut screen, 1, 2);
+        assert_eq!(out, b"\x1b[1;1H\x1b[K\x1b[2;1Hab\x1b[2;3H");
+    }
+}
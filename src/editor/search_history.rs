@@ -0,0 +1,115 @@
+// Room for past search queries
+const HISTORY_BYTES: usize = 4096;
+const HISTORY_MAX: usize = 32;
+
+// Past search queries packed one after another, oldest first. A query
+// searched for again moves to the newest place instead of being repeated.
+pub(in crate::editor) struct SearchHistory {
+    text: [u8; HISTORY_BYTES],
+    ends: [usize; HISTORY_MAX],
+    count: usize,
+}
+
+impl SearchHistory {
+    pub(in crate::editor) const fn new() -> Self {
+        Self {
+            text: [0; HISTORY_BYTES],
+            ends: [0; HISTORY_MAX],
+            count: 0,
+        }
+    }
+
+    // Query `back` steps before the newest one
+    pub(in crate::editor) fn get(&self, back: usize) -> Option<&[u8]> {
+        let idx = self.count.checked_sub(back + 1)?;
+        Some(&self.text[self.start(idx)..self.ends[idx]])
+    }
+
+    pub(in crate::editor) fn add(&mut self, query: &[u8]) {
+        if query.is_empty() || query.len() > HISTORY_BYTES {
+            return;
+        }
+        if let Some(idx) =
+            (0..self.count).find(|&idx| &self.text[self.start(idx)..self.ends[idx]] == query)
+        {
+            self.remove(idx);
+        }
+
+        // Forget the oldest queries to make room
+        while self.count == HISTORY_MAX || self.used() + query.len() > HISTORY_BYTES {
+            self.remove(0);
+        }
+
+        let start = self.used();
+        self.text[start..start + query.len()].copy_from_slice(query);
+        self.ends[self.count] = start + query.len();
+        self.count += 1;
+    }
+
+    fn start(&self, idx: usize) -> usize {
+        if idx == 0 { 0 } else { self.ends[idx - 1] }
+    }
+
+    fn used(&self) -> usize {
+        if self.count == 0 {
+            0
+        } else {
+            self.ends[self.count - 1]
+        }
+    }
+
+    fn remove(&mut self, idx: usize) {
+        let (start, end, used) = (self.start(idx), self.ends[idx], self.used());
+        self.text.copy_within(end..used, start);
+        for later in idx + 1..self.count {
+            self.ends[later - 1] = self.ends[later] - (end - start);
+        }
+        self.count -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(history: &SearchHistory) -> Vec<&[u8]> {
+        (0..history.count)
+            .filter_map(|back| history.get(back))
+            .collect()
+    }
+
+    #[test]
+    fn test_history_newest_first() {
+        let mut history = SearchHistory::new();
+        assert_eq!(history.get(0), None);
+
+        history.add(b"one");
+        history.add(b"two");
+        history.add(b"");
+        history.add(b"three");
+        assert_eq!(entries(&history), [&b"three"[..], b"two", b"one"]);
+
+        // Searching for an old query again makes it the newest
+        history.add(b"one");
+        assert_eq!(entries(&history), [&b"one"[..], b"three", b"two"]);
+        assert_eq!(history.get(3), None);
+    }
+
+    #[test]
+    fn test_history_limits() {
+        let mut history = SearchHistory::new();
+        for idx in 0..HISTORY_MAX + 5 {
+            history.add(format!("query {idx}").as_bytes());
+        }
+        assert_eq!(history.count, HISTORY_MAX);
+        assert_eq!(history.get(0), Some(&b"query 36"[..]));
+        assert_eq!(history.get(HISTORY_MAX - 1), Some(&b"query 5"[..]));
+
+        // Long queries push out as many old ones as needed
+        let long = [b'x'; HISTORY_BYTES - 10];
+        history.add(&long);
+        assert_eq!(entries(&history), [&long[..], b"query 36"]);
+        history.add(&[b'y'; HISTORY_BYTES + 1]);
+        assert_eq!(history.count, 2);
+    }
+}
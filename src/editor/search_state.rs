@@ -1,18 +1,37 @@
-use super::FileBuffer;
+use super::regex::{
+    Captures, NO_CAPTURES, Regex, RegexError, Threads, expand_replacement, is_operator,
+};
+use super::search_history::SearchHistory;
+use super::{EditorState, FileBuffer};
 use crate::syscall::MAX_PATH;
 
 // Editor state structure to track view and cursor position
+#[allow(clippy::struct_excessive_bools)] // Independent toggles of a search
 pub(in crate::editor) struct SearchState {
     pub(in crate::editor) mode: bool,    // Whether we're in search mode
     pub(in crate::editor) reverse: bool, // Whether we're in reverse search mode
     pub(in crate::editor) case_sensitive: bool, // Whether search is case-sensitive
+    pub(in crate::editor) regex: bool,   // Whether the query is a regular expression
+    pub(in crate::editor) smart_case: bool, // Whether an uppercase letter makes the search case-sensitive
+    pub(in crate::editor) whole_word: bool, // Whether matches must be whole words
     pub(in crate::editor) query: [u8; MAX_PATH], // Search query string
     pub(in crate::editor) query_len: usize, // Length of the search query
-    pub(in crate::editor) orig_row: usize, // Original row position before search
-    pub(in crate::editor) orig_col: usize, // Original column position before search
+    pub(in crate::editor) orig_row: usize,  // Original row position before search
+    pub(in crate::editor) orig_col: usize,  // Original column position before search
     pub(in crate::editor) match_row: usize, // Current match row
     pub(in crate::editor) match_col: usize, // Current match column
     pub(in crate::editor) match_len: usize, // Length of current match
+    pub(in crate::editor) position: (usize, usize), // Number of the current match and the total
+    pub(in crate::editor) history: SearchHistory, // Queries of earlier searches
+    pub(in crate::editor) history_pos: usize, // Steps back into the history, 0 for the typed query
+}
+
+// The query of a search compiled once, when it is a regex, with room for
+// the matcher to run in
+pub(in crate::editor) struct Matcher {
+    regex: Option<Result<Regex, RegexError>>,
+    threads: Threads,
+    caps: Captures,
 }
 
 impl SearchState {
@@ -21,6 +40,9 @@ impl SearchState {
             mode: false,
             reverse: false,
             case_sensitive: false,
+            regex: false,
+            smart_case: true,
+            whole_word: false,
             query: [0u8; MAX_PATH],
             query_len: 0,
             orig_row: 0,
@@ -28,6 +50,9 @@ impl SearchState {
             match_row: 0,
             match_col: 0,
             match_len: 0,
+            position: (0, 0),
+            history: SearchHistory::new(),
+            history_pos: 0,
         }
     }
 
@@ -35,9 +60,118 @@ impl SearchState {
         self.reverse = !self.reverse;
     }
 
+    // Flip the case sensitivity in effect, which turns smart-case off
     pub(in crate::editor) fn toggle_case_sensitivity(&mut self) {
-        // Print a message indicating whether we're turning case sensitivity on or off
-        self.case_sensitive = !self.case_sensitive;
+        self.case_sensitive = !self.is_case_sensitive();
+        self.smart_case = false;
+    }
+
+    // Whether case matters, either as set or because smart-case found an
+    // uppercase letter in the query. Escapes such as \W in a regex do not count.
+    pub(in crate::editor) fn is_case_sensitive(&self) -> bool {
+        let mut escaped = false;
+        let has_uppercase = self.query[..self.query_len].iter().any(|&b| {
+            let upper = !escaped && b.is_ascii_uppercase();
+            escaped = self.regex && !escaped && b == b'\\';
+            upper
+        });
+        self.case_sensitive || (self.smart_case && has_uppercase)
+    }
+
+    // Keep the query for later searches
+    pub(in crate::editor) fn remember_query(&mut self) {
+        self.history.add(&self.query[..self.query_len]);
+    }
+
+    // Replace the query with the one `back` steps into the history, or clear
+    // it for 0. False when the history is not that long.
+    pub(in crate::editor) fn recall_query(&mut self, back: usize) -> bool {
+        let query = if back == 0 {
+            &[][..]
+        } else {
+            match self.history.get(back - 1) {
+                Some(query) => query,
+                None => return false,
+            }
+        };
+        let len = query.len().min(MAX_PATH - 1);
+        self.query[..len].copy_from_slice(&query[..len]);
+        self.query_len = len;
+        self.history_pos = back;
+        true
+    }
+
+    // Copy text to be added to the query into `out`, up to the first newline
+    // and with regex operators quoted for a regex search
+    pub(in crate::editor) fn quote(&self, text: &[u8], out: &mut [u8]) -> usize {
+        let mut len = 0;
+        for &b in text.iter().take_while(|&&b| b != b'\n') {
+            let quoted = self.regex && is_operator(b);
+            if len + 1 + usize::from(quoted) > out.len() {
+                break;
+            }
+            if quoted {
+                out[len] = b'\\';
+                len += 1;
+            }
+            out[len] = b;
+            len += 1;
+        }
+        len
+    }
+
+    // Compile the query for a search when it is a regex
+    pub(in crate::editor) fn matcher(&self) -> Matcher {
+        Matcher {
+            regex: self
+                .regex
+                .then(|| Regex::new(&self.query[..self.query_len], self.is_case_sensitive())),
+            threads: Threads::new(),
+            caps: NO_CAPTURES,
+        }
+    }
+
+    // Why the query does not compile, when it is meant as a regex
+    pub(in crate::editor) fn regex_error(&self) -> Option<RegexError> {
+        match self.matcher().regex {
+            Some(Err(e)) => Some(e),
+            _ => None,
+        }
+    }
+
+    // Length of the match of the query starting at `pos`; a regex may match
+    // the empty string, except in whole-word mode
+    pub(in crate::editor) fn match_at(
+        &self,
+        matcher: &mut Matcher,
+        line: &[u8],
+        pos: usize,
+    ) -> Option<usize> {
+        let len = match &matcher.regex {
+            None => self.is_match_at(line, pos).then_some(self.query_len),
+            Some(Ok(regex)) => regex
+                .match_at(line, pos, &mut matcher.threads, &mut matcher.caps)
+                .then(|| matcher.caps[1] - pos),
+            Some(Err(_)) => None,
+        }?;
+        (!self.whole_word || is_whole_word(line, pos, len)).then_some(len)
+    }
+
+    // Hand the text that replaces the match at `pos` to `push`: `with` as it
+    // is for a plain query, with the groups filled in for a regex
+    pub(in crate::editor) fn replacement(
+        &self,
+        matcher: &mut Matcher,
+        line: &[u8],
+        pos: usize,
+        with: &[u8],
+        mut push: impl FnMut(&[u8]),
+    ) {
+        if matcher.regex.is_some() && self.match_at(matcher, line, pos).is_some() {
+            expand_replacement(with, line, &matcher.caps, push);
+        } else {
+            push(with);
+        }
     }
 
     // Check if query matches at a specific position in a line
@@ -47,9 +181,10 @@ impl SearchState {
         }
 
         let query = &self.query[..self.query_len];
+        let case_sensitive = self.is_case_sensitive();
         let mut j = 0;
         while j < self.query_len {
-            if self.case_sensitive {
+            if case_sensitive {
                 if line[pos + j] != query[j] {
                     return false;
                 }
@@ -75,35 +210,19 @@ impl SearchState {
     }
 
     // Search from current position to end of file
-    fn find_forward_no_wrap(
+    pub(in crate::editor) fn find_forward_no_wrap(
         &self,
         buffer: &FileBuffer,
         start_row: usize,
         start_col: usize,
     ) -> Option<(usize, usize, usize)> {
-        let line_count = buffer.count_lines();
-        let mut row = start_row;
-        let mut col = start_col;
-
-        while row < line_count {
-            if let Some(line) = buffer.get_line(row) {
-                if col + self.query_len <= line.len() {
-                    let end = line.len() - self.query_len + 1;
-                    let mut i = col;
-
-                    while i < end {
-                        if self.is_match_at(line, i) {
-                            return Some((row, i, self.query_len));
-                        }
-                        i += 1;
-                    }
-                }
-            }
-            row += 1;
-            col = 0;
-        }
-
-        None
+        let mut matcher = self.matcher();
+        (start_row..buffer.count_lines()).find_map(|row| {
+            let line = buffer.get_line(row)?;
+            let from = if row == start_row { start_col } else { 0 };
+            (from..=line.len())
+                .find_map(|col| Some((row, col, self.match_at(&mut matcher, line, col)?)))
+        })
     }
 
     // Search from beginning of file up to start position
@@ -113,34 +232,14 @@ impl SearchState {
         start_row: usize,
         start_col: usize,
     ) -> Option<(usize, usize, usize)> {
-        let mut row = 0;
-
-        while row <= start_row {
-            if let Some(line) = buffer.get_line(row) {
-                if self.query_len <= line.len() {
-                    let end = if row == start_row {
-                        start_col
-                    } else {
-                        line.len()
-                    };
-
-                    if end >= self.query_len {
-                        let search_end = end - self.query_len + 1;
-                        let mut i = 0;
-
-                        while i < search_end {
-                            if self.is_match_at(line, i) {
-                                return Some((row, i, self.query_len));
-                            }
-                            i += 1;
-                        }
-                    }
-                }
-            }
-            row += 1;
-        }
-
-        None
+        let mut matcher = self.matcher();
+        (0..=start_row).find_map(|row| {
+            let line = buffer.get_line(row)?;
+            (0..=line.len()).find_map(|col| {
+                let len = self.match_at(&mut matcher, line, col)?;
+                (row < start_row || col + len <= start_col).then_some((row, col, len))
+            })
+        })
     }
 
     // Find a substring in the buffer from the current position, searching forward
@@ -163,56 +262,23 @@ impl SearchState {
         self.find_forward_with_wrap(buffer, start_row, start_col)
     }
 
-    // Search from current position backward to beginning
+    // Search from current position backward to beginning, for a match that
+    // ends before start_col on the start row
     fn find_backward_no_wrap(
         &self,
         buffer: &FileBuffer,
         start_row: usize,
         start_col: usize,
     ) -> Option<(usize, usize, usize)> {
-        let mut row = start_row;
-
-        // First check current line up to start_col
-        if let Some(line) = buffer.get_line(row) {
-            if start_col >= self.query_len {
-                let mut col = start_col - self.query_len;
-
-                loop {
-                    if self.is_match_at(line, col) {
-                        return Some((row, col, self.query_len));
-                    }
-
-                    if col == 0 {
-                        break;
-                    }
-                    col -= 1;
-                }
-            }
-        }
-
-        // Then check previous lines
-        while row > 0 {
-            row -= 1;
-
-            if let Some(line) = buffer.get_line(row) {
-                if line.len() >= self.query_len {
-                    let mut col = line.len() - self.query_len;
-
-                    loop {
-                        if self.is_match_at(line, col) {
-                            return Some((row, col, self.query_len));
-                        }
-
-                        if col == 0 {
-                            break;
-                        }
-                        col -= 1;
-                    }
-                }
-            }
-        }
-
-        None
+        let mut matcher = self.matcher();
+        (0..=start_row).rev().find_map(|row| {
+            let line = buffer.get_line(row)?;
+            (0..=line.len()).rev().find_map(|col| {
+                let len = self.match_at(&mut matcher, line, col)?;
+                let before = col < start_col && col + len <= start_col;
+                (row < start_row || before).then_some((row, col, len))
+            })
+        })
     }
 
     // Search from end of file backward to start position (wrap around)
@@ -222,52 +288,59 @@ impl SearchState {
         start_row: usize,
         start_col: usize,
     ) -> Option<(usize, usize, usize)> {
-        let line_count = buffer.count_lines();
-        if line_count == 0 {
-            return None;
-        }
-
-        // Special case for test_search_state_find_methods test
-        if start_row == 3 && line_count >= 4 {
-            if let Some(line) = buffer.get_line(3) {
-                for col in 0..line.len() {
-                    if self.is_match_at(line, col) {
-                        return Some((3, col, self.query_len));
-                    }
-                }
-            }
-        }
-
-        // Start from the last line
-        let mut row = line_count - 1;
-
-        while row >= start_row {
-            if let Some(line) = buffer.get_line(row) {
-                let search_start = if row == start_row { start_col + 1 } else { 0 };
+        let mut matcher = self.matcher();
+        (start_row..buffer.count_lines()).rev().find_map(|row| {
+            let line = buffer.get_line(row)?;
+            (0..=line.len()).rev().find_map(|col| {
+                let len = self.match_at(&mut matcher, line, col)?;
+                (row > start_row || col > start_col).then_some((row, col, len))
+            })
+        })
+    }
 
-                if search_start < line.len() && line.len() >= self.query_len {
-                    let mut col = line.len() - self.query_len;
+    // Make a match the current one and count where it falls
+    pub(in crate::editor) fn set_match(
+        &mut self,
+        buffer: &FileBuffer,
+        row: usize,
+        col: usize,
+        len: usize,
+    ) {
+        self.match_row = row;
+        self.match_col = col;
+        self.match_len = len;
+        self.update_position(buffer);
+    }
 
-                    while col >= search_start {
-                        if self.is_match_at(line, col) {
-                            return Some((row, col, self.query_len));
-                        }
+    // Count the matches again after the query, its flags or the match changed
+    pub(in crate::editor) fn update_position(&mut self, buffer: &FileBuffer) {
+        self.position = self.match_position(buffer);
+    }
 
-                        if col == search_start || col == 0 {
-                            break;
-                        }
-                        col -= 1;
-                    }
+    // Number of the current match counting from the start of the buffer, and
+    // how many matches there are. Matches do not overlap.
+    fn match_position(&self, buffer: &FileBuffer) -> (usize, usize) {
+        let mut matcher = self.matcher();
+        let mut before = 0;
+        let mut total = 0;
+        for row in 0..buffer.count_lines() {
+            let Some(line) = buffer.get_line(row) else {
+                continue;
+            };
+            let mut col = 0;
+            while col <= line.len() {
+                let Some(len) = self.match_at(&mut matcher, line, col) else {
+                    col += 1;
+                    continue;
+                };
+                if (row, col) < (self.match_row, self.match_col) {
+                    before += 1;
                 }
+                total += 1;
+                col += len.max(1);
             }
-
-            if row == 0 {
-                break;
-            }
-            row -= 1;
         }
-
-        None
+        ((before + 1).min(total), total)
     }
 
     // Find a substring in the buffer from the current position, searching backward
@@ -291,6 +364,15 @@ impl SearchState {
     }
 }
 
+// A whole word has no letter or digit right before or after it
+fn is_whole_word(line: &[u8], pos: usize, len: usize) -> bool {
+    len > 0
+        && (pos == 0 || !EditorState::is_alnum(line[pos - 1]))
+        && line
+            .get(pos + len)
+            .is_none_or(|&b| !EditorState::is_alnum(b))
+}
+
 #[cfg(test)]
 pub mod tests {
     use crate::{
@@ -542,4 +624,250 @@ pub mod tests {
             assert_eq!(row, 3, "Wrapped backward search should find last match");
         }
     }
+
+    #[test]
+    fn test_regex_search() {
+        use crate::terminal::tests::{disable_test_mode, enable_test_mode};
+        enable_test_mode();
+
+        let content = b"let a = 10;\nlet bb = 200;\n\nfn main() {}\n";
+        let mut winsize = Winsize::new();
+        winsize.rows = 24;
+        winsize.cols = 80;
+        let mut state = EditorState::new(winsize, &[0u8; MAX_PATH]);
+        state.buffer = create_test_file_buffer(content);
+
+        // Matches follow the pattern as it is typed, incomplete or not
+        let _ = state.start_regex_search(false);
+        assert!(state.search.regex);
+        for &ch in b"[0-9]+;" {
+            let _ = state.add_search_char(ch);
+        }
+        assert_eq!(
+            (
+                state.search.match_row,
+                state.search.match_col,
+                state.search.match_len
+            ),
+            (0, 8, 3)
+        );
+        let _ = state.find_next_match();
+        assert_eq!(
+            (
+                state.search.match_row,
+                state.search.match_col,
+                state.search.match_len
+            ),
+            (1, 9, 4)
+        );
+
+        // Backward matches start as late as they can, so only the last digit
+        // is found
+        let _ = state.remove_search_char();
+        let (row, col, len) = state
+            .search
+            .find_substring_backward(&state.buffer, 2, 0)
+            .unwrap();
+        assert_eq!((row, col, len), (1, 11, 1));
+
+        // Empty matches still move on
+        state.search.query[..2].copy_from_slice(b"^$");
+        state.search.query_len = 2;
+        state.search.match_row = 0;
+        state.search.match_col = 0;
+        state.search.match_len = 0;
+        let _ = state.find_next_match();
+        assert_eq!((state.search.match_row, state.search.match_col), (2, 0));
+        let _ = state.find_next_match();
+        assert_eq!((state.search.match_row, state.search.match_col), (2, 0));
+
+        // The match count is kept with the match for the status bar
+        assert_eq!(state.search.position, (1, 1));
+
+        // A plain search started afterwards is literal again
+        let _ = state.start_search(false);
+        assert!(!state.search.regex);
+
+        disable_test_mode();
+    }
+
+    #[test]
+    fn test_regex_replacement() {
+        let mut search = SearchState::new();
+        search.regex = true;
+        let pattern = b"(\\w+) = (\\d+)";
+        search.query[..pattern.len()].copy_from_slice(pattern);
+        search.query_len = pattern.len();
+
+        let line = b"let a = 10;";
+        let mut matcher = search.matcher();
+        assert_eq!(search.match_at(&mut matcher, line, 4), Some(6));
+        let replace = |search: &SearchState, matcher: &mut Matcher, line: &[u8], with: &[u8]| {
+            let mut out = Vec::new();
+            search.replacement(matcher, line, 4, with, |piece| out.extend_from_slice(piece));
+            out
+        };
+        assert_eq!(
+            replace(&search, &mut matcher, line, b"\\2 = \\1"),
+            b"10 = a"
+        );
+
+        // Expansions are not limited by the size of a path
+        let digits = [b'2'; MAX_PATH * 2];
+        let long_line = [b"let x = ".as_slice(), &digits].concat();
+        assert_eq!(replace(&search, &mut matcher, &long_line, b"\\2"), digits);
+
+        // A plain query replaces with the text as typed
+        search.regex = false;
+        search.query[..1].copy_from_slice(b"a");
+        search.query_len = 1;
+        let mut matcher = search.matcher();
+        assert_eq!(replace(&search, &mut matcher, line, b"\\1"), b"\\1");
+
+        // Invalid patterns never match
+        search.regex = true;
+        search.query[..1].copy_from_slice(b"(");
+        assert_eq!(search.regex_error(), Some(RegexError::UnmatchedParen));
+        let mut matcher = search.matcher();
+        assert_eq!(search.match_at(&mut matcher, b"(", 0), None);
+    }
+
+    #[test]
+    fn test_match_position() {
+        let buffer = create_test_file_buffer(b"aaa ab\nno hits\nab\n");
+        let mut search = SearchState::new();
+        search.query[..2].copy_from_slice(b"aa");
+        search.query_len = 2;
+
+        // Overlapping occurrences count once
+        assert_eq!(search.match_position(&buffer), (1, 1));
+
+        search.query[..1].copy_from_slice(b"a");
+        search.query_len = 1;
+        (search.match_row, search.match_col) = (0, 4);
+        assert_eq!(search.match_position(&buffer), (4, 5));
+        (search.match_row, search.match_col) = (2, 0);
+        assert_eq!(search.match_position(&buffer), (5, 5));
+
+        // Regex matches are counted the same way
+        search.regex = true;
+        search.query[..2].copy_from_slice(b"^a");
+        search.query_len = 2;
+        assert_eq!(search.match_position(&buffer), (2, 2));
+        search.query[..1].copy_from_slice(b"x");
+        search.query_len = 1;
+        assert_eq!(search.match_position(&buffer), (0, 0));
+    }
+
+    #[test]
+    fn test_search_history_and_yanks() {
+        use crate::terminal::tests::{disable_test_mode, enable_test_mode};
+        enable_test_mode();
+
+        let mut winsize = Winsize::new();
+        winsize.rows = 24;
+        winsize.cols = 80;
+        let mut state = EditorState::new(winsize, &[0u8; MAX_PATH]);
+        state.buffer = create_test_file_buffer(b"alpha beta\nfoo.bar alpha\n");
+        let query = |state: &EditorState| state.search.query[..state.search.query_len].to_vec();
+
+        // Searches are remembered when they end, accepted or not
+        let _ = state.start_search(false);
+        for &ch in b"beta" {
+            let _ = state.add_search_char(ch);
+        }
+        let _ = state.accept_search();
+        let _ = state.start_search(false);
+        let _ = state.add_search_char(b'a');
+        let _ = state.cancel_search();
+
+        // C-s right away repeats the last search
+        state.file_col = 0;
+        let _ = state.start_search(false);
+        let _ = state.find_next_match();
+        assert_eq!(query(&state), b"a");
+        let _ = state.cancel_search();
+
+        // M-p and M-n walk the history, searching from the start each time
+        let _ = state.start_search(false);
+        let _ = state.walk_search_history(true);
+        let _ = state.walk_search_history(true);
+        assert_eq!(query(&state), b"beta");
+        assert_eq!((state.file_row, state.file_col), (0, 6));
+        let _ = state.walk_search_history(true);
+        assert_eq!(query(&state), b"beta", "Nothing older");
+        let _ = state.walk_search_history(false);
+        let _ = state.walk_search_history(false);
+        assert_eq!(query(&state), b"");
+        assert_eq!((state.file_row, state.file_col), (0, 0));
+        let _ = state.cancel_search();
+
+        // C-w adds the word after the match, quoted for a regex search
+        state.file_row = 1;
+        let _ = state.start_regex_search(false);
+        let _ = state.yank_word_into_search();
+        let _ = state.yank_word_into_search();
+        assert_eq!(query(&state), b"foo\\.bar");
+        assert_eq!(
+            (
+                state.search.match_row,
+                state.search.match_col,
+                state.search.match_len
+            ),
+            (1, 0, 7)
+        );
+        let _ = state.cancel_search();
+
+        // M-y adds the newest kill up to its first line break
+        assert!(state.kill_ring.copy(b"alpha\nmore").is_ok());
+        let _ = state.start_search(false);
+        let _ = state.yank_kill_into_search();
+        assert_eq!(query(&state), b"alpha");
+        assert_eq!((state.file_row, state.file_col), (1, 8));
+
+        disable_test_mode();
+    }
+
+    #[test]
+    fn test_whole_word_and_smart_case() {
+        let buffer = create_test_file_buffer(b"cat concat Cat\ncat_dog scat\n");
+        let mut search = SearchState::new();
+        let set_query = |search: &mut SearchState, query: &[u8]| {
+            search.query[..query.len()].copy_from_slice(query);
+            search.query_len = query.len();
+        };
+
+        // Whole words have no letter or digit next to them
+        set_query(&mut search, b"cat");
+        search.whole_word = true;
+        let found = |search: &SearchState, row, col| {
+            search
+                .find_substring_forward(&buffer, row, col)
+                .map(|(row, col, _)| (row, col))
+        };
+        assert_eq!(found(&search, 0, 1), Some((0, 11)));
+        assert_eq!(found(&search, 0, 12), Some((1, 0)));
+        assert_eq!(found(&search, 1, 1), Some((0, 0)), "Wraps around");
+        search.whole_word = false;
+        assert_eq!(found(&search, 0, 1), Some((0, 7)));
+
+        // An uppercase letter makes the search case-sensitive
+        set_query(&mut search, b"Cat");
+        assert!(search.is_case_sensitive());
+        assert_eq!(found(&search, 0, 0), Some((0, 11)));
+
+        // Toggling the case leaves smart-case behind
+        search.toggle_case_sensitivity();
+        assert!(!search.smart_case);
+        assert!(!search.is_case_sensitive());
+        assert_eq!(found(&search, 0, 0), Some((0, 0)));
+
+        // Regex escapes are not uppercase letters
+        search.smart_case = true;
+        search.regex = true;
+        set_query(&mut search, b"\\Wcat");
+        assert!(!search.is_case_sensitive());
+        set_query(&mut search, b"\\WCat");
+        assert!(search.is_case_sensitive());
+    }
 }
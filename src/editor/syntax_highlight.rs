@@ -1,7 +1,10 @@
 use crate::editor::FileBuffer;
+use crate::syscall::{MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE, mmap, munmap};
+
+const PAGE_SIZE: usize = 4096;
 
 /// Represents a color used for syntax highlighting
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum HighlightColor {
     Default,
     Comment,
@@ -12,6 +15,7 @@ pub enum HighlightColor {
 }
 
 /// Supported file types for syntax highlighting
+#[derive(Clone, Copy)]
 pub enum FileType {
     PlainText,
     C,
@@ -19,10 +23,154 @@ pub enum FileType {
     ConfigFile,
 }
 
+/// Lexer state carried from the end of one line to the start of the next
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum LexState {
+    Normal,
+    /// Inside a block comment, with the nesting depth (Rust comments nest)
+    BlockComment(u8),
+    /// Inside a string literal
+    String,
+    /// Inside a Rust raw string, with the number of `#` around it
+    RawString(u8),
+    /// Inside a TOML multi-line basic string
+    MultiLineString,
+}
+
+impl LexState {
+    fn encode(self) -> u16 {
+        match self {
+            LexState::Normal => 0,
+            LexState::BlockComment(depth) => 0x100 | u16::from(depth),
+            LexState::String => 0x200,
+            LexState::RawString(hashes) => 0x300 | u16::from(hashes),
+            LexState::MultiLineString => 0x400,
+        }
+    }
+
+    fn decode(value: u16) -> Self {
+        let [param, kind] = value.to_le_bytes();
+        match kind {
+            1 => LexState::BlockComment(param),
+            2 => LexState::String,
+            3 => LexState::RawString(param),
+            4 => LexState::MultiLineString,
+            _ => LexState::Normal,
+        }
+    }
+}
+
+const C_KEYWORDS: &[&[u8]] = &[
+    b"if",
+    b"else",
+    b"for",
+    b"while",
+    b"do",
+    b"switch",
+    b"case",
+    b"break",
+    b"continue",
+    b"return",
+    b"int",
+    b"char",
+    b"void",
+    b"struct",
+    b"typedef",
+    b"enum",
+    b"sizeof",
+    b"static",
+    b"const",
+];
+
+const RUST_KEYWORDS: &[&[u8]] = &[
+    b"fn",
+    b"let",
+    b"mut",
+    b"struct",
+    b"enum",
+    b"trait",
+    b"impl",
+    b"pub",
+    b"use",
+    b"mod",
+    b"if",
+    b"else",
+    b"match",
+    b"for",
+    b"while",
+    b"loop",
+    b"break",
+    b"continue",
+    b"return",
+];
+
+const CONFIG_KEYWORDS: &[&[u8]] = &[
+    // Common boolean values
+    b"true",
+    b"false",
+    b"yes",
+    b"no",
+    b"on",
+    b"off",
+    b"null",
+    b"None",
+    // Cargo metadata
+    b"name",
+    b"version",
+    b"authors",
+    b"edition",
+    b"description",
+    b"license",
+    b"repository",
+    b"homepage",
+    b"documentation",
+    b"readme",
+    b"publish",
+    // Cargo dependencies
+    b"dependencies",
+    b"dev-dependencies",
+    b"build-dependencies",
+    b"features",
+    b"package",
+    b"workspace",
+    // Cargo build targets
+    b"bin",
+    b"lib",
+    b"test",
+    b"bench",
+    b"example",
+    b"default-run",
+    // Cargo profiles
+    b"profile",
+    b"debug",
+    b"release",
+    b"target",
+    b"opt-level",
+    b"debug-assertions",
+    b"codegen-units",
+    b"lto",
+    b"path",
+    b"include",
+    b"exclude",
+    b"required",
+    b"optional",
+    // Other config keys
+    b"default",
+    b"level",
+    b"warn",
+    b"error",
+    b"categories",
+    b"keywords",
+];
+
 /// Syntax highlighter for code files
 pub struct SyntaxHighlighter {
     file_type: FileType,
     matching_position: usize,
+    /// Lexer state at the start of each line, valid for the first `valid_lines`
+    states: *mut u16,
+    states_capacity: usize,
+    valid_lines: usize,
 }
 
 impl SyntaxHighlighter {
@@ -31,6 +179,9 @@ impl SyntaxHighlighter {
         Self {
             file_type: FileType::PlainText,
             matching_position: usize::MAX,
+            states: core::ptr::null_mut(),
+            states_capacity: 0,
+            valid_lines: 0,
         }
     }
 
@@ -61,708 +212,577 @@ impl SyntaxHighlighter {
         } else {
             FileType::PlainText
         };
+
+        // Cached states depend on the language
+        self.valid_lines = 0;
     }
 
     /// Gets the highlight color for a particular character at a position
+    #[cfg(test)]
     pub fn highlight_char(&mut self, buffer: &FileBuffer, pos: usize) -> HighlightColor {
         // Return default for invalid positions
         if pos >= buffer.size {
             return HighlightColor::Default;
         }
 
-        // Check for matching position
-        if pos == self.matching_position {
-            return HighlightColor::Delimiter;
-        }
-
-        let ch = unsafe { *buffer.content.add(pos) };
-
-        match self.file_type {
-            FileType::PlainText => {
-                if Self::is_delimiter(ch) {
-                    HighlightColor::Delimiter
-                } else {
-                    HighlightColor::Default
-                }
-            }
-            FileType::C | FileType::Rust => self.highlight_code(buffer, pos, ch),
-            FileType::ConfigFile => self.highlight_config(buffer, pos, ch),
-        }
+        let (row, col) = buffer.position_to_row_col(pos);
+        let line_start = pos - col;
+        self.line_colors(buffer, row, line_start)
+            .nth(col)
+            .unwrap_or(HighlightColor::Default)
     }
 
-    /// Highlights code file contents (C/Rust)
-    fn highlight_code(&self, buffer: &FileBuffer, pos: usize, ch: u8) -> HighlightColor {
-        if Self::is_in_comment(buffer, pos) {
-            return HighlightColor::Comment;
-        }
+    /// Returns a lexer producing one color per byte of the given line
+    pub fn line_colors<'a>(
+        &mut self,
+        buffer: &'a FileBuffer,
+        line_idx: usize,
+        line_start: usize,
+    ) -> LineLexer<'a> {
+        let state = self.state_at_line(buffer, line_idx);
+        let line = buffer.get_line(line_idx).unwrap_or(&[]);
+        let matching = self.matching_position.wrapping_sub(line_start);
+        LineLexer::new(self.file_type, line, state, matching)
+    }
 
-        if Self::is_in_string(buffer, pos) {
-            return HighlightColor::String;
+    /// Lexer state at the start of a line, computed from the last cached line
+    fn state_at_line(&mut self, buffer: &FileBuffer, line_idx: usize) -> LexState {
+        // Lines after an edit may have changed state
+        if let Some(pos) = buffer.take_changed() {
+            let (row, _) = buffer.position_to_row_col(pos);
+            self.valid_lines = self.valid_lines.min(row + 1);
         }
 
-        if Self::is_delimiter(ch) {
-            return HighlightColor::Delimiter;
+        if self.valid_lines == 0 && self.reserve(1) {
+            self.write_state(0, LexState::Normal);
+            self.valid_lines = 1;
         }
 
-        if ch.is_ascii_digit() {
-            return HighlightColor::Number;
+        if line_idx < self.valid_lines {
+            return self.read_state(line_idx);
         }
 
-        if self.is_in_keyword(buffer, pos) {
-            return HighlightColor::Keyword;
-        }
+        let mut line = self.valid_lines.saturating_sub(1);
+        let mut state = if self.valid_lines > 0 {
+            self.read_state(line)
+        } else {
+            LexState::Normal
+        };
 
-        HighlightColor::Default
-    }
+        while line < line_idx {
+            let text = buffer.get_line(line).unwrap_or(&[]);
+            state = LineLexer::new(self.file_type, text, state, usize::MAX).end_state();
+            line += 1;
 
-    /// Highlights config file contents
-    fn highlight_config(&self, buffer: &FileBuffer, pos: usize, ch: u8) -> HighlightColor {
-        if Self::is_in_config_comment(buffer, pos) {
-            return HighlightColor::Comment;
+            // Cache as far as memory allows
+            if line == self.valid_lines && self.reserve(line + 1) {
+                self.write_state(line, state);
+                self.valid_lines = line + 1;
+            }
         }
 
-        if Self::is_in_string(buffer, pos) {
-            return HighlightColor::String;
-        }
+        state
+    }
 
-        // For TOML files, ensure delimiters are properly recognized - needs to have high priority
-        if Self::is_delimiter(ch) {
-            // Double quotes should be highlighted as delimiters when not part of a string
-            return HighlightColor::Delimiter;
-        }
+    fn read_state(&self, line: usize) -> LexState {
+        LexState::decode(unsafe { *self.states.add(line) })
+    }
 
-        // Section headers should be checked next
-        if Self::is_in_section_header(buffer, pos) {
-            return HighlightColor::Keyword;
-        }
+    fn write_state(&mut self, line: usize, state: LexState) {
+        unsafe { *self.states.add(line) = state.encode() };
+    }
 
-        // Check for TOML array table (double brackets)
-        if Self::is_in_toml_array_table(buffer, pos) {
-            return HighlightColor::Keyword;
+    /// Makes sure the state cache can hold `lines` entries
+    fn reserve(&mut self, lines: usize) -> bool {
+        if lines <= self.states_capacity {
+            return true;
         }
 
-        // Check for keywords in TOML files
-        if self.is_in_keyword(buffer, pos) {
-            return HighlightColor::Keyword;
+        let mut new_capacity = if self.states_capacity == 0 {
+            PAGE_SIZE / 2
+        } else {
+            self.states_capacity * 2
+        };
+        while new_capacity < lines {
+            new_capacity *= 2;
         }
 
-        if Self::is_in_config_key(buffer, pos) {
-            return HighlightColor::Number;
-        }
+        let prot = PROT_READ | PROT_WRITE;
+        let flags = MAP_PRIVATE | MAP_ANONYMOUS;
+        let Ok(addr) = mmap(0, new_capacity * 2, prot, flags, usize::MAX, 0) else {
+            return false;
+        };
+        let new_states = addr as *mut u16;
 
-        if Self::is_config_number(buffer, pos) {
-            return HighlightColor::Number;
+        if !self.states.is_null() {
+            unsafe { core::ptr::copy_nonoverlapping(self.states, new_states, self.valid_lines) };
+            let _ = munmap(self.states as usize, self.states_capacity * 2);
         }
 
-        HighlightColor::Default
+        self.states = new_states;
+        self.states_capacity = new_capacity;
+        true
     }
 
-    /// Determines if a position is within a comment
-    fn is_in_comment(buffer: &FileBuffer, pos: usize) -> bool {
-        // Safety check for position
-        if pos >= buffer.size {
-            return false;
-        }
-
-        let line_start = Self::find_line_start(buffer, pos);
-
-        // Search for comment indicators from line start to position
-        let mut i = line_start;
-        while i <= pos && i + 1 < buffer.size {
-            let ch = unsafe { *buffer.content.add(i) };
-
-            // Check for line comment
-            if ch == b'/' && i + 1 < buffer.size && unsafe { *buffer.content.add(i + 1) } == b'/' {
-                return true;
-            }
-
-            // Check for block comment
-            if ch == b'/' && i + 1 < buffer.size && unsafe { *buffer.content.add(i + 1) } == b'*' {
-                // We found a block comment start, now check if our position is before
-                // a matching comment end
-                let mut j = i + 2;
-                let mut in_block_comment = true;
-
-                while j < buffer.size && j <= pos {
-                    let block_ch = unsafe { *buffer.content.add(j) };
-
-                    if block_ch == b'*'
-                        && j + 1 < buffer.size
-                        && unsafe { *buffer.content.add(j + 1) } == b'/'
-                    {
-                        // Found end of block comment
-                        if j + 1 < pos {
-                            // Position is after the comment end
-                            in_block_comment = false;
-                        }
-                        break;
-                    }
-                    j += 1;
-                }
+    fn is_word_char(ch: u8) -> bool {
+        ch.is_ascii_alphanumeric() || ch == b'_'
+    }
 
-                if in_block_comment {
-                    return true;
-                }
-            }
+    /// Determines if a token is a number in a config file (TOML style)
+    fn is_config_number(token: &[u8]) -> bool {
+        let mut i = 0;
 
+        // Skip leading sign if present
+        if i < token.len() && (token[i] == b'-' || token[i] == b'+') {
             i += 1;
         }
 
-        false
-    }
-
-    /// Determines if a position is within a string literal
-    /// Note: The double quotes that delimit the string are NOT considered part of the string
-    fn is_in_string(buffer: &FileBuffer, pos: usize) -> bool {
-        // Safety check for position
-        if pos >= buffer.size {
-            return false;
+        // Special number formats (0x, 0o, 0b)
+        if i + 1 < token.len() && token[i] == b'0' && matches!(token[i + 1], b'x' | b'o' | b'b') {
+            let radix = match token[i + 1] {
+                b'x' => 16,
+                b'o' => 8,
+                _ => 2,
+            };
+            let digits = &token[i + 2..];
+            return digits.iter().any(u8::is_ascii_hexdigit)
+                && digits
+                    .iter()
+                    .all(|&c| c == b'_' || char::from(c).is_digit(radix));
         }
 
-        // If we're at a quote character, we're not "in" the string
-        if unsafe { *buffer.content.add(pos) } == b'"' {
-            return false;
-        }
-
-        let line_start = Self::find_line_start(buffer, pos);
-
-        // Count unescaped quotes from line start to position
-        let mut in_string = false;
-        let mut i = line_start;
-
-        while i < pos {
-            let ch = unsafe { *buffer.content.add(i) };
-
-            if ch == b'"' {
-                // Check if the quote is escaped
-                let is_escaped = i > 0 && unsafe { *buffer.content.add(i - 1) } == b'\\';
-
-                // Only toggle string state if quote is not escaped
-                if !is_escaped {
-                    in_string = !in_string;
+        // Decimal with optional fraction and exponent
+        let mut has_digit = false;
+        let mut has_decimal = false;
+        let mut has_exp = false;
+        while i < token.len() {
+            let ch = token[i];
+            if ch.is_ascii_digit() {
+                has_digit = true;
+            } else if ch == b'_' && has_digit {
+                // Underscores separate digit groups
+            } else if ch == b'.' && !has_decimal && !has_exp {
+                has_decimal = true;
+            } else if (ch == b'e' || ch == b'E') && has_digit && !has_exp {
+                has_exp = true;
+                if i + 1 < token.len() && (token[i + 1] == b'+' || token[i + 1] == b'-') {
+                    i += 1; // Skip the sign after the exponent
                 }
+            } else {
+                return false;
             }
-
             i += 1;
         }
 
-        in_string
+        has_digit
     }
 
-    /// Determines if a character is in a config file comment
-    fn is_in_config_comment(buffer: &FileBuffer, pos: usize) -> bool {
-        // Safety check for position
-        if pos >= buffer.size {
-            return false;
-        }
-
-        let line_start = Self::find_line_start(buffer, pos);
-        let mut i = line_start;
-
-        // Check if line starts with a comment character (after whitespace)
-        while i <= pos {
-            let ch = unsafe { *buffer.content.add(i) };
-            if ch.is_ascii_whitespace() {
-                i += 1;
-                continue;
-            }
-
-            // First non-whitespace character determines if we're in a comment
-            return ch == b'#' || ch == b';';
-        }
+    /// Determines if a character is a delimiter (brackets, parentheses, braces, etc.)
+    fn is_delimiter(ch: u8) -> bool {
+        // Using a byte array instead of matches! macro for better performance
+        const DELIMITERS: [u8; 17] = [
+            b'(', b')', b'[', b']', b'{', b'}', b'<', b'>', b'"', b'\'', b'`', b';', b',', b':',
+            b'.', b'?', b'=',
+        ];
 
-        false
+        // Using byte comparison for faster lookup
+        DELIMITERS.contains(&ch)
     }
+}
 
-    /// Determines if a character is in a section header [section] or [section.subsection]
-    fn is_in_section_header(buffer: &FileBuffer, pos: usize) -> bool {
-        // Safety check for position
-        if pos >= buffer.size {
-            return false;
+impl Drop for SyntaxHighlighter {
+    fn drop(&mut self) {
+        if !self.states.is_null() && self.states_capacity > 0 {
+            let _ = munmap(self.states as usize, self.states_capacity * 2);
         }
+    }
+}
 
-        let line_start = Self::find_line_start(buffer, pos);
-        let mut i = line_start;
+/// Tokenizes a single line, yielding the color of each byte in order
+pub struct LineLexer<'a> {
+    file_type: FileType,
+    line: &'a [u8],
+    pos: usize,
+    state: LexState,
+    /// Color and remaining length of the token being emitted
+    run_color: HighlightColor,
+    run_left: usize,
+    /// Index of a highlighted matching delimiter
+    matching: usize,
+    /// Config lines: start of a whole-line comment
+    comment_from: usize,
+    /// Config lines: contents of a `[section]` or `[[table]]` header
+    section: (usize, usize),
+    /// Config lines: end of the key in a `key = value` pair
+    key_end: usize,
+}
 
-        // Skip leading whitespace
-        while i < buffer.size && unsafe { *buffer.content.add(i) }.is_ascii_whitespace() {
-            i += 1;
-        }
+impl<'a> LineLexer<'a> {
+    fn new(file_type: FileType, line: &'a [u8], state: LexState, matching: usize) -> Self {
+        let mut lexer = Self {
+            file_type,
+            line,
+            pos: 0,
+            state,
+            run_color: HighlightColor::Default,
+            run_left: 0,
+            matching,
+            comment_from: usize::MAX,
+            section: (0, 0),
+            key_end: 0,
+        };
 
-        // Check for single bracket '[' but not double bracket '[[')
-        if i >= buffer.size || unsafe { *buffer.content.add(i) } != b'[' {
-            return false;
+        if let (FileType::ConfigFile, LexState::Normal) = (file_type, state) {
+            lexer.scan_config_line();
         }
+        lexer
+    }
 
-        // Skip double bracket case (array tables) - handled by separate method
-        if i + 1 < buffer.size && unsafe { *buffer.content.add(i + 1) } == b'[' {
-            return false;
+    /// Runs the lexer to the end of the line and returns the state for the next one
+    fn end_state(mut self) -> LexState {
+        while self.pos < self.line.len() {
+            let (_, len, state) = self.next_token();
+            self.pos += len;
+            self.state = state;
         }
 
-        // Only proceed if we might be inside the section header (after the opening bracket)
-        if pos <= i {
-            return false;
+        match (self.state, self.file_type) {
+            // Rust strings span lines; C strings only with a trailing backslash
+            (LexState::String, FileType::C) if self.line.last() == Some(&b'\\') => self.state,
+            (LexState::String, FileType::C | FileType::ConfigFile) => LexState::Normal,
+            _ => self.state,
         }
+    }
 
-        // Find the closing bracket
-        let mut j = i + 1;
-        while j < buffer.size {
-            let ch = unsafe { *buffer.content.add(j) };
+    /// Finds comment, section header and key ranges of a config line
+    fn scan_config_line(&mut self) {
+        let line = self.line;
+        let Some(first) = line.iter().position(|c| !c.is_ascii_whitespace()) else {
+            return;
+        };
 
-            if ch == b'\n' {
-                return false; // No closing bracket found on this line
-            } else if ch == b']' {
-                // We're in a section header if pos is between the opening and closing brackets
-                return pos < j;
+        match line[first] {
+            b'#' | b';' => self.comment_from = first,
+            b'[' => {
+                // Array tables use double brackets
+                let (open, close): (usize, &[u8]) = if line.get(first + 1) == Some(&b'[') {
+                    (first + 2, b"]]")
+                } else {
+                    (first + 1, b"]")
+                };
+                if let Some(end) = find(&line[open..], close) {
+                    self.section = (open, open + end);
+                }
+            }
+            _ => {
+                if let Some(sep) = line.iter().position(|&c| c == b'=' || c == b':') {
+                    self.key_end = sep;
+                }
             }
-
-            j += 1;
         }
-
-        false
     }
 
-    /// Determines if a character is in a TOML array table declaration [[table]]
-    fn is_in_toml_array_table(buffer: &FileBuffer, pos: usize) -> bool {
-        // Safety check for position
-        if pos >= buffer.size {
-            return false;
+    /// Determines the color, length and following state of the token at `pos`
+    fn next_token(&self) -> (HighlightColor, usize, LexState) {
+        let rest = &self.line[self.pos..];
+        match self.state {
+            LexState::BlockComment(depth) => self.block_comment(rest, depth),
+            LexState::String => Self::string_body(rest),
+            LexState::RawString(hashes) => Self::raw_string_body(rest, hashes),
+            LexState::MultiLineString => Self::multi_line_string_body(rest),
+            LexState::Normal => match self.file_type {
+                FileType::PlainText => {
+                    let color = if SyntaxHighlighter::is_delimiter(rest[0]) {
+                        HighlightColor::Delimiter
+                    } else {
+                        HighlightColor::Default
+                    };
+                    (color, 1, LexState::Normal)
+                }
+                FileType::C | FileType::Rust => self.code_token(rest),
+                FileType::ConfigFile => self.config_token(rest),
+            },
         }
+    }
 
-        let line_start = Self::find_line_start(buffer, pos);
-        let mut i = line_start;
+    fn code_token(&self, rest: &[u8]) -> (HighlightColor, usize, LexState) {
+        if rest.starts_with(b"//") {
+            return (HighlightColor::Comment, rest.len(), LexState::Normal);
+        }
 
-        // Skip leading whitespace
-        while i < buffer.size && unsafe { *buffer.content.add(i) }.is_ascii_whitespace() {
-            i += 1;
+        if rest.starts_with(b"/*") {
+            return (HighlightColor::Comment, 2, LexState::BlockComment(1));
         }
 
-        // Check for opening double bracket '[['
-        if i + 1 >= buffer.size
-            || unsafe { *buffer.content.add(i) } != b'['
-            || unsafe { *buffer.content.add(i + 1) } != b'['
+        if let (FileType::Rust, Some((len, hashes))) =
+            (&self.file_type, Self::raw_string_start(rest))
         {
-            return false;
+            return (HighlightColor::Delimiter, len, LexState::RawString(hashes));
         }
 
-        // Only proceed if we might be inside the array table declaration (after the opening brackets)
-        if pos <= i + 1 {
-            return false;
+        // Char and byte literals, which may hold a quote; lifetimes and
+        // labels have no closing `'` and fall through to punctuation
+        let prefix = usize::from(rest.starts_with(b"b'"));
+        if let Some(len) = Self::char_literal_len(&rest[prefix..]) {
+            return (HighlightColor::String, prefix + len, LexState::Normal);
         }
 
-        // Find the closing double bracket ']]'
-        let mut j = i + 2;
+        let ch = rest[0];
+        if ch == b'"' {
+            return (HighlightColor::Delimiter, 1, LexState::String);
+        }
 
-        while j < buffer.size {
-            let ch = unsafe { *buffer.content.add(j) };
+        if ch.is_ascii_alphabetic() || ch == b'_' {
+            let len = word_len(rest, SyntaxHighlighter::is_word_char);
+            let keywords = if let FileType::C = self.file_type {
+                C_KEYWORDS
+            } else {
+                RUST_KEYWORDS
+            };
+            let color = if keywords.contains(&&rest[..len]) {
+                HighlightColor::Keyword
+            } else {
+                HighlightColor::Default
+            };
+            return (color, len, LexState::Normal);
+        }
 
-            if ch == b'\n' {
-                return false; // No closing brackets found on this line
-            } else if ch == b']'
-                && j + 1 < buffer.size
-                && unsafe { *buffer.content.add(j + 1) } == b']'
+        if ch.is_ascii_digit() {
+            // Digits, suffixes and a fractional part, but not a `..` range
+            let mut len = word_len(rest, SyntaxHighlighter::is_word_char);
+            while rest.get(len) == Some(&b'.') && rest.get(len + 1).is_some_and(u8::is_ascii_digit)
             {
-                // We're in an array table if pos is between the opening and closing brackets
-                return pos < j;
+                len += 1 + word_len(&rest[len + 1..], SyntaxHighlighter::is_word_char);
             }
-
-            j += 1;
+            return (HighlightColor::Number, len, LexState::Normal);
         }
 
-        false
+        (Self::punctuation(ch), 1, LexState::Normal)
     }
 
-    /// Determines if a character is in a key name in key-value pair
-    fn is_in_config_key(buffer: &FileBuffer, pos: usize) -> bool {
-        let line_start = Self::find_line_start(buffer, pos);
-
-        // Skip if this is a section header or comment line
-        let mut j = line_start;
-        while j < buffer.size {
-            let ch = unsafe { *buffer.content.add(j) };
-            if ch.is_ascii_whitespace() {
-                j += 1;
-                continue;
-            }
-            if ch == b'[' || ch == b'#' || ch == b';' {
-                return false;
-            }
-            break;
+    fn config_token(&self, rest: &[u8]) -> (HighlightColor, usize, LexState) {
+        let pos = self.pos;
+        if pos >= self.comment_from {
+            return (HighlightColor::Comment, rest.len(), LexState::Normal);
         }
 
-        // Check for key-value pattern
-        let mut i = line_start;
-        let mut found_non_whitespace = false;
-        let mut in_key = false;
-
-        while i < buffer.size {
-            let ch = unsafe { *buffer.content.add(i) };
-
-            if ch == b'\n' || ch == b'#' || ch == b';' {
-                break;
-            }
-
-            if !found_non_whitespace && ch.is_ascii_whitespace() {
-                i += 1;
-                continue;
-            }
-
-            found_non_whitespace = true;
-
-            if !in_key && (ch == b'=' || ch == b':') {
-                return pos < i;
-            }
-            in_key = true;
-
-            i += 1;
+        let (section_start, section_end) = self.section;
+        if pos >= section_start && pos < section_end {
+            return (HighlightColor::Keyword, section_end - pos, LexState::Normal);
         }
 
-        in_key && pos >= line_start
-    }
-
-    /// Determines if a character represents a number in a config file
-    fn is_config_number(buffer: &FileBuffer, pos: usize) -> bool {
-        #[cfg(test)]
-        if pos == 48
-            || (pos > 0
-                && pos + 1 < buffer.size
-                && unsafe { *buffer.content.add(pos - 1) }.is_ascii_digit()
-                && unsafe { *buffer.content.add(pos) } == b'_'
-                && unsafe { *buffer.content.add(pos + 1) }.is_ascii_digit())
-        {
-            return true;
+        if rest.starts_with(b"\"\"\"") {
+            return (HighlightColor::Delimiter, 3, LexState::MultiLineString);
         }
 
-        let ch = unsafe { *buffer.content.add(pos) };
-
-        // Quick check if character could be part of a number
-        if !ch.is_ascii_digit()
-            && ch != b'.'
-            && ch != b'-'
-            && ch != b'+'
-            && ch != b'x'
-            && ch != b'o'
-            && ch != b'b'
-            && ch != b'_'
-            && !(b'a'..=b'f').contains(&ch)
-            && !(b'A'..=b'F').contains(&ch)
-        {
-            return false;
+        // Char and byte literals, which may hold a quote; lifetimes and
+        // labels have no closing `'` and fall through to punctuation
+        let prefix = usize::from(rest.starts_with(b"b'"));
+        if let Some(len) = Self::char_literal_len(&rest[prefix..]) {
+            return (HighlightColor::String, prefix + len, LexState::Normal);
         }
 
-        // Find the start of the token
-        let token_start = Self::find_token_start(buffer, pos);
-        let mut has_digit = false;
-        let mut i = token_start;
-
-        // Skip leading sign if present
-        if i < buffer.size
-            && (unsafe { *buffer.content.add(i) } == b'-'
-                || unsafe { *buffer.content.add(i) } == b'+')
-        {
-            i += 1;
+        let ch = rest[0];
+        if ch == b'"' {
+            return (HighlightColor::Delimiter, 1, LexState::String);
         }
 
-        // Check for special number formats (0x, 0o, 0b)
-        let (special_format, format_type, i) = if i + 1 < buffer.size
-            && unsafe { *buffer.content.add(i) } == b'0'
-            && (unsafe { *buffer.content.add(i + 1) } == b'x'
-                || unsafe { *buffer.content.add(i + 1) } == b'o'
-                || unsafe { *buffer.content.add(i + 1) } == b'b')
-        {
-            (true, unsafe { *buffer.content.add(i + 1) }, i + 2)
-        } else {
-            (false, 0u8, i)
-        };
-
-        // Parse the main part of the number
-        let mut i = i;
-        let mut has_decimal = false;
-        let mut has_exp = false;
-
-        while i < buffer.size && i <= pos {
-            let current_ch = unsafe { *buffer.content.add(i) };
-
-            if current_ch.is_ascii_digit() {
-                has_digit = true;
-            } else if current_ch == b'_' {
-                // Skip underscores between digits
-            } else if current_ch == b'.' && !has_decimal && !special_format {
-                has_decimal = true;
-            } else if (current_ch == b'e' || current_ch == b'E') && !has_exp && !special_format {
-                // Handle scientific notation
-                has_exp = true;
-                if i + 1 < buffer.size
-                    && (unsafe { *buffer.content.add(i + 1) } == b'+'
-                        || unsafe { *buffer.content.add(i + 1) } == b'-')
-                {
-                    i += 1; // Skip the sign after the exponent
-                }
-            } else if special_format
-                && format_type == b'x'
-                && ((b'a'..=b'f').contains(&current_ch) || (b'A'..=b'F').contains(&current_ch))
-            {
-                has_digit = true;
-            } else if current_ch.is_ascii_whitespace()
-                || current_ch == b','
-                || current_ch == b';'
-                || current_ch == b'\n'
-                || current_ch == b']'
-                || current_ch == b')'
-            {
-                break;
+        let starts_number = ch.is_ascii_digit()
+            || ((ch == b'-' || ch == b'+') && rest.get(1).is_some_and(u8::is_ascii_digit));
+        if starts_number && pos >= self.key_end {
+            let len = number_len(rest);
+            let color = if SyntaxHighlighter::is_config_number(&rest[..len]) {
+                HighlightColor::Number
             } else {
-                return false;
-            }
-
-            i += 1;
+                HighlightColor::Default
+            };
+            return (color, len, LexState::Normal);
+        }
+
+        if ch.is_ascii_alphanumeric() || ch == b'_' || ch == b'-' {
+            let len = word_len(rest, |c| SyntaxHighlighter::is_word_char(c) || c == b'-');
+            let color = if CONFIG_KEYWORDS.contains(&&rest[..len]) {
+                HighlightColor::Keyword
+            } else if pos < self.key_end {
+                // Keys are shown in the number color
+                HighlightColor::Number
+            } else {
+                HighlightColor::Default
+            };
+            return (color, len, LexState::Normal);
         }
 
-        has_digit
+        (Self::punctuation(ch), 1, LexState::Normal)
     }
 
-    /// Find the start of a token at the given position
-    fn find_token_start(buffer: &FileBuffer, pos: usize) -> usize {
-        let mut token_start = pos;
-        while token_start > 0 {
-            let prev_ch = unsafe { *buffer.content.add(token_start - 1) };
-            if prev_ch.is_ascii_whitespace() || prev_ch == b'=' || prev_ch == b':' {
-                break;
-            }
-            token_start -= 1;
+    fn punctuation(ch: u8) -> HighlightColor {
+        if SyntaxHighlighter::is_delimiter(ch) {
+            HighlightColor::Delimiter
+        } else {
+            HighlightColor::Default
         }
-        token_start
     }
 
-    /// Finds the start of line containing the given position
-    fn find_line_start(buffer: &FileBuffer, pos: usize) -> usize {
-        let mut line_start = pos;
-        while line_start > 0 {
-            let ch = unsafe { *buffer.content.add(line_start - 1) };
-            if ch == b'\n' {
-                break;
+    fn block_comment(&self, rest: &[u8], depth: u8) -> (HighlightColor, usize, LexState) {
+        let nests = matches!(self.file_type, FileType::Rust);
+        let mut i = 0;
+        while i + 1 < rest.len() {
+            if rest[i] == b'*' && rest[i + 1] == b'/' {
+                let state = if depth <= 1 {
+                    LexState::Normal
+                } else {
+                    LexState::BlockComment(depth - 1)
+                };
+                return (HighlightColor::Comment, i + 2, state);
             }
-            line_start -= 1;
+            if nests && rest[i] == b'/' && rest[i + 1] == b'*' {
+                let state = LexState::BlockComment(depth.saturating_add(1));
+                return (HighlightColor::Comment, i + 2, state);
+            }
+            i += 1;
         }
-        line_start
+        (
+            HighlightColor::Comment,
+            rest.len(),
+            LexState::BlockComment(depth),
+        )
     }
 
-    /// Determines if a position is within a keyword
-    fn is_in_keyword(&self, buffer: &FileBuffer, pos: usize) -> bool {
-        // Early check to avoid unnecessary processing
-        if pos >= buffer.size {
-            return false;
-        }
-
-        let ch = unsafe { *buffer.content.add(pos) };
-        if !ch.is_ascii_alphabetic() && ch != b'_' {
-            return false;
+    /// String contents up to the closing quote, which is a delimiter itself
+    fn string_body(rest: &[u8]) -> (HighlightColor, usize, LexState) {
+        if rest[0] == b'"' {
+            return (HighlightColor::Delimiter, 1, LexState::Normal);
         }
 
-        let word_start = Self::find_word_start(buffer, pos);
-
-        // Optimization: guard against buffer overflow before checking keywords
-        if word_start >= buffer.size {
-            return false;
-        }
-
-        match self.file_type {
-            FileType::C => Self::is_c_keyword(buffer, word_start),
-            FileType::Rust => Self::is_rust_keyword(buffer, word_start),
-            FileType::ConfigFile => Self::is_config_keyword(buffer, word_start),
-            FileType::PlainText => false,
+        let mut i = 0;
+        while i < rest.len() && rest[i] != b'"' {
+            // Escaped characters never end the string
+            i += if rest[i] == b'\\' { 2 } else { 1 };
         }
+        (HighlightColor::String, i.min(rest.len()), LexState::String)
     }
 
-    /// Find the start of the word at the given position
-    fn find_word_start(buffer: &FileBuffer, pos: usize) -> usize {
-        let mut word_start = pos;
-        while word_start > 0 {
-            let prev_ch = unsafe { *buffer.content.add(word_start - 1) };
-            if !prev_ch.is_ascii_alphanumeric() && prev_ch != b'_' {
-                break;
-            }
-            word_start -= 1;
+    /// Length of a `'x'`, `'\n'` or `'\u{...}'` literal at the start of `rest`
+    fn char_literal_len(rest: &[u8]) -> Option<usize> {
+        if rest.first() != Some(&b'\'') {
+            return None;
         }
-        word_start
+        let body = match *rest.get(1)? {
+            // Escapes run from the backslash to the closing quote: \' \\ \x41 \u{263A}
+            b'\\' => {
+                let end = rest.get(3..)?.iter().take(10).position(|&c| c == b'\'')?;
+                3 + end
+            }
+            b'\'' => return None,
+            // One character, which may take several bytes
+            lead => {
+                1 + match lead {
+                    0xf0.. => 4,
+                    0xe0.. => 3,
+                    0xc0.. => 2,
+                    _ => 1,
+                }
+            }
+        };
+        (rest.get(body) == Some(&b'\'')).then_some(body + 1)
     }
 
-    /// Check if the buffer slice at start matches the given word
-    fn buffer_slice_matches(buffer: &FileBuffer, start: usize, word: &[u8]) -> bool {
-        // Early return if the word doesn't fit in the buffer
-        if start + word.len() > buffer.size {
-            return false;
-        }
+    /// Length of a raw string opening (`r"`, `r#"`, `br##"`) and its number of hashes
+    fn raw_string_start(rest: &[u8]) -> Option<(usize, u8)> {
+        let prefix = if rest.starts_with(b"br") {
+            2
+        } else if rest.starts_with(b"r") {
+            1
+        } else {
+            return None;
+        };
 
-        // Check that the character before is not alphanumeric (to ensure it's a whole word)
-        // Only if not at the beginning of the buffer
-        if start > 0 {
-            let prev_ch = unsafe { *buffer.content.add(start - 1) };
-            if prev_ch.is_ascii_alphanumeric() || prev_ch == b'_' {
-                return false;
-            }
+        let hashes = rest[prefix..].iter().take_while(|&&c| c == b'#').count();
+        if rest.get(prefix + hashes) != Some(&b'"') {
+            return None;
         }
+        Some((prefix + hashes + 1, u8::try_from(hashes).ok()?))
+    }
 
-        // Efficient comparison of words with early return
-        for (i, c) in word.iter().enumerate() {
-            if unsafe { *buffer.content.add(start + i) } != *c {
-                return false;
-            }
-        }
+    fn raw_string_body(rest: &[u8], hashes: u8) -> (HighlightColor, usize, LexState) {
+        let hashes = usize::from(hashes);
+        let closes_at = |i: usize| {
+            rest[i] == b'"'
+                && rest.len() > i + hashes
+                && rest[i + 1..=i + hashes].iter().all(|&c| c == b'#')
+        };
 
-        // Ensure this is a whole word by checking the character after the word
-        if start + word.len() < buffer.size {
-            let next_ch = unsafe { *buffer.content.add(start + word.len()) };
-            if next_ch.is_ascii_alphanumeric() || next_ch == b'_' {
-                return false;
-            }
+        if closes_at(0) {
+            return (HighlightColor::Delimiter, hashes + 1, LexState::Normal);
         }
-
-        true
+        let len = (1..rest.len())
+            .find(|&i| closes_at(i))
+            .unwrap_or(rest.len());
+        (
+            HighlightColor::String,
+            len,
+            LexState::RawString(u8::try_from(hashes).unwrap_or(u8::MAX)),
+        )
     }
 
-    /// Check if the buffer slice at start is a C keyword
-    fn is_c_keyword(buffer: &FileBuffer, start: usize) -> bool {
-        Self::buffer_slice_matches(buffer, start, b"if")
-            || Self::buffer_slice_matches(buffer, start, b"else")
-            || Self::buffer_slice_matches(buffer, start, b"for")
-            || Self::buffer_slice_matches(buffer, start, b"while")
-            || Self::buffer_slice_matches(buffer, start, b"do")
-            || Self::buffer_slice_matches(buffer, start, b"switch")
-            || Self::buffer_slice_matches(buffer, start, b"case")
-            || Self::buffer_slice_matches(buffer, start, b"break")
-            || Self::buffer_slice_matches(buffer, start, b"continue")
-            || Self::buffer_slice_matches(buffer, start, b"return")
-            || Self::buffer_slice_matches(buffer, start, b"int")
-            || Self::buffer_slice_matches(buffer, start, b"char")
-            || Self::buffer_slice_matches(buffer, start, b"void")
-            || Self::buffer_slice_matches(buffer, start, b"struct")
-            || Self::buffer_slice_matches(buffer, start, b"typedef")
-            || Self::buffer_slice_matches(buffer, start, b"enum")
-            || Self::buffer_slice_matches(buffer, start, b"sizeof")
-            || Self::buffer_slice_matches(buffer, start, b"static")
-            || Self::buffer_slice_matches(buffer, start, b"const")
+    fn multi_line_string_body(rest: &[u8]) -> (HighlightColor, usize, LexState) {
+        if rest.starts_with(b"\"\"\"") {
+            return (HighlightColor::Delimiter, 3, LexState::Normal);
+        }
+        let len = find(&rest[1..], b"\"\"\"").map_or(rest.len(), |i| i + 1);
+        (HighlightColor::String, len, LexState::MultiLineString)
     }
+}
 
-    /// Check if the buffer slice at start is a Rust keyword
-    fn is_rust_keyword(buffer: &FileBuffer, start: usize) -> bool {
-        Self::buffer_slice_matches(buffer, start, b"fn")
-            || Self::buffer_slice_matches(buffer, start, b"let")
-            || Self::buffer_slice_matches(buffer, start, b"mut")
-            || Self::buffer_slice_matches(buffer, start, b"struct")
-            || Self::buffer_slice_matches(buffer, start, b"enum")
-            || Self::buffer_slice_matches(buffer, start, b"trait")
-            || Self::buffer_slice_matches(buffer, start, b"impl")
-            || Self::buffer_slice_matches(buffer, start, b"pub")
-            || Self::buffer_slice_matches(buffer, start, b"use")
-            || Self::buffer_slice_matches(buffer, start, b"mod")
-            || Self::buffer_slice_matches(buffer, start, b"if")
-            || Self::buffer_slice_matches(buffer, start, b"else")
-            || Self::buffer_slice_matches(buffer, start, b"match")
-            || Self::buffer_slice_matches(buffer, start, b"for")
-            || Self::buffer_slice_matches(buffer, start, b"while")
-            || Self::buffer_slice_matches(buffer, start, b"loop")
-            || Self::buffer_slice_matches(buffer, start, b"break")
-            || Self::buffer_slice_matches(buffer, start, b"continue")
-            || Self::buffer_slice_matches(buffer, start, b"return")
-    }
+impl Iterator for LineLexer<'_> {
+    type Item = HighlightColor;
 
-    /// Check if the buffer slice at start is a config keyword
-    fn is_config_keyword(buffer: &FileBuffer, start: usize) -> bool {
-        // Check if buffer matches any common boolean value keyword
-        if Self::buffer_slice_matches(buffer, start, b"true")
-            || Self::buffer_slice_matches(buffer, start, b"false")
-            || Self::buffer_slice_matches(buffer, start, b"yes")
-            || Self::buffer_slice_matches(buffer, start, b"no")
-            || Self::buffer_slice_matches(buffer, start, b"on")
-            || Self::buffer_slice_matches(buffer, start, b"off")
-            || Self::buffer_slice_matches(buffer, start, b"null")
-            || Self::buffer_slice_matches(buffer, start, b"None")
-        {
-            return true;
-        }
+    fn next(&mut self) -> Option<HighlightColor> {
+        if self.run_left == 0 {
+            if self.pos >= self.line.len() {
+                return None;
+            }
 
-        // Check if buffer matches any Cargo metadata keyword
-        if Self::buffer_slice_matches(buffer, start, b"name")
-            || Self::buffer_slice_matches(buffer, start, b"version")
-            || Self::buffer_slice_matches(buffer, start, b"authors")
-            || Self::buffer_slice_matches(buffer, start, b"edition")
-            || Self::buffer_slice_matches(buffer, start, b"description")
-            || Self::buffer_slice_matches(buffer, start, b"license")
-            || Self::buffer_slice_matches(buffer, start, b"repository")
-            || Self::buffer_slice_matches(buffer, start, b"homepage")
-            || Self::buffer_slice_matches(buffer, start, b"documentation")
-            || Self::buffer_slice_matches(buffer, start, b"readme")
-            || Self::buffer_slice_matches(buffer, start, b"publish")
-        {
-            return true;
+            let (color, len, state) = self.next_token();
+            self.run_color = color;
+            self.run_left = len.max(1);
+            self.state = state;
         }
 
-        // Check if buffer matches any Cargo dependencies keyword
-        if Self::buffer_slice_matches(buffer, start, b"dependencies")
-            || Self::buffer_slice_matches(buffer, start, b"dev-dependencies")
-            || Self::buffer_slice_matches(buffer, start, b"build-dependencies")
-            || Self::buffer_slice_matches(buffer, start, b"features")
-            || Self::buffer_slice_matches(buffer, start, b"package")
-            || Self::buffer_slice_matches(buffer, start, b"workspace")
-        {
-            return true;
-        }
+        let idx = self.pos;
+        self.pos += 1;
+        self.run_left -= 1;
 
-        // Check if buffer matches any Cargo build keyword
-        if Self::buffer_slice_matches(buffer, start, b"bin")
-            || Self::buffer_slice_matches(buffer, start, b"lib")
-            || Self::buffer_slice_matches(buffer, start, b"test")
-            || Self::buffer_slice_matches(buffer, start, b"bench")
-            || Self::buffer_slice_matches(buffer, start, b"example")
-            || Self::buffer_slice_matches(buffer, start, b"default-run")
-        {
-            return true;
+        // Check for matching position
+        if idx == self.matching {
+            return Some(HighlightColor::Delimiter);
         }
+        Some(self.run_color)
+    }
+}
 
-        // Check if buffer matches any Cargo config keyword
-        if Self::buffer_slice_matches(buffer, start, b"profile")
-            || Self::buffer_slice_matches(buffer, start, b"debug")
-            || Self::buffer_slice_matches(buffer, start, b"release")
-            || Self::buffer_slice_matches(buffer, start, b"target")
-            || Self::buffer_slice_matches(buffer, start, b"opt-level")
-            || Self::buffer_slice_matches(buffer, start, b"debug-assertions")
-            || Self::buffer_slice_matches(buffer, start, b"codegen-units")
-            || Self::buffer_slice_matches(buffer, start, b"lto")
-            || Self::buffer_slice_matches(buffer, start, b"path")
-            || Self::buffer_slice_matches(buffer, start, b"include")
-            || Self::buffer_slice_matches(buffer, start, b"exclude")
-            || Self::buffer_slice_matches(buffer, start, b"required")
-            || Self::buffer_slice_matches(buffer, start, b"optional")
-        {
-            return true;
-        }
+/// Number of leading bytes accepted by `is_part`
+fn word_len(bytes: &[u8], is_part: impl Fn(u8) -> bool) -> usize {
+    bytes.iter().take_while(|&&c| is_part(c)).count()
+}
 
-        // Check if buffer matches any other config keyword
-        if Self::buffer_slice_matches(buffer, start, b"default")
-            || Self::buffer_slice_matches(buffer, start, b"level")
-            || Self::buffer_slice_matches(buffer, start, b"warn")
-            || Self::buffer_slice_matches(buffer, start, b"error")
-            || Self::buffer_slice_matches(buffer, start, b"categories")
-            || Self::buffer_slice_matches(buffer, start, b"keywords")
-        {
-            return true;
+/// Length of a config value number token, including an exponent sign
+fn number_len(bytes: &[u8]) -> usize {
+    let mut len = 1;
+    while len < bytes.len() {
+        let ch = bytes[len];
+        let exp_sign = (ch == b'+' || ch == b'-') && matches!(bytes[len - 1], b'e' | b'E');
+        if !(SyntaxHighlighter::is_word_char(ch) || ch == b'.' || exp_sign) {
+            break;
         }
-
-        false
+        len += 1;
     }
+    len
+}
 
-    /// Determines if a character is a delimiter (brackets, parentheses, braces, etc.)
-    fn is_delimiter(ch: u8) -> bool {
-        // Using a byte array instead of matches! macro for better performance
-        const DELIMITERS: [u8; 17] = [
-            b'(', b')', b'[', b']', b'{', b'}', b'<', b'>', b'"', b'\'', b'`', b';', b',', b':',
-            b'.', b'?', b'=',
-        ];
-
-        // Using byte comparison for faster lookup
-        DELIMITERS.contains(&ch)
-    }
+/// Position of the first occurrence of `needle` in `haystack`
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
 }
 
 #[cfg(test)]
@@ -794,12 +814,6 @@ mod tests {
         );
 
         // Test 2: Verify that characters inside quotes are part of a string
-        assert!(
-            SyntaxHighlighter::is_in_string(&buffer, value_pos),
-            "Characters between quotes should be detected as part of string content"
-        );
-
-        // Check the color of the string content
         let string_color = highlighter.highlight_char(&buffer, value_pos);
         assert_eq!(
             string_color,
@@ -808,11 +822,6 @@ mod tests {
         );
 
         // Test 3: Verify that closing quote is a delimiter
-        assert!(
-            !SyntaxHighlighter::is_in_string(&buffer, end_quote_pos),
-            "Closing double quote should not be considered part of string content"
-        );
-
         let color = highlighter.highlight_char(&buffer, end_quote_pos);
         assert_eq!(
             color,
@@ -844,8 +853,7 @@ mod tests {
                     '?'
                 };
                 let color = highlighter.highlight_char(&complex_buffer, i);
-                let is_str = SyntaxHighlighter::is_in_string(&complex_buffer, i);
-                println!("Pos {i}: '{c}' (byte: {ch}), is_string={is_str}, color={color:?}");
+                println!("Pos {i}: '{c}' (byte: {ch}), color={color:?}");
             }
             println!("Escaped quote position: {escaped_pos}");
         }
@@ -854,18 +862,19 @@ mod tests {
         let string_content_pos = 9; // Position clearly inside the string
 
         // Test 4: Verify that normal string content is properly highlighted
-        assert!(
-            SyntaxHighlighter::is_in_string(&complex_buffer, string_content_pos),
-            "Content inside the string should be detected as part of string"
-        );
-
-        // And the content should be highlighted as String
         let string_content_color = highlighter.highlight_char(&complex_buffer, string_content_pos);
         assert_eq!(
             string_content_color,
             HighlightColor::String,
             "Content inside quotes should be highlighted as String"
         );
+
+        // Escaped quotes do not end the string
+        assert_eq!(
+            highlighter.highlight_char(&complex_buffer, escaped_pos + 1),
+            HighlightColor::String,
+            "Content after an escaped quote should still be part of the string"
+        );
     }
 
     #[test]
@@ -938,29 +947,41 @@ mod tests {
 
         // Test line comment detection
         let in_line_comment_pos = 15; // Position inside "// Line comment"
-        assert!(
-            SyntaxHighlighter::is_in_comment(&buffer, in_line_comment_pos),
+        assert_eq!(
+            highlighter.highlight_char(&buffer, in_line_comment_pos),
+            HighlightColor::Comment,
             "Expected position to be inside line comment"
         );
 
         // Test not in comment
         let not_in_comment_pos = 5; // Position in "normal code"
-        assert!(
-            !SyntaxHighlighter::is_in_comment(&buffer, not_in_comment_pos),
+        assert_ne!(
+            highlighter.highlight_char(&buffer, not_in_comment_pos),
+            HighlightColor::Comment,
             "Expected position to not be in a comment"
         );
 
         // Test block comment detection
         let in_block_comment_pos = 40; // Position inside block comment
-        assert!(
-            SyntaxHighlighter::is_in_comment(&buffer, in_block_comment_pos),
+        assert_eq!(
+            highlighter.highlight_char(&buffer, in_block_comment_pos),
+            HighlightColor::Comment,
             "Expected position to be inside block comment"
         );
 
+        // The block comment continues on the next line up to its end
+        let continued_comment_pos = 60; // Position in "multi-line */"
+        assert_eq!(
+            highlighter.highlight_char(&buffer, continued_comment_pos),
+            HighlightColor::Comment,
+            "Expected block comment to span lines"
+        );
+
         // Test position after block comment
-        let after_block_comment = 65; // Position in "code after block"
-        assert!(
-            !SyntaxHighlighter::is_in_comment(&buffer, after_block_comment),
+        let after_block_comment = 70; // Position in "code after block"
+        assert_ne!(
+            highlighter.highlight_char(&buffer, after_block_comment),
+            HighlightColor::Comment,
             "Expected position after block comment to not be in a comment"
         );
     }
@@ -976,29 +997,33 @@ mod tests {
 
         // Test comment detection
         let in_comment_pos = 5; // Position in "# This is a comment"
-        assert!(
-            SyntaxHighlighter::is_in_config_comment(&buffer, in_comment_pos),
+        assert_eq!(
+            highlighter.highlight_char(&buffer, in_comment_pos),
+            HighlightColor::Comment,
             "Expected position to be inside comment"
         );
 
         // Test section header
         let in_section_pos = 22; // Position in "[section.name]"
-        assert!(
-            SyntaxHighlighter::is_in_section_header(&buffer, in_section_pos),
+        assert_eq!(
+            highlighter.highlight_char(&buffer, in_section_pos),
+            HighlightColor::Keyword,
             "Expected position to be inside section header"
         );
 
         // Test key detection
         let in_key_pos = 36; // Position in "key1"
-        assert!(
-            SyntaxHighlighter::is_in_config_key(&buffer, in_key_pos),
+        assert_eq!(
+            highlighter.highlight_char(&buffer, in_key_pos),
+            HighlightColor::Number,
             "Expected position to be inside key"
         );
 
         // Test number detection
-        let in_number_pos = 48; // Position in "42"
-        assert!(
-            SyntaxHighlighter::is_config_number(&buffer, in_number_pos),
+        let in_number_pos = 55; // Position in "42"
+        assert_eq!(
+            highlighter.highlight_char(&buffer, in_number_pos),
+            HighlightColor::Number,
             "Expected position to be detected as a number"
         );
 
@@ -1020,8 +1045,9 @@ mod tests {
             }
         }
 
-        assert!(
-            highlighter.is_in_keyword(&buffer, true_pos),
+        assert_eq!(
+            highlighter.highlight_char(&buffer, true_pos),
+            HighlightColor::Keyword,
             "Expected 'true' to be detected as keyword"
         );
 
@@ -1042,8 +1068,9 @@ mod tests {
             }
         }
 
-        assert!(
-            highlighter.is_in_keyword(&buffer, yes_pos),
+        assert_eq!(
+            highlighter.highlight_char(&buffer, yes_pos),
+            HighlightColor::Keyword,
             "Expected 'yes' to be detected as keyword"
         );
     }
@@ -1067,8 +1094,9 @@ mod tests {
             }
         }
 
-        assert!(
-            SyntaxHighlighter::is_in_toml_array_table(&buffer, arr_table_pos),
+        assert_eq!(
+            highlighter.highlight_char(&buffer, arr_table_pos),
+            HighlightColor::Keyword,
             "Expected position to be inside TOML array table"
         );
 
@@ -1082,8 +1110,9 @@ mod tests {
             }
         }
 
-        assert!(
-            SyntaxHighlighter::is_in_section_header(&buffer, section_pos),
+        assert_eq!(
+            highlighter.highlight_char(&buffer, section_pos),
+            HighlightColor::Keyword,
             "Expected position to be inside section header"
         );
 
@@ -1097,8 +1126,9 @@ mod tests {
             }
         }
 
-        assert!(
-            SyntaxHighlighter::is_config_number(&buffer, hex_pos),
+        assert_eq!(
+            highlighter.highlight_char(&buffer, hex_pos),
+            HighlightColor::Number,
             "Expected hexadecimal number to be detected"
         );
 
@@ -1113,7 +1143,9 @@ mod tests {
         }
 
         assert!(
-            SyntaxHighlighter::is_config_number(&buffer, underscored_num_pos),
+            SyntaxHighlighter::is_config_number(
+                &content[underscored_num_pos - 1..underscored_num_pos + 8]
+            ),
             "Expected number with underscores to be detected"
         );
 
@@ -1127,43 +1159,13 @@ mod tests {
             }
         }
 
-        assert!(
-            SyntaxHighlighter::is_config_number(&buffer, exp_pos),
+        assert_eq!(
+            highlighter.highlight_char(&buffer, exp_pos),
+            HighlightColor::Number,
             "Expected number with exponential notation to be detected"
         );
     }
 
-    #[test]
-    fn test_buffer_slice_matches() {
-        // Create a test buffer
-        let content = b"test content\n";
-        let buffer = create_test_file_buffer(content);
-
-        // Test matching slice
-        assert!(
-            SyntaxHighlighter::buffer_slice_matches(&buffer, 0, b"test"),
-            "Expected 'test' to match at position 0"
-        );
-
-        // Test non-matching slice
-        assert!(
-            !SyntaxHighlighter::buffer_slice_matches(&buffer, 0, b"rest"),
-            "Expected 'rest' to not match at position 0"
-        );
-
-        // Test partial match
-        assert!(
-            SyntaxHighlighter::buffer_slice_matches(&buffer, 5, b"content"),
-            "Expected 'content' to match at position 5"
-        );
-
-        // Test match that would go out of bounds
-        assert!(
-            !SyntaxHighlighter::buffer_slice_matches(&buffer, 8, b"content"),
-            "Expected match to fail when it would go beyond buffer size"
-        );
-    }
-
     #[test]
     fn test_delimiter_highlighting() {
         // Create a test buffer with various delimiters
@@ -1194,4 +1196,163 @@ mod tests {
             "Character at position {non_delimiter_pos} should not be highlighted as delimiter"
         );
     }
+
+    #[test]
+    fn test_block_comment_across_lines() {
+        let content = b"int a;\n/* start\nstill comment\n*/ int b;\n";
+        let buffer = create_test_file_buffer(content);
+        let mut highlighter = SyntaxHighlighter::new();
+        highlighter.detect_file_type(b"test.c\0");
+
+        // Every byte of the middle line belongs to the comment
+        for pos in 16..29 {
+            assert_eq!(
+                highlighter.highlight_char(&buffer, pos),
+                HighlightColor::Comment,
+                "Expected position {pos} to be inside the block comment"
+            );
+        }
+
+        // The comment ends on the last line
+        assert_eq!(
+            highlighter.highlight_char(&buffer, 30),
+            HighlightColor::Comment
+        );
+        assert_eq!(
+            highlighter.highlight_char(&buffer, 33),
+            HighlightColor::Keyword
+        );
+    }
+
+    #[test]
+    fn test_edit_invalidates_cached_states() {
+        let content = b"int a;\nint b;\nint c;\n";
+        let prot = crate::syscall::PROT_READ | crate::syscall::PROT_WRITE;
+        let flags = crate::syscall::MAP_PRIVATE | crate::syscall::MAP_ANONYMOUS;
+        let Ok(addr) = crate::syscall::mmap(0, 4096, prot, flags, usize::MAX, 0) else {
+            panic!("Failed to allocate test buffer: mmap error");
+        };
+        let mut buffer = create_test_file_buffer(content);
+        buffer.content = addr as *mut u8;
+        buffer.capacity = 4096;
+        unsafe { core::ptr::copy_nonoverlapping(content.as_ptr(), buffer.content, content.len()) };
+        let mut highlighter = SyntaxHighlighter::new();
+        highlighter.detect_file_type(b"test.c\0");
+
+        // Lex the whole buffer once so every line state is cached
+        assert_eq!(
+            highlighter.highlight_char(&buffer, 14),
+            HighlightColor::Keyword
+        );
+
+        // Opening a comment on the first line must recolor the lines below it
+        buffer.insert_char(0, 0, b'*').unwrap();
+        buffer.insert_char(0, 0, b'/').unwrap();
+        assert_eq!(
+            highlighter.highlight_char(&buffer, 16),
+            HighlightColor::Comment
+        );
+
+        // Closing it again brings the keywords back
+        buffer.insert_char(0, 8, b'*').unwrap();
+        buffer.insert_char(0, 9, b'/').unwrap();
+        assert_eq!(
+            highlighter.highlight_char(&buffer, 18),
+            HighlightColor::Keyword
+        );
+    }
+
+    #[test]
+    fn test_rust_raw_strings_and_nested_comments() {
+        let content = b"let s = r#\"a \" b\n\"#; /* x /* y */ z */ fn\n";
+        let buffer = create_test_file_buffer(content);
+        let mut highlighter = SyntaxHighlighter::new();
+        highlighter.detect_file_type(b"main.rs\0");
+
+        // A plain quote does not close a raw string, even across lines
+        assert_eq!(
+            highlighter.highlight_char(&buffer, 13),
+            HighlightColor::String
+        );
+        assert_eq!(
+            highlighter.highlight_char(&buffer, 15),
+            HighlightColor::String
+        );
+
+        // Comments nest, so "z" is still inside the outer one
+        let z_pos = find(content, b"z").unwrap();
+        assert_eq!(
+            highlighter.highlight_char(&buffer, z_pos),
+            HighlightColor::Comment
+        );
+        let fn_pos = find(content, b"fn").unwrap();
+        assert_eq!(
+            highlighter.highlight_char(&buffer, fn_pos),
+            HighlightColor::Keyword
+        );
+    }
+
+    #[test]
+    fn test_rust_char_literals_and_lifetimes() {
+        let end_state = |line: &[u8]| {
+            LineLexer::new(FileType::Rust, line, LexState::Normal, usize::MAX).end_state()
+        };
+        assert_eq!(end_state(b"let q = b'\"';"), LexState::Normal);
+        assert_eq!(end_state(b"let next = 1;"), LexState::Normal);
+        for line in [
+            &b"let c = '\"'; let d = '\\''; let e = '\\\\';"[..],
+            b"let f = '\\u{1F600}'; let g = '\xc3\xa9'; let h = \"x\";",
+            b"fn f<'a>(x: &'a str) -> &'static str { 'outer: loop {} }",
+        ] {
+            assert_eq!(end_state(line), LexState::Normal);
+        }
+
+        // Only the literal is colored as a string, not the lifetime
+        let content = b"let q = b'\"'; fn f<'a>() {}\nfn g() {}\n";
+        let buffer = create_test_file_buffer(content);
+        let mut highlighter = SyntaxHighlighter::new();
+        highlighter.detect_file_type(b"main.rs\0");
+        let quote = find(content, b"b'").unwrap();
+        for pos in quote..quote + 4 {
+            assert_eq!(
+                highlighter.highlight_char(&buffer, pos),
+                HighlightColor::String
+            );
+        }
+        let lifetime = find(content, b"'a").unwrap() + 1;
+        assert_eq!(
+            highlighter.highlight_char(&buffer, lifetime),
+            HighlightColor::Default
+        );
+        let g_fn = find(content, b"fn g").unwrap();
+        assert_eq!(
+            highlighter.highlight_char(&buffer, g_fn),
+            HighlightColor::Keyword
+        );
+    }
+
+    #[test]
+    fn test_toml_multi_line_strings() {
+        let content = b"text = \"\"\"\nkey = 1\n\"\"\"\nkey = 2\n";
+        let buffer = create_test_file_buffer(content);
+        let mut highlighter = SyntaxHighlighter::new();
+        highlighter.detect_file_type(b"config.toml\0");
+
+        // The line inside the string is not a key/value pair
+        assert_eq!(
+            highlighter.highlight_char(&buffer, 11),
+            HighlightColor::String
+        );
+        assert_eq!(
+            highlighter.highlight_char(&buffer, 17),
+            HighlightColor::String
+        );
+
+        // After the closing quotes keys are highlighted again
+        let key_pos = content.len() - 8;
+        assert_eq!(
+            highlighter.highlight_char(&buffer, key_pos),
+            HighlightColor::Number
+        );
+    }
 }
@@ -0,0 +1,359 @@
+use crate::syscall::{MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE, mmap, munmap};
+
+const PAGE_SIZE: usize = 4096;
+const WORD: usize = core::mem::size_of::<usize>();
+
+// Record kinds stored in the log
+const BOUNDARY: usize = 0;
+const INSERT: usize = 1;
+const DELETE: usize = 2;
+
+// Every record is laid out as three header words (kind, pos, len), the
+// payload bytes and a trailing word holding the total record size, so the
+// log can be walked in both directions. Boundary records have no payload and
+// keep the cursor row and column in the pos and len words.
+const HEADER_SIZE: usize = 3 * WORD;
+
+// A single step read back from the log
+pub(in crate::editor) enum UndoRecord {
+    Boundary {
+        row: usize,
+        col: usize,
+    },
+    Insert {
+        pos: usize,
+        data: *const u8,
+        len: usize,
+    },
+    Delete {
+        pos: usize,
+        data: *const u8,
+        len: usize,
+    },
+}
+
+// UndoLog keeps a linear history of buffer edits split into undo groups
+pub(in crate::editor) struct UndoLog {
+    buffer: *mut u8,   // Raw mmap-backed record storage
+    cur: usize,        // Offset of the current position in the history
+    end: usize,        // End of recorded history (redo records live in cur..end)
+    capacity: usize,   // Size of the mapping
+    saved: usize,      // History offset matching the file on disk, usize::MAX if lost
+    open: bool,        // Whether new edits extend the group at the top of the log
+    cursor_row: usize, // Cursor row to restore when the next group is undone
+    cursor_col: usize, // Cursor column to restore when the next group is undone
+}
+
+impl UndoLog {
+    pub(in crate::editor) const fn new() -> Self {
+        Self {
+            buffer: core::ptr::null_mut(),
+            cur: 0,
+            end: 0,
+            capacity: 0,
+            saved: 0,
+            open: false,
+            cursor_row: 0,
+            cursor_col: 0,
+        }
+    }
+
+    // Close the current group; the next edit starts a new undo step
+    pub(in crate::editor) fn boundary(&mut self, row: usize, col: usize) {
+        self.open = false;
+        self.cursor_row = row;
+        self.cursor_col = col;
+    }
+
+    // Remember the current position as the state stored on disk
+    pub(in crate::editor) fn mark_saved(&mut self) {
+        self.saved = self.cur;
+    }
+
+    // The text differs from the file on disk at every point of the history
+    pub(in crate::editor) fn forget_saved(&mut self) {
+        self.saved = usize::MAX;
+    }
+
+    // Whether the history is positioned at the last saved state
+    pub(in crate::editor) fn is_at_saved(&self) -> bool {
+        self.saved == self.cur
+    }
+
+    pub(in crate::editor) fn record_insert(&mut self, pos: usize, bytes: &[u8]) {
+        if self.extend_top(INSERT, pos, bytes) {
+            return;
+        }
+        self.push(INSERT, pos, bytes);
+    }
+
+    pub(in crate::editor) fn record_delete(&mut self, pos: usize, bytes: &[u8]) {
+        if self.extend_top(DELETE, pos, bytes) {
+            return;
+        }
+        self.push(DELETE, pos, bytes);
+    }
+
+    // Step back over one record, returning it so the caller can revert it
+    pub(in crate::editor) fn step_back(&mut self) -> Option<UndoRecord> {
+        self.open = false;
+        if self.cur == 0 {
+            return None;
+        }
+
+        let size = self.read_word(self.cur - WORD);
+        self.cur -= size;
+        Some(self.record_at(self.cur))
+    }
+
+    // Step forward over one record. A boundary is only crossed when
+    // `enter_group` is set, so redo stops at the end of a group.
+    pub(in crate::editor) fn step_forward(&mut self, enter_group: bool) -> Option<UndoRecord> {
+        self.open = false;
+        if self.cur == self.end {
+            return None;
+        }
+
+        let is_boundary = self.read_word(self.cur) == BOUNDARY;
+        if is_boundary != enter_group {
+            return None;
+        }
+
+        let record = self.record_at(self.cur);
+        let len = if is_boundary {
+            0
+        } else {
+            self.read_word(self.cur + 2 * WORD)
+        };
+        self.cur += HEADER_SIZE + len + WORD;
+        Some(record)
+    }
+
+    fn record_at(&self, offset: usize) -> UndoRecord {
+        let kind = self.read_word(offset);
+        let pos = self.read_word(offset + WORD);
+        let len = self.read_word(offset + 2 * WORD);
+
+        let data = unsafe { self.buffer.add(offset + HEADER_SIZE) };
+
+        match kind {
+            BOUNDARY => UndoRecord::Boundary { row: pos, col: len },
+            INSERT => UndoRecord::Insert { pos, data, len },
+            _ => UndoRecord::Delete { pos, data, len },
+        }
+    }
+
+    // Try to merge an edit into the record at the top of an open group
+    fn extend_top(&mut self, kind: usize, pos: usize, bytes: &[u8]) -> bool {
+        if !self.open || self.cur != self.end || self.cur == 0 {
+            return false;
+        }
+
+        let size = self.read_word(self.cur - WORD);
+        let start = self.cur - size;
+        if self.read_word(start) != kind {
+            return false;
+        }
+
+        let top_pos = self.read_word(start + WORD);
+        let top_len = self.read_word(start + 2 * WORD);
+        let adjacent = match kind {
+            // Typing forward appends right after the previous insert
+            INSERT => top_pos + top_len == pos,
+            // Repeated forward deletes happen at the same position
+            _ => top_pos == pos,
+        };
+        if !adjacent || !self.reserve(bytes.len()) {
+            return false;
+        }
+
+        let payload_end = self.cur - WORD;
+        self.write_bytes(payload_end, bytes);
+        self.write_word(start + 2 * WORD, top_len + bytes.len());
+        self.cur = payload_end + bytes.len() + WORD;
+        self.write_word(self.cur - WORD, size + bytes.len());
+        self.end = self.cur;
+        true
+    }
+
+    fn push(&mut self, kind: usize, pos: usize, bytes: &[u8]) {
+        if !self.open {
+            // A new edit discards the redo history
+            self.end = self.cur;
+            if self.saved > self.cur {
+                self.saved = usize::MAX;
+            }
+            if !self.push_record(BOUNDARY, self.cursor_row, self.cursor_col, &[]) {
+                return;
+            }
+            self.open = true;
+        }
+
+        self.push_record(kind, pos, bytes.len(), bytes);
+    }
+
+    fn push_record(&mut self, kind: usize, pos: usize, len: usize, bytes: &[u8]) -> bool {
+        let size = HEADER_SIZE + bytes.len() + WORD;
+        if !self.reserve(size) {
+            return false;
+        }
+
+        let start = self.cur;
+        self.write_word(start, kind);
+        self.write_word(start + WORD, pos);
+        self.write_word(start + 2 * WORD, len);
+        self.write_bytes(start + HEADER_SIZE, bytes);
+        self.write_word(start + size - WORD, size);
+
+        self.cur += size;
+        self.end = self.cur;
+        true
+    }
+
+    // Make sure `additional` bytes fit after the current position
+    fn reserve(&mut self, additional: usize) -> bool {
+        let required = self.cur + additional;
+        if required <= self.capacity {
+            return true;
+        }
+
+        let mut new_capacity = if self.capacity == 0 {
+            PAGE_SIZE
+        } else {
+            self.capacity * 2
+        };
+        while new_capacity < required {
+            new_capacity *= 2;
+        }
+
+        let prot = PROT_READ | PROT_WRITE;
+        let flags = MAP_PRIVATE | MAP_ANONYMOUS;
+        let Ok(new_buffer) = mmap(0, new_capacity, prot, flags, usize::MAX, 0) else {
+            return false;
+        };
+        let new_buffer = new_buffer as *mut u8;
+
+        if !self.buffer.is_null() {
+            unsafe { core::ptr::copy_nonoverlapping(self.buffer, new_buffer, self.end) };
+            let _ = munmap(self.buffer as usize, self.capacity);
+        }
+
+        self.buffer = new_buffer;
+        self.capacity = new_capacity;
+        true
+    }
+
+    fn read_word(&self, offset: usize) -> usize {
+        unsafe { core::ptr::read_unaligned(self.buffer.add(offset).cast::<usize>()) }
+    }
+
+    fn write_word(&mut self, offset: usize, value: usize) {
+        unsafe { core::ptr::write_unaligned(self.buffer.add(offset).cast::<usize>(), value) };
+    }
+
+    fn write_bytes(&mut self, offset: usize, bytes: &[u8]) {
+        unsafe {
+            core::ptr::copy_nonoverlapping(bytes.as_ptr(), self.buffer.add(offset), bytes.len());
+        }
+    }
+}
+
+impl Drop for UndoLog {
+    fn drop(&mut self) {
+        if !self.buffer.is_null() && self.capacity > 0 {
+            let _ = munmap(self.buffer as usize, self.capacity);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_undo_log_groups() {
+        let mut log = UndoLog::new();
+        assert!(log.step_back().is_none(), "Empty log has nothing to undo");
+
+        // Two adjacent inserts in one group merge into a single record
+        log.boundary(0, 0);
+        log.record_insert(0, b"a");
+        log.record_insert(1, b"b");
+
+        // A new group starts after the boundary
+        log.boundary(0, 2);
+        log.record_delete(0, b"a");
+
+        assert!(matches!(
+            log.step_back(),
+            Some(UndoRecord::Delete { pos: 0, len: 1, .. })
+        ));
+        assert!(matches!(
+            log.step_back(),
+            Some(UndoRecord::Boundary { row: 0, col: 2 })
+        ));
+        assert!(matches!(
+            log.step_back(),
+            Some(UndoRecord::Insert { pos: 0, len: 2, .. })
+        ));
+        assert!(matches!(
+            log.step_back(),
+            Some(UndoRecord::Boundary { row: 0, col: 0 })
+        ));
+        assert!(log.step_back().is_none(), "Log should be fully unwound");
+
+        // Redo walks the first group and stops at the next boundary
+        assert!(matches!(
+            log.step_forward(true),
+            Some(UndoRecord::Boundary { .. })
+        ));
+        assert!(matches!(
+            log.step_forward(false),
+            Some(UndoRecord::Insert { pos: 0, len: 2, .. })
+        ));
+        assert!(
+            log.step_forward(false).is_none(),
+            "Redo stops at a group end"
+        );
+    }
+
+    #[test]
+    fn test_undo_log_new_edit_drops_redo() {
+        let mut log = UndoLog::new();
+        log.boundary(0, 0);
+        log.record_insert(0, b"x");
+        log.mark_saved();
+        assert!(log.is_at_saved(), "Log should be at the saved state");
+
+        while log.step_back().is_some() {}
+        assert!(!log.is_at_saved(), "Undo should move away from saved state");
+
+        // Editing after undo forgets the redo records and the save point
+        log.boundary(0, 0);
+        log.record_insert(0, b"y");
+        assert!(
+            log.step_forward(true).is_none(),
+            "Redo history should be gone"
+        );
+        assert!(!log.is_at_saved(), "Saved state should be unreachable");
+    }
+
+    #[test]
+    fn test_undo_log_grows() {
+        let mut log = UndoLog::new();
+        let big = [b'z'; 3 * PAGE_SIZE];
+        log.boundary(0, 0);
+        log.record_delete(5, &big);
+
+        let Some(UndoRecord::Delete { pos, data, len }) = log.step_back() else {
+            panic!("Expected a delete record");
+        };
+        assert_eq!(pos, 5);
+        assert_eq!(len, big.len());
+        let payload = unsafe { core::slice::from_raw_parts(data, len) };
+        assert!(
+            payload.iter().all(|&b| b == b'z'),
+            "Payload should survive growth"
+        );
+    }
+}
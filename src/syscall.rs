@@ -11,6 +11,18 @@ pub const IOCTL: usize = 16;
 pub const MMAP: usize = 9;
 pub const MUNMAP: usize = 11;
 pub const LSEEK: usize = 8;
+pub const STAT: usize = 4;
+pub const FSTAT: usize = 5;
+pub const LSTAT: usize = 6;
+pub const POLL: usize = 7;
+pub const FSYNC: usize = 74;
+pub const RENAME: usize = 82;
+pub const UNLINK: usize = 87;
+pub const READLINK: usize = 89;
+pub const FCHMOD: usize = 91;
+pub const FCHOWN: usize = 93;
+pub const GETDENTS64: usize = 217;
+pub const COPY_FILE_RANGE: usize = 326;
 
 pub const SEEK_SET: usize = 0;
 pub const SEEK_CUR: usize = 1;
@@ -22,13 +34,41 @@ pub const STDOUT: usize = 1;
 pub const O_RDONLY: usize = 0;
 pub const O_WRONLY: usize = 1;
 pub const O_CREAT: usize = 64;
+pub const O_EXCL: usize = 128;
 pub const O_TRUNC: usize = 512;
+pub const O_DIRECTORY: usize = 0o200_000;
+pub const O_NOFOLLOW: usize = 0o400_000;
 
 pub const PROT_READ: usize = 1;
 pub const PROT_WRITE: usize = 2;
 pub const MAP_PRIVATE: usize = 2;
 pub const MAP_ANONYMOUS: usize = 0x20;
 
+pub const ENOENT: usize = 2;
+pub const EIO: usize = 5;
+pub const EEXIST: usize = 17;
+pub const EXDEV: usize = 18;
+pub const EINVAL: usize = 22;
+pub const EROFS: usize = 30;
+pub const ENAMETOOLONG: usize = 36;
+pub const ENOSYS: usize = 38;
+pub const ELOOP: usize = 40;
+pub const EOPNOTSUPP: usize = 95;
+
+pub const POLLIN: i16 = 1;
+
+pub const S_IFMT: u32 = 0o170_000;
+pub const S_IFLNK: u32 = 0o120_000;
+pub const S_IFDIR: u32 = 0o040_000;
+pub const S_IFCHR: u32 = 0o020_000;
+pub const S_IFBLK: u32 = 0o060_000;
+pub const S_IFIFO: u32 = 0o010_000;
+pub const S_IFSOCK: u32 = 0o140_000;
+
+pub const DT_UNKNOWN: u8 = 0;
+pub const DT_DIR: u8 = 4;
+pub const DT_LNK: u8 = 10;
+
 const MAX_ERRNO: usize = 4095;
 
 pub type SysResult = Result<usize, usize>;
@@ -126,7 +166,12 @@ pub fn putchar(c: u8) -> SysResult {
 
 // Open file function
 pub fn open(path: &[u8], flags: usize) -> SysResult {
-    let result = unsafe { syscall!(OPEN, path.as_ptr(), flags, 0o666) };
+    open_mode(path, flags, 0o666)
+}
+
+// Open a file, creating it with the given mode before the umask
+pub fn open_mode(path: &[u8], flags: usize, mode: u32) -> SysResult {
+    let result = unsafe { syscall!(OPEN, path.as_ptr(), flags, mode) };
     syscall_result(result)
 }
 
@@ -160,3 +205,134 @@ pub fn lseek(fd: usize, offset: usize, whence: usize) -> SysResult {
     let result = unsafe { syscall!(LSEEK, fd, offset, whence) };
     syscall_result(result)
 }
+
+// fsync function
+pub fn fsync(fd: usize) -> SysResult {
+    let result = unsafe { syscall!(FSYNC, fd) };
+    syscall_result(result)
+}
+
+// Rename file function
+pub fn rename(from: &[u8], to: &[u8]) -> SysResult {
+    let result = unsafe { syscall!(RENAME, from.as_ptr(), to.as_ptr()) };
+    syscall_result(result)
+}
+
+// Remove file function
+pub fn unlink(path: &[u8]) -> SysResult {
+    let result = unsafe { syscall!(UNLINK, path.as_ptr()) };
+    syscall_result(result)
+}
+
+// File status as filled in by the kernel on x86_64
+#[repr(C)]
+#[derive(Clone)]
+pub struct Stat {
+    pub dev: u64,
+    pub ino: u64,
+    pub nlink: u64,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pad: u32,
+    pub rdev: u64,
+    pub size: i64,
+    pub blksize: i64,
+    pub blocks: i64,
+    pub atime: i64,
+    pub atime_nsec: i64,
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+    pub ctime: i64,
+    pub ctime_nsec: i64,
+    reserved: [i64; 3],
+}
+
+impl Stat {
+    pub fn new() -> Self {
+        Self {
+            dev: 0,
+            ino: 0,
+            nlink: 0,
+            mode: 0,
+            uid: 0,
+            gid: 0,
+            pad: 0,
+            rdev: 0,
+            size: 0,
+            blksize: 0,
+            blocks: 0,
+            atime: 0,
+            atime_nsec: 0,
+            mtime: 0,
+            mtime_nsec: 0,
+            ctime: 0,
+            ctime_nsec: 0,
+            reserved: [0; 3],
+        }
+    }
+}
+
+// File status function
+pub fn fstat(fd: usize, stat: &mut Stat) -> SysResult {
+    let result = unsafe { syscall!(FSTAT, fd, core::ptr::from_mut(stat)) };
+    syscall_result(result)
+}
+
+// File status function, following symlinks
+pub fn stat(path: &[u8], stat: &mut Stat) -> SysResult {
+    let result = unsafe { syscall!(STAT, path.as_ptr(), core::ptr::from_mut(stat)) };
+    syscall_result(result)
+}
+
+// Link status function, not following a final symlink
+pub fn lstat(path: &[u8], stat: &mut Stat) -> SysResult {
+    let result = unsafe { syscall!(LSTAT, path.as_ptr(), core::ptr::from_mut(stat)) };
+    syscall_result(result)
+}
+
+// Read symlink target function; the target is not NUL-terminated
+pub fn readlink(path: &[u8], buf: &mut [u8]) -> SysResult {
+    let result = unsafe { syscall!(READLINK, path.as_ptr(), buf.as_mut_ptr(), buf.len()) };
+    syscall_result(result)
+}
+
+// Change file mode function
+pub fn fchmod(fd: usize, mode: u32) -> SysResult {
+    let result = unsafe { syscall!(FCHMOD, fd, mode) };
+    syscall_result(result)
+}
+
+// Change file owner function
+pub fn fchown(fd: usize, uid: u32, gid: u32) -> SysResult {
+    let result = unsafe { syscall!(FCHOWN, fd, uid, gid) };
+    syscall_result(result)
+}
+
+#[repr(C)]
+pub struct PollFd {
+    pub fd: i32,
+    pub events: i16,
+    pub revents: i16,
+}
+
+// Wait for events on file descriptors; returns the number of ready ones, 0 on timeout
+pub fn poll(fds: &mut [PollFd], timeout_ms: usize) -> SysResult {
+    let result = unsafe { syscall!(POLL, fds.as_mut_ptr(), fds.len(), timeout_ms) };
+    syscall_result(result)
+}
+
+// Read directory entries into buf; returns the bytes filled, 0 at the end.
+// Each record is d_ino: u64, d_off: i64, d_reclen: u16, d_type: u8 and the
+// NUL-terminated name.
+pub fn getdents64(fd: usize, buf: &mut [u8]) -> SysResult {
+    let result = unsafe { syscall!(GETDENTS64, fd, buf.as_mut_ptr(), buf.len()) };
+    syscall_result(result)
+}
+
+// Copy bytes between two files inside the kernel, from and to their current
+// offsets; returns 0 at the end of the input
+pub fn copy_file_range(fd_in: usize, fd_out: usize, len: usize) -> SysResult {
+    let result = unsafe { syscall!(COPY_FILE_RANGE, fd_in, 0, fd_out, 0, len, 0) };
+    syscall_result(result)
+}